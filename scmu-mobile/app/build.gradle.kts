@@ -82,6 +82,7 @@ dependencies {
     implementation("io.ktor:ktor-serialization-kotlinx-json:$ktorVersion")
 
     implementation("com.juul.kable:core:0.17.1")
+    implementation("org.bouncycastle:bcprov-jdk15to18:1.71")
 
     implementation("com.ensarsarajcic.kotlinx:serialization-msgpack:0.5.1}")
     implementation("com.ensarsarajcic.kotlinx:serialization-msgpack-unsigned-support:0.5.1")
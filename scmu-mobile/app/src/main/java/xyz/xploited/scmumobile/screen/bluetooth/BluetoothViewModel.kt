@@ -24,6 +24,7 @@ import kotlinx.coroutines.flow.first
 import kotlinx.coroutines.flow.map
 import kotlinx.coroutines.flow.onEach
 import kotlinx.coroutines.launch
+import kotlinx.serialization.SerialName
 import kotlinx.serialization.decodeFromByteArray
 import kotlinx.serialization.decodeFromString
 import kotlinx.serialization.json.Json
@@ -33,6 +34,8 @@ import xyz.xploited.scmumobile.MobileApplication
 data class WifiNetwork(
     val ssid: String,
     val security: Int,
+    @SerialName("security_type")
+    val securityType: String? = null,
     val strength: Int
 )
 
@@ -91,19 +94,27 @@ class BluetoothViewModel(application: Application) : AndroidViewModel(applicatio
         }
     }
 
-    fun getWifiNetworks(): LiveData<List<WifiNetwork>> {
+    fun getWifiNetworks(proofOfPossession: String): LiveData<List<WifiNetwork>> {
         val liveData = MutableLiveData<List<WifiNetwork>>()
         viewModelScope.launch {
             _peripheral?.let {
                 it.state.first { state -> state == State.Connected }
                 Log.e("hello", "connected")
 
+                // the device sends nothing until the session is verified
+                val session = try {
+                    ProvisioningSession.start(it, proofOfPossession)
+                } catch (ex: Throwable) {
+                    ex.printStackTrace()
+                    return@launch
+                }
+
                 it.observe(characteristicOf(
                     service = "ddbc279f-61eb-484a-bbc2-f65f2d4325be",
                     characteristic = "a6bb77a3-e0d5-4841-b424-55a7ddc9f1cb"
                 )).map { bytes ->
                     Log.e("hello", "received data")
-                    MsgPack.decodeFromByteArray<List<WifiNetwork>>(bytes)
+                    MsgPack.decodeFromByteArray<List<WifiNetwork>>(session.decrypt(bytes))
                 }.collect { networks ->
                     liveData.postValue(networks)
                 }
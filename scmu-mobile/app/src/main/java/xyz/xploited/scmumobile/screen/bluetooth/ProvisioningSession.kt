@@ -0,0 +1,119 @@
+package xyz.xploited.scmumobile.screen.bluetooth
+
+import com.ensarsarajcic.kotlinx.serialization.msgpack.MsgPack
+import com.juul.kable.Peripheral
+import com.juul.kable.WriteType
+import com.juul.kable.characteristicOf
+import kotlinx.serialization.SerialName
+import kotlinx.serialization.decodeFromByteArray
+import kotlinx.serialization.encodeToByteArray
+import org.bouncycastle.crypto.agreement.X25519Agreement
+import org.bouncycastle.crypto.digests.SHA256Digest
+import org.bouncycastle.crypto.generators.HKDFBytesGenerator
+import org.bouncycastle.crypto.params.HKDFParameters
+import org.bouncycastle.crypto.params.X25519PrivateKeyParameters
+import org.bouncycastle.crypto.params.X25519PublicKeyParameters
+import java.security.MessageDigest
+import java.security.SecureRandom
+import javax.crypto.Cipher
+import javax.crypto.spec.GCMParameterSpec
+import javax.crypto.spec.SecretKeySpec
+
+private val sessionCharacteristic = characteristicOf(
+    service = "ddbc279f-61eb-484a-bbc2-f65f2d4325be",
+    characteristic = "7d3f9b2e-1c4a-4e86-9f05-b8a2c6e4d173"
+)
+
+private val keyInfo = "scmu-provisioning".toByteArray()
+private const val NONCE_LEN = 12
+private const val TAG_BITS = 128
+
+@kotlinx.serialization.Serializable
+private data class SessionRequest(
+    @SerialName("client_key")
+    val clientKey: String? = null,
+    @SerialName("client_proof")
+    val clientProof: String? = null
+)
+
+@kotlinx.serialization.Serializable
+private data class SessionInfo(
+    @SerialName("device_key")
+    val deviceKey: String,
+    @SerialName("device_proof")
+    val deviceProof: String? = null,
+    val verified: Boolean
+)
+
+private fun ByteArray.toHex(): String = joinToString("") { "%02x".format(it) }
+
+private fun String.fromHex(): ByteArray = chunked(2).map { it.toInt(16).toByte() }.toByteArray()
+
+// every value of the device is encrypted with AES-GCM, with the nonce in front of the ciphertext
+class ProvisioningSession private constructor(private val key: SecretKeySpec) {
+
+    private val random = SecureRandom()
+
+    fun encrypt(data: ByteArray): ByteArray {
+        val nonce = ByteArray(NONCE_LEN).also { random.nextBytes(it) }
+        val cipher = Cipher.getInstance("AES/GCM/NoPadding")
+        cipher.init(Cipher.ENCRYPT_MODE, key, GCMParameterSpec(TAG_BITS, nonce))
+        return nonce + cipher.doFinal(data)
+    }
+
+    fun decrypt(data: ByteArray): ByteArray {
+        require(data.size > NONCE_LEN) { "The message is too short" }
+
+        val cipher = Cipher.getInstance("AES/GCM/NoPadding")
+        cipher.init(Cipher.DECRYPT_MODE, key, GCMParameterSpec(TAG_BITS, data, 0, NONCE_LEN))
+        return cipher.doFinal(data, NONCE_LEN, data.size - NONCE_LEN)
+    }
+
+    companion object {
+        // the app proves first that it knows the proof of possession, and only then
+        // reads the proof of the device, which shows that it knows it too
+        suspend fun start(peripheral: Peripheral, proofOfPossession: String): ProvisioningSession {
+            val secret = X25519PrivateKeyParameters(SecureRandom())
+            val clientKey = secret.generatePublicKey().encoded
+
+            peripheral.write(
+                sessionCharacteristic,
+                MsgPack.encodeToByteArray(SessionRequest(clientKey = clientKey.toHex())),
+                WriteType.WithResponse
+            )
+
+            val info = MsgPack.decodeFromByteArray<SessionInfo>(peripheral.read(sessionCharacteristic))
+            val deviceKey = info.deviceKey.fromHex()
+
+            val shared = ByteArray(32)
+            X25519Agreement().apply {
+                init(secret)
+                calculateAgreement(X25519PublicKeyParameters(deviceKey, 0), shared, 0)
+            }
+
+            // keys of a low order give a shared secret anyone can compute
+            check(shared.any { it != 0.toByte() }) { "Invalid device key" }
+
+            val salt = MessageDigest.getInstance("SHA-256").digest(proofOfPossession.toByteArray())
+            val key = ByteArray(32)
+            HKDFBytesGenerator(SHA256Digest()).apply {
+                init(HKDFParameters(shared, salt, keyInfo))
+                generateBytes(key, 0, key.size)
+            }
+
+            val session = ProvisioningSession(SecretKeySpec(key, "AES"))
+
+            peripheral.write(
+                sessionCharacteristic,
+                MsgPack.encodeToByteArray(SessionRequest(clientProof = session.encrypt(deviceKey).toHex())),
+                WriteType.WithResponse
+            )
+
+            val verified = MsgPack.decodeFromByteArray<SessionInfo>(peripheral.read(sessionCharacteristic))
+            val deviceProof = checkNotNull(verified.deviceProof) { "The device did not accept the proof of possession" }
+            check(session.decrypt(deviceProof.fromHex()).contentEquals(clientKey)) { "The device does not know the proof of possession" }
+
+            return session
+        }
+    }
+}
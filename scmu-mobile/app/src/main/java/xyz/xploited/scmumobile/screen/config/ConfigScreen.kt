@@ -1,14 +1,24 @@
 package xyz.xploited.scmumobile.screen.config
 
 import androidx.activity.ComponentActivity
+import androidx.compose.foundation.layout.Column
+import androidx.compose.foundation.layout.padding
 import androidx.compose.foundation.lazy.LazyColumn
 import androidx.compose.foundation.lazy.items
+import androidx.compose.material3.Button
+import androidx.compose.material3.OutlinedTextField
 import androidx.compose.material3.Text
 import androidx.compose.runtime.Composable
 import androidx.compose.runtime.LaunchedEffect
 import androidx.compose.runtime.getValue
 import androidx.compose.runtime.livedata.observeAsState
+import androidx.compose.runtime.mutableStateOf
+import androidx.compose.runtime.remember
+import androidx.compose.runtime.setValue
+import androidx.compose.ui.Modifier
 import androidx.compose.ui.platform.LocalContext
+import androidx.compose.ui.text.input.PasswordVisualTransformation
+import androidx.compose.ui.unit.dp
 import androidx.lifecycle.viewmodel.compose.viewModel
 import androidx.navigation.NavType
 import androidx.navigation.compose.composable
@@ -52,17 +62,44 @@ fun ConfigScreenView(
         }
     } else {
         WithBluetoothPermissions {
-            btViewModel.connect(blDevice)
+            var proofOfPossession by remember { mutableStateOf<String?>(null) }
 
-            val wifiNetworks by btViewModel.getWifiNetworks()
-                .observeAsState(initial = emptyList())
+            if (proofOfPossession == null) {
+                ProofOfPossessionInput(onSubmit = { proofOfPossession = it })
+            } else {
+                val wifiNetworks by remember {
+                    btViewModel.connect(blDevice)
+                    btViewModel.getWifiNetworks(proofOfPossession!!)
+                }.observeAsState(initial = emptyList())
 
-            LazyColumn {
-                items(wifiNetworks) {
-                    Text(text = it.ssid)
+                LazyColumn {
+                    items(wifiNetworks) {
+                        Text(text = it.ssid)
+                    }
                 }
             }
         }
     }
 }
 
+// the code printed on the device, which the app needs to talk to it
+@Composable
+private fun ProofOfPossessionInput(onSubmit: (String) -> Unit) {
+    var value by remember { mutableStateOf("") }
+
+    Column(modifier = Modifier.padding(16.dp)) {
+        OutlinedTextField(
+            value = value,
+            onValueChange = { value = it },
+            label = { Text(text = "Proof of possession") },
+            singleLine = true,
+            visualTransformation = PasswordVisualTransformation()
+        )
+        Button(
+            onClick = { onSubmit(value) },
+            enabled = value.isNotEmpty()
+        ) {
+            Text(text = "Connect")
+        }
+    }
+}
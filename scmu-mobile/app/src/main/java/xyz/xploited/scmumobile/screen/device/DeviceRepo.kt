@@ -50,9 +50,9 @@ data class DeviceIncomingData(
     @SerialName("is_raining")
     val isRaining: Boolean,
     @SerialName("pm_25_level")
-    val pm25Level: Int,
+    val pm25Level: Int? = null,
     @SerialName("pm_10_level")
-    val pm10Level: Int
+    val pm10Level: Int? = null
 ) : Parcelable
 
 @kotlinx.serialization.Serializable
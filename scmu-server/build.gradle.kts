@@ -30,6 +30,7 @@ dependencies {
     implementation("ch.qos.logback:logback-classic:$logback_version")
     implementation("com.squareup.moshi:moshi:1.13.0")
     implementation("com.squareup.moshi:moshi-kotlin:1.13.0")
+    implementation("org.msgpack:msgpack-core:0.9.3")
 
     ksp("com.squareup.moshi:moshi-kotlin-codegen:1.13.0")
 
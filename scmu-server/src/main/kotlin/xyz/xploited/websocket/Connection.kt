@@ -4,7 +4,9 @@ import com.squareup.moshi.Json
 import com.squareup.moshi.JsonClass
 import io.ktor.websocket.WebSocketSession
 import java.util.UUID
+import java.util.concurrent.ConcurrentHashMap
 import java.util.concurrent.ConcurrentLinkedQueue
+import java.util.concurrent.atomic.AtomicInteger
 
 abstract class Connection(
     open val id: UUID,
@@ -20,8 +22,19 @@ data class UbiquitousConnection(
 
 data class UbiquitousConnectionConfiguration(
     val publicKey: String,
-    val mobileConnections: ConcurrentLinkedQueue<MobileConnection> = ConcurrentLinkedQueue()
-)
+    val mobileConnections: ConcurrentLinkedQueue<MobileConnection> = ConcurrentLinkedQueue(),
+    val pendingHistory: ConcurrentHashMap<Int, PendingHistoryRequest> = ConcurrentHashMap(),
+    private val historyRequestIds: AtomicInteger = AtomicInteger(BACKFILL_REQUEST_ID)
+) {
+    // phones pick their own ids, so they are replaced by ids unique for the device
+    fun nextHistoryRequestId(): Int {
+        while (true) {
+            val id = historyRequestIds.incrementAndGet() and Int.MAX_VALUE
+            if (id != BACKFILL_REQUEST_ID && !pendingHistory.containsKey(id))
+                return id
+        }
+    }
+}
 
 @JsonClass(generateAdapter = false)
 data class UbiquitousConfiguration(
@@ -53,6 +66,28 @@ data class WifiPacket(
     val strength: Int
 )
 
+@JsonClass(generateAdapter = false)
+data class AqiThresholdPacket(
+    // us_epa, caqi or eaqi
+    val scale: String,
+    // highest category of the scale before the window is closed, e.g. "moderate"
+    val category: String
+)
+
+@JsonClass(generateAdapter = false)
+data class IndexPacket(
+    val value: Int,
+    val category: String
+)
+
+@JsonClass(generateAdapter = false)
+data class AirQualityPacket(
+    @Json(name = "us_epa")
+    val usEpa: IndexPacket? = null,
+    val caqi: IndexPacket? = null,
+    val eaqi: String? = null
+)
+
 @JsonClass(generateAdapter = false)
 data class ThresholdPacket(
     @Json(name = "rain_threshold")
@@ -61,6 +96,20 @@ data class ThresholdPacket(
     val pm25Threshold: Int,
     @Json(name = "pm_10_threshold")
     val pm10Threshold: Int,
+    @Json(name = "wind_threshold")
+    val windThreshold: Float? = null,
+    @Json(name = "gust_threshold")
+    val gustThreshold: Float? = null,
+    @Json(name = "co2_threshold")
+    val co2Threshold: Int? = null,
+    @Json(name = "co2_recalibration")
+    val co2Recalibration: Int? = null,
+    @Json(name = "compare_particle_matter")
+    val compareParticleMatter: Boolean? = null,
+    @Json(name = "pm_margin")
+    val pmMargin: Int? = null,
+    @Json(name = "aqi_threshold")
+    val aqiThreshold: AqiThresholdPacket? = null,
     val signature: String? = null
 )
 
@@ -73,7 +122,31 @@ data class InfoPacket(
     @Json(name = "is_raining")
     val isRaining: Boolean,
     @Json(name = "pm_25_level")
-    val pm25Level: Int,
+    val pm25Level: Int? = null,
     @Json(name = "pm_10_level")
-    val pm10Level: Int
-)
\ No newline at end of file
+    val pm10Level: Int? = null,
+    @Json(name = "indoor_pm_25_level")
+    val indoorPm25Level: Int? = null,
+    @Json(name = "indoor_pm_10_level")
+    val indoorPm10Level: Int? = null,
+    @Json(name = "air_quality")
+    val airQuality: AirQualityPacket? = null,
+    @Json(name = "wind_speed")
+    val windSpeed: Float? = null,
+    @Json(name = "wind_gust")
+    val windGust: Float? = null,
+    @Json(name = "co2_level")
+    val co2Level: Int? = null
+)
+
+@JsonClass(generateAdapter = false)
+data class HistoryRequestPacket(
+    @Json(name = "request_id")
+    val requestId: Int,
+    val from: Long,
+    val to: Long,
+    val resolution: String,
+    val metric: String? = null,
+    @Json(name = "page_size")
+    val pageSize: Int? = null
+)
@@ -0,0 +1,54 @@
+package xyz.xploited.websocket
+
+import org.msgpack.core.MessagePack
+import org.msgpack.value.Value
+import org.msgpack.value.ValueFactory
+
+// request id of the chunks a device sends unprompted after reconnecting, which go to every phone
+const val BACKFILL_REQUEST_ID = 0
+
+// history request of a phone, forwarded to the device with an id that is unique for the device
+data class PendingHistoryRequest(
+    val mobile: MobileConnection,
+    val requestId: Int
+)
+
+data class HistoryChunk(
+    val requestId: Int,
+    // no other chunk follows for the same request
+    val last: Boolean,
+    private val fields: Map<Value, Value>
+) {
+    fun withRequestId(requestId: Int): ByteArray {
+        val map = fields.toMutableMap()
+        map[ValueFactory.newString("request_id")] = ValueFactory.newInteger(requestId)
+
+        return MessagePack.newDefaultBufferPacker().use {
+            it.packValue(ValueFactory.newMap(map))
+            it.toByteArray()
+        }
+    }
+}
+
+// history chunks are messagepack encoded maps, as sent by the device
+fun parseHistoryChunk(bytes: ByteArray): HistoryChunk? {
+    return try {
+        val value = MessagePack.newDefaultUnpacker(bytes).use { it.unpackValue() }
+        if (!value.isMapValue)
+            return null
+
+        val fields = value.asMapValue().map()
+        val field = { name: String -> fields[ValueFactory.newString(name)] }
+
+        val requestId = field("request_id")?.takeIf { it.isIntegerValue }?.asIntegerValue()?.toInt()
+            ?: return null
+
+        val page = field("page")?.takeIf { it.isIntegerValue }?.asIntegerValue()?.toInt() ?: 0
+        val pages = field("pages")?.takeIf { it.isIntegerValue }?.asIntegerValue()?.toInt() ?: 0
+        val failed = field("error")?.isNilValue == false
+
+        HistoryChunk(requestId, failed || page >= pages - 1, fields)
+    } catch (ex: Exception) {
+        null
+    }
+}
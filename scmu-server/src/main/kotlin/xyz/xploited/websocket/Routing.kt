@@ -2,6 +2,7 @@
 
 package xyz.xploited.websocket
 
+import com.squareup.moshi.JsonDataException
 import com.squareup.moshi.Moshi
 import com.squareup.moshi.adapter
 import com.squareup.moshi.kotlin.reflect.KotlinJsonAdapterFactory
@@ -22,6 +23,14 @@ private val moshi = Moshi.Builder()
     .add(KotlinJsonAdapterFactory())
     .build()
 
+private inline fun <reified T> parseOrNull(text: String): T? {
+    return try {
+        moshi.adapter<T>().fromJson(text)
+    } catch (ex: JsonDataException) {
+        null
+    }
+}
+
 fun Application.configureWebsocket() {
     routing {
         val ubiquitousHandler = ConnectionHandler(
@@ -43,7 +52,12 @@ fun Application.configureWebsocket() {
         val mobileHandler = ConnectionHandler(
             connectionProducer = { id, session -> MobileConnection(id, session) },
             handleFrames = { handleMobileFrame(it, ubiquitousHandler) },
-            onDisconnect = { it.config?.ubiquitousConnection?.config?.mobileConnections?.remove(it) }
+            onDisconnect = { mobile ->
+                mobile.config?.ubiquitousConnection?.config?.let {
+                    it.mobileConnections.remove(mobile)
+                    it.pendingHistory.values.removeIf { pending -> pending.mobile.id == mobile.id }
+                }
+            }
         )
 
         webSocket("/mobile") {
@@ -54,6 +68,37 @@ fun Application.configureWebsocket() {
 
 private suspend fun handleUbiquitousFrame(conn: UbiquitousConnection) {
     for (frame in conn.session.incoming) {
+        // history chunks are messagepack encoded, and only go to the phone that asked for them
+        if (frame is Frame.Binary) {
+            val connConfig = conn.config ?: continue
+            val bytes = frame.readBytes()
+            val chunk = parseHistoryChunk(bytes) ?: continue
+
+            if (chunk.requestId == BACKFILL_REQUEST_ID) {
+                for (mobile in connConfig.mobileConnections) {
+                    if (mobile.session.isActive)
+                        mobile.session.send(Frame.Binary(true, bytes))
+                }
+
+                continue
+            }
+
+            val pending = if (chunk.last)
+                connConfig.pendingHistory.remove(chunk.requestId)
+            else
+                connConfig.pendingHistory[chunk.requestId]
+
+            if (pending == null) {
+                log.warn("Dropping history chunk of unknown request ${chunk.requestId} from ${conn.id}")
+                continue
+            }
+
+            if (pending.mobile.session.isActive)
+                pending.mobile.session.send(Frame.Binary(true, chunk.withRequestId(pending.requestId)))
+
+            continue
+        }
+
         frame as? Frame.Text ?: continue
         val text = frame.readBytes()
             .decodeToString()
@@ -109,6 +154,19 @@ private suspend fun handleMobileFrame(conn: MobileConnection, ubiquitousHandler:
             conn.config = MobileConnectionConfiguration(config.publicKey, ub)
             ub.config!!.mobileConnections.add(conn)
         } else {
+            val historyRequest = parseOrNull<HistoryRequestPacket>(text)
+            if (historyRequest != null) {
+                val ub = connConfig.ubiquitousConnection
+                val ubConfig = ub.config ?: continue
+                val id = ubConfig.nextHistoryRequestId()
+                ubConfig.pendingHistory[id] = PendingHistoryRequest(conn, historyRequest.requestId)
+
+                log.info("Forwarding history request ${historyRequest.requestId} from ${conn.id} as $id")
+                ub.session.send(moshi.adapter<HistoryRequestPacket>().toJson(historyRequest.copy(requestId = id)))
+
+                continue
+            }
+
             val data = moshi.adapter<ThresholdPacket>()
                 .fromJson(text)
 
@@ -0,0 +1,131 @@
+//! local http api, which serves the history stored on the device to the network it is connected to
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration
+};
+
+use serde::Serialize;
+use serde_json::json;
+use url::form_urlencoded;
+
+use crate::{clock::SharedClock, history::{History, Point, Resolution, WindowEvent}, util::Result};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEADER_LINES: usize = 100;
+
+// range returned when the request does not give one
+const DEFAULT_RANGE_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Serialize)]
+struct HistoryResponse {
+    points: Vec<Point>,
+    events: Vec<WindowEvent>
+}
+
+/// answers `GET /history?from=&to=&resolution=&metric=` with the stored readings as json,
+/// `from` and `to` are unix timestamps and default to the last 24 hours
+pub fn serve(address: &str, history: Arc<Mutex<History>>, clock: SharedClock) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .map_err(|err| format!("Unable to listen on {}: {}", address, err))?;
+
+    println!("Serving the history on {}", address);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let history = history.clone();
+            let clock = clock.clone();
+            thread::spawn(move || handle(stream, &history, &clock));
+        }
+    });
+
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, history: &Mutex<History>, clock: &SharedClock) {
+    if stream.set_read_timeout(Some(REQUEST_TIMEOUT)).is_err() {
+        return
+    }
+
+    let target = match read_target(&stream) {
+        Ok(target) => target,
+        Err(err) => return respond(&mut stream, "400 Bad Request", &json!({ "error": err }).to_string())
+    };
+
+    let (path, query) = target.split_once('?')
+        .unwrap_or((&target, ""));
+
+    let (status, body) = match path {
+        "/history" => match query_history(history, query, clock.unix_time()) {
+            Ok(response) => ("200 OK", serde_json::to_string(&response).unwrap()),
+            Err(err) => ("400 Bad Request", json!({ "error": err }).to_string())
+        },
+        _ => ("404 Not Found", json!({ "error": "Not found" }).to_string())
+    };
+
+    respond(&mut stream, status, &body);
+}
+
+// the method and headers are not needed, only GET requests are answered
+fn read_target(stream: &TcpStream) -> Result<String> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)
+        .map_err(|err| err.to_string())?;
+
+    let mut parts = request_line.split_whitespace();
+    if parts.next() != Some("GET") {
+        return Err(String::from("Only GET requests are supported"))
+    }
+
+    let target = parts.next()
+        .ok_or_else(|| String::from("Invalid request"))?
+        .to_string();
+
+    let mut line = String::new();
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if reader.read_line(&mut line).map_err(|err| err.to_string())? == 0 || line == "\r\n" {
+            return Ok(target)
+        }
+    }
+
+    Err(String::from("Too many headers"))
+}
+
+fn query_history(history: &Mutex<History>, query: &str, now: i64) -> Result<HistoryResponse> {
+    let mut from = now - DEFAULT_RANGE_SECS;
+    let mut to = now;
+    let mut resolution = Resolution::Minute;
+    let mut metric = None;
+
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "from" => from = value.parse().map_err(|_| format!("Invalid from {}", value))?,
+            "to" => to = value.parse().map_err(|_| format!("Invalid to {}", value))?,
+            "resolution" => resolution = value.parse()?,
+            "metric" => metric = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    let history = history.lock().unwrap();
+    Ok(HistoryResponse {
+        points: history.query(from, to, resolution, metric.as_deref())?,
+        events: history.window_events(from, to)?
+    })
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: application/json\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes()).unwrap_or_default();
+}
@@ -0,0 +1,287 @@
+use std::{collections::VecDeque, time::Instant};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{clock::SharedClock, gpio::particle_matter::ParticleMatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsEpaCategory {
+    Good,
+    Moderate,
+    UnhealthyForSensitiveGroups,
+    Unhealthy,
+    VeryUnhealthy,
+    Hazardous
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaqiCategory {
+    VeryLow,
+    Low,
+    Medium,
+    High,
+    VeryHigh
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EaqiCategory {
+    Good,
+    Fair,
+    Moderate,
+    Poor,
+    VeryPoor,
+    ExtremelyPoor
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Index<C> {
+    pub value: u32,
+    pub category: C
+}
+
+/// the indexes are only available once there is enough history to compute them
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AirQuality {
+    pub us_epa: Option<Index<UsEpaCategory>>,
+    pub caqi: Option<Index<CaqiCategory>>,
+    pub eaqi: Option<EaqiCategory>
+}
+
+/// maximum air quality category before the window has to be closed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "scale", content = "category", rename_all = "snake_case")]
+pub enum AqiThreshold {
+    UsEpa(UsEpaCategory),
+    Caqi(CaqiCategory),
+    Eaqi(EaqiCategory)
+}
+
+impl AqiThreshold {
+    /// returns None when the index of the threshold's scale is not available yet
+    pub fn is_exceeded(&self, air_quality: &AirQuality) -> Option<bool> {
+        match self {
+            AqiThreshold::UsEpa(max) => air_quality.us_epa.map(|index| index.category > *max),
+            AqiThreshold::Caqi(max) => air_quality.caqi.map(|index| index.category > *max),
+            AqiThreshold::Eaqi(max) => air_quality.eaqi.map(|category| category > *max)
+        }
+    }
+}
+
+// (concentration low, concentration high, index low, index high)
+type Breakpoint = (f32, f32, f32, f32);
+
+// https://www.airnow.gov/publications/air-quality-index/technical-assistance-document-for-reporting-the-daily-aqi/
+const US_EPA_PM_25: [Breakpoint; 6] = [
+    (0.0, 9.0, 0.0, 50.0),
+    (9.1, 35.4, 51.0, 100.0),
+    (35.5, 55.4, 101.0, 150.0),
+    (55.5, 125.4, 151.0, 200.0),
+    (125.5, 225.4, 201.0, 300.0),
+    (225.5, 325.4, 301.0, 500.0)
+];
+
+const US_EPA_PM_10: [Breakpoint; 6] = [
+    (0.0, 54.0, 0.0, 50.0),
+    (55.0, 154.0, 51.0, 100.0),
+    (155.0, 254.0, 101.0, 150.0),
+    (255.0, 354.0, 151.0, 200.0),
+    (355.0, 424.0, 201.0, 300.0),
+    (425.0, 604.0, 301.0, 500.0)
+];
+
+// hourly grid values, see https://www.airqualitynow.eu/about_indices_definition.php
+const CAQI_PM_25: [Breakpoint; 4] = [
+    (0.0, 15.0, 0.0, 25.0),
+    (15.0, 30.0, 25.0, 50.0),
+    (30.0, 55.0, 50.0, 75.0),
+    (55.0, 110.0, 75.0, 100.0)
+];
+
+const CAQI_PM_10: [Breakpoint; 4] = [
+    (0.0, 25.0, 0.0, 25.0),
+    (25.0, 50.0, 25.0, 50.0),
+    (50.0, 90.0, 50.0, 75.0),
+    (90.0, 180.0, 75.0, 100.0)
+];
+
+// upper limit of each band for the 24 hour running means, see https://airindex.eea.europa.eu/
+const EAQI_PM_25: [f32; 5] = [10.0, 20.0, 25.0, 50.0, 75.0];
+const EAQI_PM_10: [f32; 5] = [20.0, 40.0, 50.0, 100.0, 150.0];
+
+const NOWCAST_HOURS: usize = 12;
+const EAQI_HOURS: usize = 24;
+
+fn interpolate(breakpoints: &[Breakpoint], concentration: f32) -> f32 {
+    let (c_lo, c_hi, i_lo, i_hi) = breakpoints.iter()
+        .find(|(_, c_hi, _, _)| concentration <= *c_hi)
+        .copied()
+        // above the last breakpoint the index keeps growing at the same rate
+        .unwrap_or(breakpoints[breakpoints.len() - 1]);
+
+    (i_hi - i_lo) / (c_hi - c_lo) * (concentration - c_lo) + i_lo
+}
+
+/// index of concentrations averaged with the nowcast, in µg/m³
+pub fn us_epa_index(pm_25: f32, pm_10: f32) -> Index<UsEpaCategory> {
+    // concentrations are truncated to the precision of the breakpoints
+    let pm_25_index = interpolate(&US_EPA_PM_25, (pm_25 * 10.0).floor() / 10.0);
+    let pm_10_index = interpolate(&US_EPA_PM_10, pm_10.floor());
+    let value = pm_25_index.max(pm_10_index).round().min(500.0) as u32;
+
+    let category = match value {
+        0..=50 => UsEpaCategory::Good,
+        51..=100 => UsEpaCategory::Moderate,
+        101..=150 => UsEpaCategory::UnhealthyForSensitiveGroups,
+        151..=200 => UsEpaCategory::Unhealthy,
+        201..=300 => UsEpaCategory::VeryUnhealthy,
+        _ => UsEpaCategory::Hazardous
+    };
+
+    Index { value, category }
+}
+
+/// index of hourly concentrations, in µg/m³
+pub fn caqi_index(pm_25: f32, pm_10: f32) -> Index<CaqiCategory> {
+    let value = interpolate(&CAQI_PM_25, pm_25)
+        .max(interpolate(&CAQI_PM_10, pm_10))
+        .round() as u32;
+
+    let category = match value {
+        0..=24 => CaqiCategory::VeryLow,
+        25..=49 => CaqiCategory::Low,
+        50..=74 => CaqiCategory::Medium,
+        75..=100 => CaqiCategory::High,
+        _ => CaqiCategory::VeryHigh
+    };
+
+    Index { value, category }
+}
+
+/// category of 24 hour mean concentrations, in µg/m³
+pub fn eaqi_category(pm_25: f32, pm_10: f32) -> EaqiCategory {
+    let band = |limits: &[f32; 5], concentration: f32| limits.iter()
+        .position(|limit| concentration <= *limit)
+        .unwrap_or(limits.len());
+
+    match band(&EAQI_PM_25, pm_25).max(band(&EAQI_PM_10, pm_10)) {
+        0 => EaqiCategory::Good,
+        1 => EaqiCategory::Fair,
+        2 => EaqiCategory::Moderate,
+        3 => EaqiCategory::Poor,
+        4 => EaqiCategory::VeryPoor,
+        _ => EaqiCategory::ExtremelyPoor
+    }
+}
+
+// https://www3.epa.gov/airnow/aqicalctest/nowcast.htm
+fn nowcast(hourly: &[Option<f32>]) -> Option<f32> {
+    // two of the three most recent hours must be available
+    if hourly.iter().take(3).flatten().count() < 2 {
+        return None
+    }
+
+    let values: Vec<f32> = hourly.iter().flatten().copied().collect();
+    let max = values.iter().copied().fold(f32::MIN, f32::max);
+    let min = values.iter().copied().fold(f32::MAX, f32::min);
+    if max <= 0.0 {
+        return Some(0.0)
+    }
+
+    let weight = (min / max).max(0.5);
+    let (sum, weights) = hourly.iter()
+        .enumerate()
+        .filter_map(|(hour, value)| value.map(|value| (hour, value)))
+        .fold((0.0, 0.0), |(sum, weights), (hour, value)| {
+            let w = weight.powi(hour as i32);
+            (sum + w * value, weights + w)
+        });
+
+    Some(sum / weights)
+}
+
+struct HourlyAverage {
+    hour: u64,
+    pm_25_sum: f32,
+    pm_10_sum: f32,
+    count: u32
+}
+
+pub struct PmHistory {
+    clock: SharedClock,
+    start: Instant,
+    hours: VecDeque<HourlyAverage>
+}
+
+impl PmHistory {
+    pub fn new(clock: SharedClock) -> Self {
+        Self {
+            start: clock.now(),
+            clock,
+            hours: VecDeque::new()
+        }
+    }
+
+    fn current_hour(&self) -> u64 {
+        self.clock.now().duration_since(self.start).as_secs() / 3600
+    }
+
+    pub fn record(&mut self, pm: &ParticleMatter) {
+        let hour = self.current_hour();
+        if self.hours.back().map(|avg| avg.hour) != Some(hour) {
+            self.hours.push_back(HourlyAverage { hour, pm_25_sum: 0.0, pm_10_sum: 0.0, count: 0 });
+        }
+
+        let avg = self.hours.back_mut().unwrap();
+        avg.pm_25_sum += pm.pm_25_level as f32;
+        avg.pm_10_sum += pm.pm_10_level as f32;
+        avg.count += 1;
+
+        while let Some(oldest) = self.hours.front() {
+            if hour - oldest.hour < EAQI_HOURS as u64 {
+                break
+            }
+
+            self.hours.pop_front();
+        }
+    }
+
+    // hourly (pm 2.5, pm 10) averages, starting with the current hour
+    fn hourly(&self, hours: usize) -> Vec<Option<(f32, f32)>> {
+        let current = self.current_hour();
+        let mut hourly = vec![None; hours];
+        for avg in &self.hours {
+            let age = (current - avg.hour) as usize;
+            if age < hours && avg.count > 0 {
+                hourly[age] = Some((avg.pm_25_sum / avg.count as f32, avg.pm_10_sum / avg.count as f32));
+            }
+        }
+
+        hourly
+    }
+
+    pub fn air_quality(&self) -> AirQuality {
+        let hourly = self.hourly(EAQI_HOURS);
+
+        let nowcast_25 = nowcast(&hourly[..NOWCAST_HOURS].iter().map(|h| h.map(|(pm_25, _)| pm_25)).collect::<Vec<_>>());
+        let nowcast_10 = nowcast(&hourly[..NOWCAST_HOURS].iter().map(|h| h.map(|(_, pm_10)| pm_10)).collect::<Vec<_>>());
+        let us_epa = nowcast_25.zip(nowcast_10)
+            .map(|(pm_25, pm_10)| us_epa_index(pm_25, pm_10));
+
+        let caqi = hourly[0].map(|(pm_25, pm_10)| caqi_index(pm_25, pm_10));
+
+        let available: Vec<(f32, f32)> = hourly.iter().flatten().copied().collect();
+        let eaqi = if available.is_empty() {
+            None
+        } else {
+            let count = available.len() as f32;
+            let pm_25 = available.iter().map(|(pm_25, _)| pm_25).sum::<f32>() / count;
+            let pm_10 = available.iter().map(|(_, pm_10)| pm_10).sum::<f32>() / count;
+            Some(eaqi_category(pm_25, pm_10))
+        };
+
+        AirQuality { us_epa, caqi, eaqi }
+    }
+}
@@ -3,52 +3,74 @@ use bluer::{
         AdvertisementHandle,
         Advertisement
     }, 
-    agent::{
-        AgentHandle,
-        Agent
-    }, 
+    agent::AgentHandle, 
     gatt::local::{
         ApplicationHandle,
         Application
     }, 
     Session,
-    Adapter
+    Adapter,
+    Address
 };
 
+pub mod pairing;
+pub mod security;
 pub mod services;
 
-use crate::util::{Result, ToErrString};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::{clock::SharedClock, util::{Result, ToErrString}};
 
+use self::pairing::PasskeyDisplay;
+
+/// a phone that paired with the device and is trusted on the next connections
+#[derive(Debug, Clone, Serialize)]
+pub struct BondedDevice {
+    pub address: String,
+    pub name: Option<String>,
+    pub connected: bool
+}
+
+/// the bluetooth adapter of the device, advertised under the device name, with the agent that
+/// pairs the phones and the gatt application of the provisioning services;
+/// everything is unregistered when it is dropped
 pub struct Bluetooth {
     agent_handle: AgentHandle,
     adapter: Adapter,
+    advertisement: Advertisement,
     adv_handle: AdvertisementHandle,
     app_handle: Option<ApplicationHandle>
 }
 
 impl Bluetooth {
-    pub async fn new(advertisement: Advertisement) -> Result<Self> {
+    /// powers the adapter on, registers the pairing agent and starts advertising,
+    /// `display` shows the passkeys of the pairings
+    pub async fn new(advertisement: Advertisement, display: Arc<dyn PasskeyDisplay>, clock: SharedClock) -> Result<Self> {
         let session = Session::new()
             .await
             .or_err_str()?;
 
-        let agent_handle = Bluetooth::create_agent(&session)
+        let agent_handle = Bluetooth::create_agent(&session, display, clock)
             .await?;
 
         let adapter = Bluetooth::get_adapter(&session)
             .await?;
 
-        let adv_handle = Bluetooth::create_advertisement(&adapter, advertisement)
+        let adv_handle = Bluetooth::create_advertisement(&adapter, advertisement.clone())
             .await?;
 
         return Ok(Bluetooth {
             agent_handle,
             adapter,
+            advertisement,
             adv_handle,
             app_handle: None
         })
     }
 
+    /// serves the gatt services of the application, replacing the ones served before
     pub async fn start_app(&mut self, application: Application) -> Result<()> {
         let app_handle = self.adapter
             .serve_gatt_application(application)
@@ -59,6 +81,40 @@ impl Bluetooth {
         Ok(())
     }
 
+    /// renames the adapter and advertises again with the new name
+    pub async fn set_name(&mut self, name: &str) -> Result<()> {
+        self.adapter.set_alias(name.to_string())
+            .await
+            .or_err_str()?;
+
+        let mut advertisement = self.advertisement.clone();
+        advertisement.local_name = Some(name.to_string());
+
+        // the previous advertisement stops once its handle is replaced
+        self.adv_handle = Bluetooth::create_advertisement(&self.adapter, advertisement.clone())
+            .await?;
+
+        self.advertisement = advertisement;
+        Ok(())
+    }
+
+    /// the phones that paired with the device, whether or not they are connected
+    pub async fn bonded_devices(&self) -> Result<Vec<BondedDevice>> {
+        self.bonds().list().await
+    }
+
+    /// forgets the keys of the device, which has to pair again to write anything
+    pub async fn remove_bonded_device(&self, address: &str) -> Result<()> {
+        self.bonds().remove(address).await
+    }
+
+    /// the bonded devices of the adapter, which the services manage from the phone
+    pub fn bonds(&self) -> Bonds {
+        Bonds {
+            adapter: self.adapter.clone()
+        }
+    }
+
     async fn get_adapter(session: &Session) -> Result<Adapter> {
         let adapter = session.default_adapter()
             .await
@@ -68,16 +124,15 @@ impl Bluetooth {
             .await
             .or_err_str()?;
 
+        adapter.set_pairable(true)
+            .await
+            .or_err_str()?;
+
         Ok(adapter)
     }
 
-    async fn create_agent(session: &Session) -> Result<AgentHandle> {
-        let agent = Agent {
-            request_default: true,
-            ..Default::default()
-        };
-
-        session.register_agent(agent)
+    async fn create_agent(session: &Session, display: Arc<dyn PasskeyDisplay>, clock: SharedClock) -> Result<AgentHandle> {
+        session.register_agent(pairing::agent(display, clock))
             .await
             .or_err_str()
     }
@@ -92,6 +147,45 @@ impl Bluetooth {
     }
 }
 
+/// the phones that paired with the adapter, apart from [`Bluetooth`] so that it can be shared
+#[derive(Clone)]
+pub struct Bonds {
+    adapter: Adapter
+}
+
+impl Bonds {
+    /// the phones that paired with the device, whether or not they are connected
+    pub async fn list(&self) -> Result<Vec<BondedDevice>> {
+        let mut bonded = Vec::new();
+        for address in self.adapter.device_addresses().await.or_err_str()? {
+            let device = self.adapter.device(address)
+                .or_err_str()?;
+
+            if !device.is_paired().await.or_err_str()? {
+                continue
+            }
+
+            bonded.push(BondedDevice {
+                address: address.to_string(),
+                name: device.name().await.or_err_str()?,
+                connected: device.is_connected().await.or_err_str()?
+            });
+        }
+
+        Ok(bonded)
+    }
+
+    /// forgets the keys of the device, which has to pair again to write anything
+    pub async fn remove(&self, address: &str) -> Result<()> {
+        let address: Address = address.parse()
+            .map_err(|_| format!("Invalid address {}", address))?;
+
+        self.adapter.remove_device(address)
+            .await
+            .or_err_str()
+    }
+}
+
 impl Drop for Bluetooth {
     fn drop(&mut self) {
         drop(&self.adv_handle);
@@ -0,0 +1,125 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use bluer::{agent::{Agent, ReqError, ReqResult, RequestConfirmationFn}, Address};
+use tokio::task::JoinHandle;
+
+use crate::{clock::SharedClock, gpio::status_led::StatusLed};
+
+// the passkey is useless once the pairing is over, which BlueZ does not report
+const PASSKEY_TIMEOUT: Duration = Duration::from_secs(30);
+const PASSKEY_DIGITS: usize = 6;
+
+/// shows the passkey of a pairing, which the user types or compares on the phone
+pub trait PasskeyDisplay: Send + Sync {
+    fn show(&self, device: Address, passkey: u32);
+    fn hide(&self);
+
+    /// whether someone next to the device sees the passkey, which a numeric comparison needs
+    fn is_physical(&self) -> bool {
+        true
+    }
+}
+
+/// prints the passkey, for devices without a screen or a led
+pub struct ConsoleDisplay;
+
+impl PasskeyDisplay for ConsoleDisplay {
+    fn show(&self, device: Address, passkey: u32) {
+        println!("Pairing with {}, passkey {:06}", device, passkey);
+    }
+
+    fn hide(&self) {}
+
+    fn is_physical(&self) -> bool {
+        false
+    }
+}
+
+impl PasskeyDisplay for StatusLed {
+    fn show(&self, _device: Address, passkey: u32) {
+        self.show_number(passkey, PASSKEY_DIGITS);
+    }
+
+    fn hide(&self) {
+        self.off();
+    }
+}
+
+// shows one passkey at a time, hiding it after PASSKEY_TIMEOUT
+struct Passkeys {
+    display: Arc<dyn PasskeyDisplay>,
+    clock: SharedClock,
+    // hides the passkey shown last, so that it does not hide a newer one
+    hide_task: Mutex<Option<JoinHandle<()>>>
+}
+
+impl Passkeys {
+    fn show(&self, device: Address, passkey: u32) {
+        let mut hide_task = self.hide_task.lock().unwrap();
+        if let Some(task) = hide_task.take() {
+            task.abort();
+        }
+
+        self.display.show(device, passkey);
+
+        let display = self.display.clone();
+        let sleep = self.clock.sleep_async(PASSKEY_TIMEOUT);
+        *hide_task = Some(tokio::spawn(async move {
+            sleep.await;
+            display.hide();
+        }));
+    }
+}
+
+/// only pairings protected against a man in the middle are accepted: the device shows a passkey,
+/// which is typed on the phone or compared with the one the phone shows, the latter only when
+/// the display can be seen next to the device
+pub fn agent(display: Arc<dyn PasskeyDisplay>, clock: SharedClock) -> Agent {
+    let physical = display.is_physical();
+    let passkeys = Arc::new(Passkeys {
+        display,
+        clock,
+        hide_task: Mutex::new(None)
+    });
+
+    let passkey_display = passkeys.clone();
+    let pin_code_display = passkeys.clone();
+    let confirmation_display = passkeys;
+
+    // the device has no buttons, so the comparison is confirmed on the phone only, which means
+    // nothing when the passkey is printed where the user does not see it; without it the agent
+    // can only display, so the phone asks for the passkey to be typed instead
+    let request_confirmation: Option<RequestConfirmationFn> = match physical {
+        true => Some(Box::new(move |req| {
+            confirmation_display.show(req.device, req.passkey);
+            Box::pin(async { Ok(()) })
+        })),
+        false => None
+    };
+
+    Agent {
+        request_default: true,
+        display_passkey: Some(Box::new(move |req| {
+            passkey_display.show(req.device, req.passkey);
+            Box::pin(async { Ok(()) })
+        })),
+        display_pin_code: Some(Box::new(move |req| {
+            let result: ReqResult<()> = match req.pincode.parse() {
+                Ok(pin_code) => {
+                    pin_code_display.show(req.device, pin_code);
+                    Ok(())
+                },
+                Err(_) => Err(ReqError::Rejected)
+            };
+
+            Box::pin(async move { result })
+        })),
+        request_confirmation,
+        // pairing without a passkey
+        request_authorization: Some(Box::new(|req| {
+            println!("Rejected the unauthenticated pairing of {}", req.device);
+            Box::pin(async { Err(ReqError::Rejected) })
+        })),
+        ..Default::default()
+    }
+}
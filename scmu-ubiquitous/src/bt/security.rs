@@ -0,0 +1,276 @@
+use std::{sync::Mutex, time::{Duration, Instant}};
+
+use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
+use bluer::{gatt::local::{ReqError, ReqResult}, Address};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::watch;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::{clock::SharedClock, util::Result};
+
+const KEY_INFO: &[u8] = b"scmu-provisioning";
+const NONCE_LEN: usize = 12;
+
+// wrong proofs allowed before the device stops accepting new sessions for a while
+const FREE_ATTEMPTS: u32 = 3;
+// doubled on every wrong proof after the free ones
+const LOCKOUT: Duration = Duration::from_secs(30);
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+// a verified session can not be replaced by another device until it has been idle this long
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// what the app reads after starting a session, to check that the device knows the proof of possession too
+#[derive(Serialize)]
+pub struct SessionInfo {
+    /// hex encoded X25519 public key of the device
+    pub device_key: String,
+    /// the public key of the app encrypted with the session key, hex encoded, only given
+    /// once the app has proven itself so that it can not be used to guess the proof of possession
+    pub device_proof: Option<String>,
+    pub verified: bool
+}
+
+struct Session {
+    device: Address,
+    cipher: Aes256Gcm,
+    device_key: [u8; 32],
+    client_key: [u8; 32],
+    verified: bool,
+    // last request of the device, notifications do not count
+    last_used: Instant
+}
+
+// wrong proofs, counted for every device since an attacker can change its address
+#[derive(Default)]
+struct Attempts {
+    failures: u32,
+    locked_until: Option<Instant>
+}
+
+/// session with the app doing the provisioning, which encrypts every value with AES-GCM:
+/// the app sends its X25519 key, both sides derive the key from the shared secret and the
+/// proof of possession, and the app proves it knows the latter by encrypting the key of the device.
+/// the device proves it back only after that, and too many wrong proofs lock new sessions out
+pub struct ProvisioningSecurity {
+    proof_of_possession: String,
+    clock: SharedClock,
+    // only one app provisions the device at a time
+    session: Mutex<Option<Session>>,
+    attempts: Mutex<Attempts>,
+    verified: watch::Sender<u64>,
+    // kept so that sending never fails for lack of receivers
+    verified_rx: watch::Receiver<u64>
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| std::str::from_utf8(pair)
+            .ok()
+            .filter(|pair| pair.len() == 2)
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or_else(|| String::from("Invalid hex string")))
+        .collect()
+}
+
+// the nonce is sent in front of the ciphertext
+fn seal(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), data)
+        .map_err(|_| String::from("Unable to encrypt"))?;
+
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn open(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return Err(String::from("The message is too short"))
+    }
+
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| String::from("Unable to decrypt"))
+}
+
+impl ProvisioningSecurity {
+    pub fn new(proof_of_possession: &str, clock: SharedClock) -> Self {
+        let (verified, verified_rx) = watch::channel(0);
+        Self {
+            proof_of_possession: proof_of_possession.to_string(),
+            clock,
+            session: Mutex::new(None),
+            attempts: Mutex::new(Attempts::default()),
+            verified,
+            verified_rx
+        }
+    }
+
+    /// changes every time a session is verified, so that the values held back until then can be sent
+    pub fn verified_sessions(&self) -> watch::Receiver<u64> {
+        self.verified_rx.clone()
+    }
+
+    fn check_lockout(&self) -> Result<()> {
+        let attempts = self.attempts.lock().unwrap();
+        match attempts.locked_until {
+            Some(until) if self.clock.now() < until => Err(String::from("Too many wrong proofs, try again later")),
+            _ => Ok(())
+        }
+    }
+
+    fn record_failure(&self) {
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.failures += 1;
+
+        if attempts.failures >= FREE_ATTEMPTS {
+            // past the maximum after 7 doublings
+            let doublings = (attempts.failures - FREE_ATTEMPTS).min(7);
+            let lockout = (LOCKOUT * 2u32.pow(doublings)).min(MAX_LOCKOUT);
+            attempts.locked_until = Some(self.clock.now() + lockout);
+        }
+    }
+
+    /// starts a session for the device with the hex encoded public key of the app,
+    /// replacing the session that was there before unless another device verified it and still uses it
+    pub fn start(&self, device: Address, client_key: &str) -> Result<()> {
+        if self.proof_of_possession.is_empty() {
+            return Err(String::from("No proof of possession has been configured"))
+        }
+
+        self.check_lockout()?;
+
+        {
+            let session = self.session.lock().unwrap();
+            if let Some(session) = session.as_ref() {
+                let idle = self.clock.now().saturating_duration_since(session.last_used);
+                if session.verified && session.device != device && idle < SESSION_IDLE_TIMEOUT {
+                    return Err(String::from("Another device is provisioning this one"))
+                }
+            }
+        }
+
+        let client_key: [u8; 32] = from_hex(client_key)?
+            .try_into()
+            .map_err(|_| String::from("Invalid public key"))?;
+
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let device_key = PublicKey::from(&secret).to_bytes();
+        let shared = secret.diffie_hellman(&PublicKey::from(client_key));
+
+        // keys of a low order give a shared secret anyone can compute
+        if !shared.was_contributory() {
+            return Err(String::from("Invalid public key"))
+        }
+
+        let salt = Sha256::digest(self.proof_of_possession.as_bytes());
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(&salt[..]), shared.as_bytes())
+            .expand(KEY_INFO, &mut key)
+            .map_err(|_| String::from("Unable to derive the session key"))?;
+
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| String::from("Invalid session key"))?;
+
+        *self.session.lock().unwrap() = Some(Session {
+            device,
+            cipher,
+            device_key,
+            client_key,
+            verified: false,
+            last_used: self.clock.now()
+        });
+
+        Ok(())
+    }
+
+    pub fn info(&self, device: Address) -> Result<SessionInfo> {
+        let session = self.session.lock().unwrap();
+        let session = session.as_ref()
+            .filter(|session| session.device == device)
+            .ok_or_else(|| String::from("No session has been started"))?;
+
+        let device_proof = match session.verified {
+            true => Some(to_hex(&seal(&session.cipher, &session.client_key)?)),
+            false => None
+        };
+
+        Ok(SessionInfo {
+            device_key: to_hex(&session.device_key),
+            device_proof,
+            verified: session.verified
+        })
+    }
+
+    /// the proof is the public key of the device encrypted with the session key, which
+    /// only matches when the app used the same proof of possession; a wrong one ends the session
+    /// and counts towards the lockout
+    pub fn verify(&self, device: Address, client_proof: &str) -> Result<()> {
+        self.check_lockout()?;
+
+        let mut lkd = self.session.lock().unwrap();
+        let session = lkd.as_mut()
+            .filter(|session| session.device == device)
+            .ok_or_else(|| String::from("No session has been started"))?;
+
+        let verified = from_hex(client_proof)
+            .and_then(|proof| open(&session.cipher, &proof))
+            .map(|key| key == session.device_key)
+            .unwrap_or_default();
+
+        if !verified {
+            *lkd = None;
+            self.record_failure();
+            return Err(String::from("Wrong proof of possession"))
+        }
+
+        session.verified = true;
+        *self.attempts.lock().unwrap() = Attempts::default();
+        let count = *self.verified_rx.borrow();
+        self.verified.send(count + 1).unwrap_or_default();
+        Ok(())
+    }
+
+    // only the device with a verified session gets through
+    fn with_session<T>(&self, device: Option<Address>, fun: impl FnOnce(&Session) -> Result<T>) -> ReqResult<T> {
+        let mut session = self.session.lock().unwrap();
+        let session = session.as_mut()
+            .filter(|session| session.verified && (device.is_none() || device == Some(session.device)))
+            .ok_or(ReqError::NotAuthorized)?;
+
+        if device.is_some() {
+            session.last_used = self.clock.now();
+        }
+
+        fun(session).or(Err(ReqError::Failed))
+    }
+
+    pub fn authorize(&self, device: Address) -> ReqResult<()> {
+        self.with_session(Some(device), |_| Ok(()))
+    }
+
+    /// the plain value written by the device, when it has a verified session
+    pub fn decrypt(&self, device: Address, data: &[u8]) -> ReqResult<Vec<u8>> {
+        self.with_session(Some(device), |session| open(&session.cipher, data))
+    }
+
+    /// encrypts a value read by the device, when it has a verified session
+    pub fn encrypt(&self, device: Address, data: &[u8]) -> ReqResult<Vec<u8>> {
+        self.with_session(Some(device), |session| seal(&session.cipher, data))
+    }
+
+    /// notifications do not tell who they go to, so they are encrypted for the current session
+    pub fn encrypt_notification(&self, data: &[u8]) -> ReqResult<Vec<u8>> {
+        self.with_session(None, |session| seal(&session.cipher, data))
+    }
+}
@@ -0,0 +1,77 @@
+use bluer::gatt::local::{
+    Service,
+    Characteristic,
+    CharacteristicRead,
+    ReqResult,
+    ReqError,
+    CharacteristicWrite,
+    CharacteristicWriteMethod
+};
+
+use serde::Deserialize;
+
+use super::encrypted_read;
+use crate::{bt::{security::ProvisioningSecurity, Bonds}, util::{Result, parse_uuid}};
+
+async fn list_bonds(bonds: Bonds) -> ReqResult<Vec<u8>> {
+    let devices = bonds.list()
+        .await
+        .or(Err(ReqError::Failed))?;
+
+    rmp_serde::to_vec(&devices)
+        .or(Err(ReqError::Failed))
+}
+
+async fn remove_bond(bonds: Bonds, data: &[u8]) -> ReqResult<()> {
+    #[derive(Deserialize)]
+    struct RemoveBond {
+        address: String
+    }
+
+    let request: RemoveBond = rmp_serde::from_slice(data)
+        .or(Err(ReqError::Failed))?;
+
+    bonds.remove(&request.address)
+        .await
+        .or(Err(ReqError::Failed))
+}
+
+/// the phones paired with the device, so that a lost one can be forgotten from another
+pub struct BondedDevicesService {}
+
+impl BondedDevicesService {
+    pub fn create_service(bonds: Bonds, security: &'static ProvisioningSecurity) -> Result<Service> {
+        let read_bonds = bonds.clone();
+
+        Ok(Service {
+            uuid: parse_uuid("4b8e2d17-6a3f-4c95-b1e2-7d0f9a3c5e41")?,
+            primary: true,
+            characteristics: vec![
+                Characteristic {
+                    uuid: parse_uuid("9c2e5a7b-3d14-4f68-a0b9-e6c1d8f42a37")?,
+                    read: Some(CharacteristicRead {
+                        read: true,
+                        fun: Box::new(move |req| {
+                            Box::pin(encrypted_read(security, req.device_address, list_bonds(read_bonds.clone())))
+                        }),
+                        ..Default::default()
+                    }),
+                    write: Some(CharacteristicWrite {
+                        write: true,
+                        encrypt_authenticated_write: true,
+                        method: CharacteristicWriteMethod::Fun(Box::new(move |data, req| {
+                            let bonds = bonds.clone();
+                            Box::pin(async move {
+                                let data = security.decrypt(req.device_address, &data)?;
+                                remove_bond(bonds, &data).await
+                            })
+                        })),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }
+            ],
+            ..Default::default()
+        })
+    }
+}
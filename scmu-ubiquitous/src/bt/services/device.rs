@@ -0,0 +1,111 @@
+use bluer::gatt::local::{
+    Service,
+    Characteristic,
+    CharacteristicRead,
+    ReqResult,
+    ReqError,
+    CharacteristicNotify,
+    CharacteristicNotifier,
+    CharacteristicNotifyMethod,
+    CharacteristicWrite,
+    CharacteristicWriteMethod
+};
+
+use super::encrypted_read;
+use crate::{bt::security::ProvisioningSecurity, device::{DeviceSettings, SettingsUpdate, SharedDeviceConfiguration}, util::{Result, parse_uuid}};
+
+fn encode_settings(settings: &DeviceSettings) -> ReqResult<Vec<u8>> {
+    rmp_serde::to_vec(settings)
+        .or(Err(ReqError::Failed))
+}
+
+async fn update_settings(device: SharedDeviceConfiguration, data: &Vec<u8>) -> ReqResult<()> {
+    let update: SettingsUpdate = rmp_serde::from_slice(data.as_slice())
+        .or(Err(ReqError::Failed))?;
+
+    device.update(update)
+        .or(Err(ReqError::Failed))
+}
+
+// includes the thresholds sent by the server, so the phone always shows the ones in use
+async fn notify_settings(mut nt: CharacteristicNotifier, device: SharedDeviceConfiguration, security: &'static ProvisioningSecurity) {
+    let mut settings = device.subscribe();
+    let mut sessions = security.verified_sessions();
+    let mut last = None;
+
+    loop {
+        let value = settings.borrow().clone();
+        if last.as_ref() != Some(&value) {
+            if let Ok(encrypted) = encode_settings(&value).and_then(|bytes| security.encrypt_notification(&bytes)) {
+                if nt.notify(encrypted).await.is_err() {
+                    return
+                }
+
+                last = Some(value);
+            }
+        }
+
+        let changed = tokio::select! {
+            _ = nt.stopped() => false,
+            changed = settings.changed() => changed.is_ok(),
+            // the new session gets the current settings, which it may never have received
+            Ok(_) = sessions.changed() => {
+                last = None;
+                continue
+            }
+        };
+
+        if !changed {
+            return
+        }
+    }
+}
+
+/// the settings of the device, so that it can be commissioned from the phone before it has internet
+pub struct DeviceConfigurationService {}
+
+impl DeviceConfigurationService {
+    pub fn create_service(device: SharedDeviceConfiguration, security: &'static ProvisioningSecurity) -> Result<Service> {
+        let read_device = device.clone();
+        let write_device = device.clone();
+
+        Ok(Service {
+            uuid: parse_uuid("e1a7c3f0-9b24-4d6e-8a51-0f3b7d2c6e98")?,
+            primary: true,
+            characteristics: vec![
+                Characteristic {
+                    uuid: parse_uuid("5f2b8e4a-7c19-4a3d-b6e0-91d4c8a7f205")?,
+                    read: Some(CharacteristicRead {
+                        read: true,
+                        fun: Box::new(move |req| {
+                            let device = read_device.clone();
+                            Box::pin(encrypted_read(security, req.device_address, async move { encode_settings(&device.settings()) }))
+                        }),
+                        ..Default::default()
+                    }),
+                    write: Some(CharacteristicWrite {
+                        write: true,
+                        encrypt_authenticated_write: true,
+                        method: CharacteristicWriteMethod::Fun(Box::new(move |data, req| {
+                            let device = write_device.clone();
+                            Box::pin(async move {
+                                let data = security.decrypt(req.device_address, &data)?;
+                                update_settings(device, &data).await
+                            })
+                        })),
+                        ..Default::default()
+                    }),
+                    notify: Some(CharacteristicNotify {
+                        notify: true,
+                        method: CharacteristicNotifyMethod::Fun(Box::new(move |nt| {
+                            Box::pin(notify_settings(nt, device.clone(), security))
+                        })),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }
+            ],
+            ..Default::default()
+        })
+    }
+}
@@ -1 +1,19 @@
-pub mod wifi;
\ No newline at end of file
+use std::future::Future;
+
+use bluer::{gatt::local::ReqResult, Address};
+
+use super::security::ProvisioningSecurity;
+
+pub mod bonds;
+pub mod device;
+pub mod wifi;
+
+// the value is only read once the device is known to have a verified session
+async fn encrypted_read<F>(security: &'static ProvisioningSecurity, device: Address, read: F) -> ReqResult<Vec<u8>>
+where
+    F: Future<Output = ReqResult<Vec<u8>>>
+{
+    security.authorize(device)?;
+    let value = read.await?;
+    security.encrypt(device, &value)
+}
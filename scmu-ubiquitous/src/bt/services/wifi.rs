@@ -1,4 +1,4 @@
-use std::{vec, time::Duration, sync::Arc};
+use std::{vec, time::Duration, future::Future};
 
 use bluer::gatt::local::{
     Service, 
@@ -7,36 +7,68 @@ use bluer::gatt::local::{
     ReqResult, 
     ReqError,
     CharacteristicNotify, 
+    CharacteristicNotifier,
     CharacteristicNotifyMethod, 
     CharacteristicWrite, 
     CharacteristicWriteMethod
 };
 
-use network_manager::{AccessPointCredentials, Security};
+use bluer::Address;
 use serde::{Serialize, Deserialize};
-use tokio::time;
+use tokio::sync::{broadcast::{Receiver, error::{RecvError, TryRecvError}}, watch};
+use super::encrypted_read;
+use crate::{bt::security::ProvisioningSecurity, clock::SharedClock, util::{Result, parse_uuid}, wifi::{EnterpriseSettings, IpSettings, NetworkProfile, ProvisioningStatus, ScannedNetwork, WifiEvent, WifiManager, WifiSecurity}};
 
-use crate::{util::{Result, parse_uuid}, wifi::WifiManager};
+// time given to a burst of signals to settle before the value is read again
+const SIGNAL_DEBOUNCE: Duration = Duration::from_millis(500);
 
-async fn read_wifi_networks(wm: &'static WifiManager) -> ReqResult<Vec<u8>> {
+async fn read_session(security: &'static ProvisioningSecurity, device: Address) -> ReqResult<Vec<u8>> {
+    let info = security.info(device)
+        .or(Err(ReqError::Failed))?;
+
+    let bytes = rmp_serde::to_vec(&info)
+        .or(Err(ReqError::Failed))?;
+
+    Ok(bytes)
+}
+
+// the only write accepted without a session, in two steps: the key of the app, then its proof
+async fn write_session(security: &'static ProvisioningSecurity, device: Address, data: &Vec<u8>) -> ReqResult<()> {
+    #[derive(Deserialize)]
+    struct SessionRequest {
+        client_key: Option<String>,
+        client_proof: Option<String>
+    }
+
+    let request: SessionRequest = rmp_serde::from_slice(data.as_slice())
+        .or(Err(ReqError::Failed))?;
+
+    match (request.client_key, request.client_proof) {
+        (Some(client_key), _) => security.start(device, &client_key)
+            .or(Err(ReqError::Failed)),
+        (None, Some(client_proof)) => security.verify(device, &client_proof)
+            .or(Err(ReqError::NotAuthorized)),
+        (None, None) => Err(ReqError::Failed)
+    }
+}
+
+fn encode_access_points(access_points: Vec<ScannedNetwork>) -> ReqResult<Vec<u8>> {
     #[derive(Serialize)]
     struct AccessPointInfo {
         ssid: String,
         security: u32,
+        security_type: WifiSecurity,
         strength: u32
     }
 
-    let access_points = wm.get_access_points()
-        .await
-        .or(Err(ReqError::Failed))?;
-
     let access_points_list: Vec<AccessPointInfo> = access_points.into_iter()
-        .filter(|v| v.ssid.as_str().unwrap().len() > 0)
+        .filter(|v| v.access_point.ssid.as_str().unwrap().len() > 0)
         .map(|v| {
             AccessPointInfo {
-                ssid: v.ssid.as_str().unwrap().to_string(),
-                security: v.security.bits(),
-                strength: v.strength
+                ssid: v.access_point.ssid.as_str().unwrap().to_string(),
+                security: v.access_point.security.bits(),
+                security_type: v.security,
+                strength: v.access_point.strength
             }
         })
         .collect();
@@ -47,6 +79,23 @@ async fn read_wifi_networks(wm: &'static WifiManager) -> ReqResult<Vec<u8>> {
     Ok(bytes)
 }
 
+async fn read_wifi_networks(wm: &'static WifiManager) -> ReqResult<Vec<u8>> {
+    let access_points = wm.get_access_points()
+        .await
+        .or(Err(ReqError::Failed))?;
+
+    encode_access_points(access_points)
+}
+
+// same as read_wifi_networks, without requesting a new scan
+async fn list_wifi_networks(wm: &'static WifiManager) -> ReqResult<Vec<u8>> {
+    let access_points = wm.list_access_points()
+        .await
+        .or(Err(ReqError::Failed))?;
+
+    encode_access_points(access_points)
+}
+
 async fn get_connection_status(wm: &'static WifiManager) -> ReqResult<Vec<u8>> {
     #[derive(Serialize)]
     struct IsConnected {
@@ -72,79 +121,271 @@ async fn connect_to_ap(wm: &'static WifiManager, data: &Vec<u8>) -> ReqResult<()
     struct AccessPointDetails {
         ssid: String,
         password: Option<String>,
-        identity: Option<String>    
+        identity: Option<String>,
+        // hidden networks are not scanned, so their security has to be given
+        hidden: Option<bool>,
+        security: Option<WifiSecurity>,
+        eap: Option<EnterpriseSettings>,
+        // addresses assigned automatically when missing
+        ip: Option<IpSettings>
     }
 
     let ap_r: AccessPointDetails = rmp_serde::from_slice(data.as_slice())
         .or(Err(ReqError::Failed))?;
 
-    let ap = wm.get_access_points()
-        .await
-        .or(Err(ReqError::Failed))?
-        .into_iter()
-        .find(|ap| ap.ssid.as_str().unwrap() == ap_r.ssid)
-        .ok_or(ReqError::Failed)?;
-
-    let ap_creds = match ap.security {
-        Security::NONE => AccessPointCredentials::None,
-        Security::WEP => AccessPointCredentials::Wep {
-            passphrase: ap_r.password.ok_or(ReqError::Failed)?
-        },
-        Security::WPA |
-        Security::WPA2 => AccessPointCredentials::Wpa {
-            passphrase: ap_r.password.ok_or(ReqError::Failed)?
-        },
-        Security::ENTERPRISE => AccessPointCredentials::Enterprise {
-            identity: ap_r.identity.ok_or(ReqError::Failed)?, 
-            passphrase: ap_r.password.ok_or(ReqError::Failed)?
-        },
-        _ => Err(ReqError::Failed)?
+    let hidden = ap_r.hidden.unwrap_or_default();
+    let security = match (hidden, ap_r.security) {
+        (_, Some(security)) => security,
+        (true, None) => Err(ReqError::Failed)?,
+        (false, None) => wm.get_access_points()
+            .await
+            .or(Err(ReqError::Failed))?
+            .into_iter()
+            .find(|ap| ap.access_point.ssid.as_str().unwrap() == ap_r.ssid)
+            .ok_or(ReqError::Failed)?
+            .security
     };
 
-    let ap_a = Arc::new(ap);
-    let ap_creds_a = Arc::new(ap_creds);
+    let profile = NetworkProfile {
+        ssid: ap_r.ssid,
+        hidden,
+        security,
+        password: ap_r.password,
+        identity: ap_r.identity,
+        enterprise: ap_r.eap,
+        ip: ap_r.ip.unwrap_or_default()
+    };
+
+    wm.connect_to_ap(profile)
+        .await
+        .or(Err(ReqError::Failed))?;
+
+    Ok(())
+}
 
-    wm.connect_to_ap(&ap_a, &ap_creds_a)
+async fn read_ip_configuration(wm: &'static WifiManager) -> ReqResult<Vec<u8>> {
+    let configuration = wm.ip_configuration()
         .await
         .or(Err(ReqError::Failed))?;
 
+    let bytes = rmp_serde::to_vec(&configuration)
+        .or(Err(ReqError::Failed))?;
+
+    Ok(bytes)
+}
+
+async fn upload_certificate(wm: &'static WifiManager, data: &Vec<u8>) -> ReqResult<()> {
+    // pem encoded certificate or private key, split to fit in a write
+    #[derive(Deserialize)]
+    struct CertificateChunk {
+        name: String,
+        offset: u64,
+        data: String
+    }
+
+    let chunk: CertificateChunk = rmp_serde::from_slice(data.as_slice())
+        .or(Err(ReqError::Failed))?;
+
+    wm.store_certificate(&chunk.name, chunk.offset, &chunk.data)
+        .or(Err(ReqError::Failed))?;
+
+    Ok(())
+}
+
+// waits for an event of the given kinds, returning false once no more events can arrive
+async fn changed(events: &mut Receiver<WifiEvent>, kinds: &[WifiEvent]) -> bool {
+    loop {
+        match events.recv().await {
+            Ok(event) if kinds.contains(&event) => return true,
+            Ok(_) => {},
+            // some events were missed, so anything may have changed
+            Err(RecvError::Lagged(_)) => return true,
+            Err(RecvError::Closed) => return false
+        }
+    }
+}
+
+// notifies the initial value and then every change of it, until the client unsubscribes
+async fn notify_changes<I, F, R>(
+    mut nt: CharacteristicNotifier,
+    wifi: &'static WifiManager,
+    security: &'static ProvisioningSecurity,
+    kinds: &'static [WifiEvent],
+    clock: SharedClock,
+    initial: I,
+    read: F
+) where
+    I: Future<Output = ReqResult<Vec<u8>>>,
+    F: Fn() -> R,
+    R: Future<Output = ReqResult<Vec<u8>>>
+{
+    // subscribed before the first read, so that no change is missed
+    let mut events = wifi.subscribe();
+    let mut sessions = security.verified_sessions();
+    let mut last = None;
+    let mut value = initial.await;
+
+    loop {
+        if let Ok(value) = &value {
+            if last.as_ref() != Some(value) {
+                // nothing is sent until the session is verified
+                if let Ok(encrypted) = security.encrypt_notification(value) {
+                    if nt.notify(encrypted).await.is_err() {
+                        return
+                    }
+
+                    last = Some(value.clone());
+                }
+            }
+        }
+
+        let changed = tokio::select! {
+            _ = nt.stopped() => false,
+            changed = changed(&mut events, kinds) => changed,
+            // the new session gets the current value, which it may never have received
+            Ok(_) = sessions.changed() => {
+                last = None;
+                continue
+            }
+        };
+
+        if !changed {
+            return
+        }
+
+        clock.sleep_async(SIGNAL_DEBOUNCE).await;
+        while !matches!(events.try_recv(), Err(TryRecvError::Empty | TryRecvError::Closed)) {}
+
+        value = read().await;
+    }
+}
+
+fn encode_provisioning_status(status: &watch::Receiver<ProvisioningStatus>) -> ReqResult<Vec<u8>> {
+    let status = status.borrow().clone();
+    rmp_serde::to_vec(&status)
+        .or(Err(ReqError::Failed))
+}
+
+// every step of a connection attempt is notified, without waiting for it to settle
+async fn notify_provisioning_status(mut nt: CharacteristicNotifier, wifi: &'static WifiManager, security: &'static ProvisioningSecurity) {
+    let mut status = wifi.provisioning_status();
+    let mut sessions = security.verified_sessions();
+    let mut last = None;
+
+    loop {
+        if let Ok(value) = encode_provisioning_status(&status) {
+            if last.as_ref() != Some(&value) {
+                // nothing is sent until the session is verified
+                if let Ok(encrypted) = security.encrypt_notification(&value) {
+                    if nt.notify(encrypted).await.is_err() {
+                        return
+                    }
+
+                    last = Some(value);
+                }
+            }
+        }
+
+        let changed = tokio::select! {
+            _ = nt.stopped() => false,
+            changed = status.changed() => changed.is_ok(),
+            // the new session gets the current status, which it may never have received
+            Ok(_) = sessions.changed() => {
+                last = None;
+                continue
+            }
+        };
+
+        if !changed {
+            return
+        }
+    }
+}
+
+async fn read_saved_networks(wm: &'static WifiManager) -> ReqResult<Vec<u8>> {
+    let networks = wm.saved_networks()
+        .await
+        .or(Err(ReqError::Failed))?;
+
+    let bytes = rmp_serde::to_vec(&networks)
+        .or(Err(ReqError::Failed))?;
+
+    Ok(bytes)
+}
+
+async fn update_saved_network(wm: &'static WifiManager, data: &Vec<u8>) -> ReqResult<()> {
+    #[derive(Deserialize)]
+    struct SavedNetworkUpdate {
+        ssid: String,
+        priority: Option<i32>,
+        forget: Option<bool>
+    }
+
+    let update: SavedNetworkUpdate = rmp_serde::from_slice(data.as_slice())
+        .or(Err(ReqError::Failed))?;
+
+    if update.forget.unwrap_or_default() {
+        wm.forget_network(&update.ssid)
+            .await
+            .or(Err(ReqError::Failed))?;
+    } else if let Some(priority) = update.priority {
+        wm.set_network_priority(&update.ssid, priority)
+            .await
+            .or(Err(ReqError::Failed))?;
+    } else {
+        // an update that changes nothing is most likely a mistake of the app
+        return Err(ReqError::Failed)
+    }
+
     Ok(())
 }
 
 pub struct WifiConfigurationService {}
 
 impl WifiConfigurationService {
-    pub fn create_service(wifi: &'static WifiManager) -> Result<Service> {
+    pub fn create_service(wifi: &'static WifiManager, security: &'static ProvisioningSecurity, clock: SharedClock) -> Result<Service> {
+        let status_clock = clock.clone();
+        let saved_clock = clock.clone();
+        let ip_clock = clock.clone();
+
         Ok(Service {
             uuid: parse_uuid("ddbc279f-61eb-484a-bbc2-f65f2d4325be")?,
             primary: true,
             characteristics: vec![
+                Characteristic {
+                    uuid: parse_uuid("7d3f9b2e-1c4a-4e86-9f05-b8a2c6e4d173")?,
+                    read: Some(CharacteristicRead {
+                        read: true,
+                        fun: Box::new(move |req| {
+                            Box::pin(read_session(security, req.device_address))
+                        }),
+                        ..Default::default()
+                    }),
+                    write: Some(CharacteristicWrite {
+                        write: true,
+                        // writes need a bonded phone and an encrypted link, which starts the pairing
+                        encrypt_authenticated_write: true,
+                        method: CharacteristicWriteMethod::Fun(Box::new(move |data, req| {
+                            Box::pin(async move { write_session(security, req.device_address, &data).await })
+                        })),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
                 Characteristic {
                     uuid: parse_uuid("a6bb77a3-e0d5-4841-b424-55a7ddc9f1cb")?,
                     read: Some(CharacteristicRead {
                         read: true,
-                        fun: Box::new(move |_| {
-                            Box::pin(read_wifi_networks(wifi))
+                        fun: Box::new(move |req| {
+                            Box::pin(encrypted_read(security, req.device_address, read_wifi_networks(wifi)))
                         }),
                         ..Default::default()
                     }),
                     notify: Some(CharacteristicNotify {
                         notify: true,
-                        method: CharacteristicNotifyMethod::Fun(Box::new(move |mut nt| {
-                            Box::pin(async move { 
-                                while !nt.is_stopped() {
-                                    let networks = read_wifi_networks(wifi)
-                                        .await;
-
-                                    if let Ok(res) = networks {
-                                        nt.notify(res)
-                                            .await
-                                            .unwrap_or_default();
-                                    }
-
-                                    time::sleep(Duration::from_secs(10)).await;
-                                }
-                            })
+                        method: CharacteristicNotifyMethod::Fun(Box::new(move |nt| {
+                            let clock = clock.clone();
+                            Box::pin(notify_changes(nt, wifi, security, &[WifiEvent::AccessPointsChanged], clock, read_wifi_networks(wifi), move || list_wifi_networks(wifi)))
                         })),
                         ..Default::default()
                     }),
@@ -154,27 +395,30 @@ impl WifiConfigurationService {
                     uuid: parse_uuid("3fa8daec-bb2a-465c-b5e5-5735a5c7acbd")?,
                     read: Some(CharacteristicRead {
                         read: true,
-                        fun: Box::new(move |_| {
-                            Box::pin(get_connection_status(wifi))
+                        fun: Box::new(move |req| {
+                            Box::pin(encrypted_read(security, req.device_address, get_connection_status(wifi)))
                         }),
                         ..Default::default()
                     }),
                     notify: Some(CharacteristicNotify {
                         notify: true,
-                        method: CharacteristicNotifyMethod::Fun(Box::new(move |mut nt| {
+                        method: CharacteristicNotifyMethod::Fun(Box::new(move |nt| {
+                            let clock = status_clock.clone();
+                            Box::pin(notify_changes(nt, wifi, security, &[WifiEvent::StateChanged], clock, get_connection_status(wifi), move || get_connection_status(wifi)))
+                        })),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                Characteristic {
+                    uuid: parse_uuid("beb1ed79-7b42-4bd1-968c-7d6d4c10eaa6")?,
+                    write: Some(CharacteristicWrite {
+                        write: true,
+                        encrypt_authenticated_write: true,
+                        method: CharacteristicWriteMethod::Fun(Box::new(move |data, req| {
                             Box::pin(async move {
-                                loop {
-                                    let is_connected = get_connection_status(wifi)
-                                        .await;
-
-                                    if let Ok(res) = is_connected {
-                                        nt.notify(res)
-                                            .await
-                                            .unwrap_or_default();
-                                    }
-
-                                    time::sleep(Duration::from_secs(3)).await;
-                                }
+                                let data = security.decrypt(req.device_address, &data)?;
+                                connect_to_ap(wifi, &data).await
                             })
                         })),
                         ..Default::default()
@@ -182,11 +426,82 @@ impl WifiConfigurationService {
                     ..Default::default()
                 },
                 Characteristic {
-                    uuid: parse_uuid("beb1ed79-7b42-4bd1-968c-7d6d4c10eaa6")?,
+                    uuid: parse_uuid("5c1a3b0e-8f2d-4c7e-9b6a-2d4e6f8a1c3b")?,
+                    read: Some(CharacteristicRead {
+                        read: true,
+                        fun: Box::new(move |req| {
+                            Box::pin(encrypted_read(security, req.device_address, read_saved_networks(wifi)))
+                        }),
+                        ..Default::default()
+                    }),
                     write: Some(CharacteristicWrite {
                         write: true,
-                        method: CharacteristicWriteMethod::Fun(Box::new(move |data, _| {
-                            Box::pin(async move { connect_to_ap(wifi, &data).await })
+                        encrypt_authenticated_write: true,
+                        method: CharacteristicWriteMethod::Fun(Box::new(move |data, req| {
+                            Box::pin(async move {
+                                let data = security.decrypt(req.device_address, &data)?;
+                                update_saved_network(wifi, &data).await
+                            })
+                        })),
+                        ..Default::default()
+                    }),
+                    notify: Some(CharacteristicNotify {
+                        notify: true,
+                        method: CharacteristicNotifyMethod::Fun(Box::new(move |nt| {
+                            let clock = saved_clock.clone();
+                            Box::pin(notify_changes(nt, wifi, security, &[WifiEvent::SavedNetworksChanged, WifiEvent::StateChanged], clock, read_saved_networks(wifi), move || read_saved_networks(wifi)))
+                        })),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                Characteristic {
+                    uuid: parse_uuid("9e7d2c41-3a6b-4f58-8c1e-7b5a0d9f2e63")?,
+                    write: Some(CharacteristicWrite {
+                        write: true,
+                        encrypt_authenticated_write: true,
+                        method: CharacteristicWriteMethod::Fun(Box::new(move |data, req| {
+                            Box::pin(async move {
+                                let data = security.decrypt(req.device_address, &data)?;
+                                upload_certificate(wifi, &data).await
+                            })
+                        })),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                Characteristic {
+                    uuid: parse_uuid("c2f4a8d1-5e93-4b7a-a6d0-3e8b1f7c9a52")?,
+                    read: Some(CharacteristicRead {
+                        read: true,
+                        fun: Box::new(move |req| {
+                            Box::pin(encrypted_read(security, req.device_address, async move { encode_provisioning_status(&wifi.provisioning_status()) }))
+                        }),
+                        ..Default::default()
+                    }),
+                    notify: Some(CharacteristicNotify {
+                        notify: true,
+                        method: CharacteristicNotifyMethod::Fun(Box::new(move |nt| {
+                            Box::pin(notify_provisioning_status(nt, wifi, security))
+                        })),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                Characteristic {
+                    uuid: parse_uuid("4b8e1f26-c7d3-4a95-b0e2-6f1d8a3c5e47")?,
+                    read: Some(CharacteristicRead {
+                        read: true,
+                        fun: Box::new(move |req| {
+                            Box::pin(encrypted_read(security, req.device_address, read_ip_configuration(wifi)))
+                        }),
+                        ..Default::default()
+                    }),
+                    notify: Some(CharacteristicNotify {
+                        notify: true,
+                        method: CharacteristicNotifyMethod::Fun(Box::new(move |nt| {
+                            let clock = ip_clock.clone();
+                            Box::pin(notify_changes(nt, wifi, security, &[WifiEvent::StateChanged], clock, read_ip_configuration(wifi), move || read_ip_configuration(wifi)))
                         })),
                         ..Default::default()
                     }),
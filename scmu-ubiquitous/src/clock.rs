@@ -0,0 +1,225 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH}
+};
+
+use tokio::sync::watch;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// source of time for everything that waits or measures time, so that it can be simulated;
+/// the short waits required by the sensor protocols always use the real time
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn system_time(&self) -> SystemTime;
+    fn sleep(&self, duration: Duration);
+    fn sleep_async(&self, duration: Duration) -> Sleep;
+
+    // seconds since the unix epoch
+    fn unix_time(&self) -> i64 {
+        self.system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
+    }
+}
+
+/// the clock shared by every part of the device
+pub type SharedClock = Arc<dyn Clock>;
+
+/// the real time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+
+    fn sleep_async(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// time only moves forward when the clock is advanced or every participant is asleep,
+/// then it jumps to the earliest deadline, so sleepers wake in the order of their deadlines
+/// and hours of simulated time take as long as the work done in between;
+/// a thread that has not joined only takes part while it sleeps
+pub struct VirtualClock {
+    origin: Instant,
+    system_origin: SystemTime,
+    shared: Arc<Shared>
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wakeup: Condvar,
+    // publishes the elapsed time to the async sleepers
+    ticks: watch::Sender<Duration>,
+    // keeps the channel open, and is cloned by every async sleeper
+    ticks_rx: watch::Receiver<Duration>
+}
+
+#[derive(Default)]
+struct State {
+    elapsed: Duration,
+    // deadline and sequence number of every blocked sleeper
+    sleepers: BTreeMap<(Duration, u64), ThreadId>,
+    next_sleeper: u64,
+    participants: HashSet<ThreadId>
+}
+
+impl State {
+    fn all_asleep(&self) -> bool {
+        self.participants.iter()
+            .all(|participant| self.sleepers.values().any(|sleeper| sleeper == participant))
+    }
+
+    // the sleepers that are due have resumed, and the participants among them are asleep again or gone
+    fn settled(&self, thread: ThreadId) -> bool {
+        self.sleepers.keys().all(|(deadline, _)| *deadline > self.elapsed)
+            && self.participants.iter()
+                .filter(|participant| **participant != thread)
+                .all(|participant| self.sleepers.values().any(|sleeper| sleeper == participant))
+    }
+
+    fn earliest_deadline(&self) -> Option<Duration> {
+        self.sleepers.keys()
+            .next()
+            .map(|(deadline, _)| *deadline)
+    }
+}
+
+impl Shared {
+    fn set_elapsed(&self, state: &mut State, elapsed: Duration) {
+        if elapsed > state.elapsed {
+            state.elapsed = elapsed;
+            self.ticks.send(elapsed).unwrap_or_default();
+            self.wakeup.notify_all();
+        }
+    }
+}
+
+/// keeps time from moving while the thread that joined is awake, until it is dropped
+pub struct Participant {
+    shared: Arc<Shared>,
+    thread: ThreadId
+}
+
+impl Drop for Participant {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().participants.remove(&self.thread);
+        self.shared.wakeup.notify_all();
+    }
+}
+
+impl VirtualClock {
+    pub fn new(system_origin: SystemTime) -> Self {
+        let (ticks, ticks_rx) = watch::channel(Duration::ZERO);
+        Self {
+            origin: Instant::now(),
+            system_origin,
+            shared: Arc::new(Shared {
+                state: Mutex::new(State::default()),
+                wakeup: Condvar::new(),
+                ticks,
+                ticks_rx
+            })
+        }
+    }
+
+    /// makes the current thread take part in the simulation, so that time does not move while it is awake
+    pub fn join(&self) -> Participant {
+        let thread = thread::current().id();
+        self.shared.state.lock().unwrap().participants.insert(thread);
+
+        Participant {
+            shared: self.shared.clone(),
+            thread
+        }
+    }
+
+    /// moves time forward, waking the sleepers on the way one deadline at a time
+    /// and waiting for the participants among them to go back to sleep in between
+    pub fn advance(&self, duration: Duration) {
+        let thread = thread::current().id();
+        let mut state = self.shared.state.lock().unwrap();
+        let target = state.elapsed + duration;
+
+        loop {
+            if !state.settled(thread) {
+                state = self.shared.wakeup.wait(state).unwrap();
+                continue
+            }
+
+            match state.earliest_deadline().filter(|deadline| *deadline <= target) {
+                Some(deadline) => self.shared.set_elapsed(&mut state, deadline),
+                None => break
+            }
+        }
+
+        self.shared.set_elapsed(&mut state, target);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.shared.state.lock().unwrap().elapsed
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.origin + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.system_origin + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut state = self.shared.state.lock().unwrap();
+        let deadline = state.elapsed + duration;
+        let key = (deadline, state.next_sleeper);
+        state.next_sleeper += 1;
+        state.sleepers.insert(key, thread::current().id());
+
+        while state.elapsed < deadline {
+            // nothing can happen before the earliest deadline once every participant is asleep
+            match state.earliest_deadline() {
+                Some(earliest) if earliest > state.elapsed && state.all_asleep() => self.shared.set_elapsed(&mut state, earliest),
+                _ => state = self.shared.wakeup.wait(state).unwrap()
+            }
+        }
+
+        state.sleepers.remove(&key);
+        self.shared.wakeup.notify_all();
+    }
+
+    // async sleepers wait for the time moved by the threads, they never move it themselves
+    fn sleep_async(&self, duration: Duration) -> Sleep {
+        let mut ticks = self.shared.ticks_rx.clone();
+        let deadline = *ticks.borrow() + duration;
+
+        Box::pin(async move {
+            while *ticks.borrow() < deadline {
+                if ticks.changed().await.is_err() {
+                    return
+                }
+            }
+        })
+    }
+}
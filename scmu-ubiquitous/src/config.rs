@@ -0,0 +1,387 @@
+use std::{env, fs, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{gpio::{anemometer::{ANEMOMETER_PIN, DEFAULT_CALIBRATION}, Location}, health::FailSafePolicy, history::Retention};
+
+const DEFAULT_CONFIG_PATH: &str = "config.json";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParticleMatterSensorKind {
+    Sps30,
+    Simulator
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RainSensorKind {
+    /// resistive plate, which only tells whether it is raining
+    Plate,
+    TippingBucket,
+    #[default]
+    Simulator,
+    TippingBucketSimulator
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindSensorKind {
+    /// cup anemometer with a reed switch
+    Anemometer,
+    #[default]
+    Simulator
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvironmentSensorKind {
+    /// temperature, humidity and pressure
+    Bme280,
+    /// temperature and humidity
+    Sht3x,
+    #[default]
+    Simulator
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Co2SensorKind {
+    Scd30,
+    Scd4x,
+    Simulator
+}
+
+#[derive(Deserialize)]
+pub struct ParticleMatterSensorConfig {
+    pub location: Location,
+    pub sensor: ParticleMatterSensorKind,
+    /// serial port of the sensor, the primary uart is used when absent
+    pub uart: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WindConfig {
+    pub sensor: WindSensorKind,
+    /// gpio pin of the reed switch of the anemometer
+    pub pin: u8,
+    /// wind speed (m/s) that corresponds to one pulse per second
+    pub calibration: f32
+}
+
+impl Default for WindConfig {
+    fn default() -> Self {
+        Self {
+            sensor: WindSensorKind::default(),
+            pin: ANEMOMETER_PIN,
+            calibration: DEFAULT_CALIBRATION
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct EnvironmentSensorConfig {
+    pub sensor: EnvironmentSensorKind,
+    /// i2c address of the sensor (decimal, 0x76 is 118), the default address of its kind is used when absent
+    pub address: Option<u16>
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct EnvironmentConfig {
+    pub outdoor: EnvironmentSensorConfig,
+    pub indoor: EnvironmentSensorConfig
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub path: String,
+    pub raw_retention_hours: u64,
+    pub minute_retention_days: u64,
+    pub hour_retention_days: u64
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            path: String::from("history.db"),
+            raw_retention_hours: 48,
+            minute_retention_days: 7,
+            hour_retention_days: 365
+        }
+    }
+}
+
+impl HistoryConfig {
+    pub fn retention(&self) -> Retention {
+        Retention {
+            raw: Duration::from_secs(self.raw_retention_hours * 60 * 60),
+            minute: Duration::from_secs(self.minute_retention_days * 24 * 60 * 60),
+            hour: Duration::from_secs(self.hour_retention_days * 24 * 60 * 60)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// address the local http api listens on, it is disabled when absent
+    pub listen: Option<String>
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            listen: Some(String::from("127.0.0.1:8081"))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// consecutive failed readings before a sensor is considered failed
+    pub max_read_errors: u32,
+    /// time without a valid reading before a sensor is considered failed
+    pub timeout_secs: u64,
+    /// identical consecutive readings before a sensor is considered stuck
+    pub stuck_readings: u32,
+    /// days without a change before the rain sensor is considered stuck
+    pub rain_stuck_days: u64,
+    pub fail_safe: FailSafePolicy
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_read_errors: 3,
+            timeout_secs: 60,
+            // 30 minutes with a reading every 5 seconds
+            stuck_readings: 360,
+            rain_stuck_days: 14,
+            fail_safe: FailSafePolicy::default()
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+    /// a random seed is used (and printed) when absent
+    pub seed: Option<u64>,
+    /// path of a scenario file replayed by the simulators
+    pub scenario: Option<String>
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    /// raw sensor readings are written to this file when present
+    pub path: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct ReplayConfig {
+    pub path: String,
+    /// 1 replays the recording in real time
+    #[serde(default = "default_replay_speed")]
+    pub speed: f32
+}
+
+fn default_replay_speed() -> f32 {
+    1.0
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            path: String::from("recording.jsonl"),
+            speed: default_replay_speed()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub url: String,
+    pub public_key: String,
+    /// http proxy to reach the server through, instead of the one provisioned with the wifi network
+    pub proxy: Option<String>
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            url: String::from("ws://192.168.0.232:8080/ubiquitous"),
+            public_key: String::from("test1_pk"),
+            proxy: None
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WifiConfig {
+    /// whether the wifi is managed through NetworkManager, which opens the hotspot when the device stays offline
+    pub enabled: bool,
+    /// where the certificates of enterprise networks uploaded over bluetooth are kept
+    pub certificate_dir: String,
+    pub hotspot: HotspotConfig
+}
+
+impl Default for WifiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            certificate_dir: String::from("certificates"),
+            hotspot: HotspotConfig::default()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    /// advertised over bluetooth
+    pub name: String,
+    /// where the settings changed from the phone are kept, on top of this configuration
+    pub settings_path: String
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            name: String::from("scmu"),
+            settings_path: String::from("device.json")
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct BluetoothConfig {
+    /// whether the wifi is provisioned over bluetooth, which needs the wifi to be enabled too
+    pub enabled: bool,
+    /// secret printed on the label of the device, which the app needs to provision it;
+    /// provisioning over bluetooth is refused while it is empty
+    pub proof_of_possession: String,
+    /// gpio pin of a led that blinks the passkeys of the pairings, which are printed otherwise
+    pub status_led_pin: Option<u8>
+}
+
+impl Default for BluetoothConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            proof_of_possession: String::new(),
+            status_led_pin: None
+        }
+    }
+}
+
+/// access point opened to configure the wifi from a browser, when the device stays offline
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct HotspotConfig {
+    /// minutes without a connection before the hotspot is opened
+    pub after_minutes: u64,
+    pub ssid: String,
+    /// at least 8 characters, the proof of possession of the bluetooth provisioning is used when absent
+    pub password: Option<String>,
+    /// port of the configuration page
+    pub port: u16
+}
+
+impl Default for HotspotConfig {
+    fn default() -> Self {
+        Self {
+            after_minutes: 5,
+            ssid: String::from("scmu-setup"),
+            password: None,
+            port: 80
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub device: DeviceConfig,
+    pub server: ServerConfig,
+    /// time between two readings of the sensors
+    pub sampling_interval_ms: u64,
+    pub rain: RainSensorKind,
+    pub particle_matter: Vec<ParticleMatterSensorConfig>,
+    pub wind: WindConfig,
+    pub environment: EnvironmentConfig,
+    /// indoor co2 sensor, the window is only opened to ventilate the room when there is one
+    pub co2: Option<Co2SensorKind>,
+    pub history: HistoryConfig,
+    pub api: ApiConfig,
+    pub health: HealthConfig,
+    pub simulation: SimulationConfig,
+    pub recording: RecordingConfig,
+    pub wifi: WifiConfig,
+    pub bluetooth: BluetoothConfig,
+    /// replaces every sensor with a recording
+    pub replay: Option<ReplayConfig>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            device: DeviceConfig::default(),
+            server: ServerConfig::default(),
+            sampling_interval_ms: 5000,
+            rain: RainSensorKind::default(),
+            particle_matter: vec![
+                ParticleMatterSensorConfig {
+                    location: Location::Outdoor,
+                    sensor: ParticleMatterSensorKind::Simulator,
+                    uart: None
+                }
+            ],
+            wind: WindConfig::default(),
+            environment: EnvironmentConfig::default(),
+            co2: None,
+            history: HistoryConfig::default(),
+            api: ApiConfig::default(),
+            health: HealthConfig::default(),
+            simulation: SimulationConfig::default(),
+            recording: RecordingConfig::default(),
+            wifi: WifiConfig::default(),
+            bluetooth: BluetoothConfig::default(),
+            replay: None
+        }
+    }
+}
+
+impl Config {
+    /// reads the configuration from the path given as the first argument (or SCMU_CONFIG),
+    /// falling back to the defaults when there is no configuration file
+    pub fn load() -> Self {
+        let path = env::args()
+            .nth(1)
+            .or_else(|| env::var("SCMU_CONFIG").ok())
+            .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+
+        let config: Config = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .unwrap_or_else(|err| panic!("Invalid configuration file {}: {}", path, err)),
+            Err(_) => {
+                println!("No configuration file found at {}, using defaults", path);
+                Config::default()
+            }
+        };
+
+        if !config.particle_matter.iter().any(|pm| pm.location == Location::Outdoor) {
+            panic!("At least one outdoor particle matter sensor must be configured")
+        }
+
+        config
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{aqi::{AirQuality, AqiThreshold}, gpio::{anemometer::Wind, co2::Co2, environment::Environment, particle_matter::ParticleMatter, rain::Rain}};
+
+/// limits above which the window is closed
+pub struct Thresholds {
+    pub pm_25: u32,
+    pub pm_10: u32,
+    pub wind_speed: f32,
+    pub wind_gust: f32,
+    pub co2: u16,
+    /// only open the window when the outdoor air is cleaner than the indoor air by `pm_margin`
+    pub compare_particle_matter: bool,
+    pub pm_margin: u32,
+    /// highest index category allowed, on top of the pm 2.5 and pm 10 thresholds
+    pub aqi: Option<AqiThreshold>
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            pm_25: 100,
+            pm_10: 100,
+            wind_speed: 10.0,
+            wind_gust: 15.0,
+            co2: 1000,
+            compare_particle_matter: false,
+            pm_margin: 10,
+            aqi: None
+        }
+    }
+}
+
+/// latest readings the decision is based on
+pub struct Readings<'a> {
+    /// readings of failed sensors are left out
+    pub rain: Option<&'a Rain>,
+    pub pm: Option<&'a ParticleMatter>,
+    pub indoor_pm: Option<&'a ParticleMatter>,
+    pub wind: Option<&'a Wind>,
+    /// only the humidity is used, to compensate the particle matter; the temperature is reported but
+    /// does not move the window, since closing it in the cold would keep the co2 from being ventilated
+    /// and the rain and wind already protect the room from the weather
+    pub outdoor: Option<&'a Environment>,
+    pub indoor: Option<&'a Environment>,
+    pub co2: Option<&'a Co2>,
+    pub air_quality: Option<&'a AirQuality>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CloseReason {
+    Rain,
+    Pm25,
+    Pm10,
+    Wind,
+    Gust,
+    AirQuality,
+    AirRenewed,
+    OutdoorDirtier,
+    SensorFailure
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenReason {
+    ClearAir,
+    Ventilation,
+    CleanerOutside
+}
+
+/// what the window should do after a reading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    Open(OpenReason),
+    Close(CloseReason),
+    Keep
+}
+
+// once opened for ventilation, the window stays open until the co2 drops this much below the limit
+const CO2_HYSTERESIS: u16 = 200;
+
+fn compensated(pm: &ParticleMatter, environment: Option<&Environment>) -> ParticleMatter {
+    match environment {
+        Some(environment) => pm.compensate_humidity(environment.humidity),
+        None => *pm
+    }
+}
+
+/// returns the first condition that requires the window to be closed, if any
+pub fn close_reason(thresholds: &Thresholds, readings: &Readings) -> Option<CloseReason> {
+    if readings.rain.is_some_and(|rain| rain.is_raining) {
+        return Some(CloseReason::Rain)
+    }
+
+    if let Some(wind) = readings.wind {
+        if wind.speed > thresholds.wind_speed {
+            return Some(CloseReason::Wind)
+        }
+
+        if wind.gust > thresholds.wind_gust {
+            return Some(CloseReason::Gust)
+        }
+    }
+
+    let aqi_exceeded = thresholds.aqi
+        .zip(readings.air_quality)
+        .and_then(|(threshold, air_quality)| threshold.is_exceeded(air_quality));
+
+    if aqi_exceeded == Some(true) {
+        return Some(CloseReason::AirQuality)
+    }
+
+    let pm = match readings.pm {
+        Some(pm) => compensated(pm, readings.outdoor),
+        None => return None
+    };
+
+    if (pm.pm_25_level as u32) > thresholds.pm_25 {
+        return Some(CloseReason::Pm25)
+    }
+
+    if (pm.pm_10_level as u32) > thresholds.pm_10 {
+        return Some(CloseReason::Pm10)
+    }
+
+    None
+}
+
+/// without an indoor co2 sensor the window is open whenever the outdoor conditions allow it,
+/// otherwise it is only opened to ventilate the room when the co2 goes above the limit;
+/// when comparing particle matter it is also opened whenever the outdoor air is cleaner
+pub fn decide(thresholds: &Thresholds, readings: &Readings, closed: bool) -> Decision {
+    if let Some(reason) = close_reason(thresholds, readings) {
+        return Decision::Close(reason)
+    }
+
+    if let (true, Some(outdoor_pm), Some(indoor_pm)) = (thresholds.compare_particle_matter, readings.pm, readings.indoor_pm) {
+        let outdoor_pm = compensated(outdoor_pm, readings.outdoor);
+        let indoor_pm = compensated(indoor_pm, readings.indoor);
+
+        let levels = [
+            (outdoor_pm.pm_25_level as u32, indoor_pm.pm_25_level as u32),
+            (outdoor_pm.pm_10_level as u32, indoor_pm.pm_10_level as u32)
+        ];
+
+        if levels.iter().any(|(outdoor, indoor)| outdoor > indoor) {
+            return Decision::Close(CloseReason::OutdoorDirtier)
+        }
+
+        // neither is dirtier outside, so one being cleaner by the margin is enough
+        if levels.iter().any(|(outdoor, indoor)| outdoor + thresholds.pm_margin < *indoor) {
+            return Decision::Open(OpenReason::CleanerOutside)
+        }
+
+        // while the difference is within the margin only the co2 can open the window
+        return match readings.co2 {
+            Some(co2) if co2.concentration > thresholds.co2 => Decision::Open(OpenReason::Ventilation),
+            _ => Decision::Keep
+        }
+    }
+
+    match readings.co2 {
+        None => Decision::Open(OpenReason::ClearAir),
+        Some(co2) if co2.concentration > thresholds.co2 => Decision::Open(OpenReason::Ventilation),
+        Some(co2) if !closed && co2.concentration < thresholds.co2.saturating_sub(CO2_HYSTERESIS) => Decision::Close(CloseReason::AirRenewed),
+        Some(_) => Decision::Keep
+    }
+}
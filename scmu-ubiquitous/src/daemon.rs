@@ -0,0 +1,405 @@
+//! sampling loop of the device: reads the sensors, decides on the window and talks to the server
+
+use std::{thread, sync::{Arc, Mutex}, time};
+
+use tungstenite::Message;
+use url::Url;
+
+use crate::{
+    api,
+    aqi::PmHistory,
+    clock::SharedClock,
+    config::{Co2SensorKind, Config, EnvironmentSensorConfig, EnvironmentSensorKind, ParticleMatterSensorKind, RainSensorKind, WindSensorKind},
+    controller::{self, Thresholds, Readings, Decision, CloseReason},
+    device::{DeviceSettings, SharedDeviceConfiguration},
+    health::{Fault, FailSafeAction, HealthMonitor},
+    history::History,
+    protocol::{backfill_chunks, history_chunks, EnvironmentData, Incoming, Outgoing, PkConfiguration, ServerMessage, Wifi},
+    transport::ServerConnection,
+    wifi,
+    gpio::{Location, recording::{Event, Recorder, Replay}, simulation::Simulation, rain::{ReplayRainSensor, RainSensorReal, RainSensorSimulator, RainSensor}, rain_gauge::{TippingBucketRainSensorReal, TippingBucketRainSensorSimulator}, particle_matter::{ReplayParticleMatterSensor, ParticleMatterSensorSimulator, ParticleMatterSensorReal, ParticleMatterSensor, ParticleMatter}, anemometer::{AnemometerReal, AnemometerSimulator, ReplayWindSensor, WindSensor}, environment::{EnvironmentSensorSimulator, ReplayEnvironmentSensor, EnvironmentSensor}, co2::{Co2SensorSimulator, ReplayCo2Sensor, Co2Sensor}, bme280::Bme280, sht3x::Sht3x, scd30::Scd30, scd4x::Scd4x}
+};
+
+fn apply_thresholds(sd: &mut SharedData, inc: Incoming) {
+    sd.thresholds.pm_25 = inc.pm_25_threshold;
+    sd.thresholds.pm_10 = inc.pm_10_threshold;
+
+    if let Some(wind_threshold) = inc.wind_threshold {
+        sd.thresholds.wind_speed = wind_threshold;
+    }
+
+    if let Some(gust_threshold) = inc.gust_threshold {
+        sd.thresholds.wind_gust = gust_threshold;
+    }
+
+    if let Some(co2_threshold) = inc.co2_threshold {
+        sd.thresholds.co2 = co2_threshold;
+    }
+
+    if let Some(compare_particle_matter) = inc.compare_particle_matter {
+        sd.thresholds.compare_particle_matter = compare_particle_matter;
+    }
+
+    if let Some(pm_margin) = inc.pm_margin {
+        sd.thresholds.pm_margin = pm_margin;
+    }
+
+    if let Some(aqi_threshold) = inc.aqi_threshold {
+        sd.thresholds.aqi = Some(aqi_threshold);
+    }
+
+    if inc.co2_recalibration.is_some() {
+        sd.co2_recalibration = inc.co2_recalibration;
+    }
+
+    println!("pm 2.5 threshold: {}", inc.pm_25_threshold);
+    println!("pm 10 threshold: {}", inc.pm_10_threshold);
+    println!("wind threshold: {}", sd.thresholds.wind_speed);
+    println!("gust threshold: {}", sd.thresholds.wind_gust);
+    println!("co2 threshold: {}", sd.thresholds.co2);
+    println!("aqi threshold: {:?}", sd.thresholds.aqi);
+}
+
+type LocatedParticleMatterSensor = (Location, Box<dyn ParticleMatterSensor>);
+
+// sensors are named after their position in the configuration, e.g. pm_0
+fn pm_sensor_name(index: usize) -> String {
+    format!("pm_{}", index)
+}
+
+fn create_pm_sensors(config: &Config, simulation: &Simulation, recorder: &Recorder, replay: Option<&Replay>) -> Vec<LocatedParticleMatterSensor> {
+    config.particle_matter
+        .iter()
+        .enumerate()
+        .map(|(index, pm)| {
+            let name = pm_sensor_name(index);
+            let sensor: Box<dyn ParticleMatterSensor> = match (replay, &pm.sensor, &pm.uart) {
+                (Some(replay), _, _) => Box::new(ReplayParticleMatterSensor::with_replay(replay, &name)),
+                (None, ParticleMatterSensorKind::Sps30, Some(path)) => Box::new(ParticleMatterSensorReal::with_path(path).with_recorder(recorder, &name).with_clock(simulation.clock())),
+                (None, ParticleMatterSensorKind::Sps30, None) => Box::new(ParticleMatterSensorReal::new().with_recorder(recorder, &name).with_clock(simulation.clock())),
+                (None, ParticleMatterSensorKind::Simulator, _) => Box::new(ParticleMatterSensorSimulator::with_simulation(simulation, pm.location, index))
+            };
+
+            (pm.location, sensor)
+        })
+        .collect()
+}
+
+fn create_env_sensor(config: &EnvironmentSensorConfig, location: Location, simulation: &Simulation, replay: Option<&Replay>) -> Box<dyn EnvironmentSensor> {
+    let name = match location {
+        Location::Outdoor => "outdoor_environment",
+        Location::Indoor => "indoor_environment"
+    };
+
+    match (replay, &config.sensor, config.address) {
+        (Some(replay), _, _) => Box::new(ReplayEnvironmentSensor::with_replay(replay, name)),
+        (None, EnvironmentSensorKind::Bme280, Some(address)) => Box::new(Bme280::with_address(address)),
+        (None, EnvironmentSensorKind::Bme280, None) => Box::new(Bme280::new()),
+        (None, EnvironmentSensorKind::Sht3x, Some(address)) => Box::new(Sht3x::with_address(address)),
+        (None, EnvironmentSensorKind::Sht3x, None) => Box::new(Sht3x::new()),
+        (None, EnvironmentSensorKind::Simulator, _) => Box::new(EnvironmentSensorSimulator::with_simulation(simulation, location))
+    }
+}
+
+// averages the readings of every sensor installed at the given location
+fn read_pm(sensors: &mut [LocatedParticleMatterSensor], location: Location, recorder: &Recorder) -> Option<ParticleMatter> {
+    let readings: Vec<ParticleMatter> = sensors.iter_mut()
+        .enumerate()
+        .filter(|(_, (sensor_location, _))| *sensor_location == location)
+        .filter_map(|(index, (_, sensor))| recorder.reading(&pm_sensor_name(index), sensor.read_value(), Event::ParticleMatter))
+        .collect();
+
+    if readings.is_empty() {
+        return None
+    }
+
+    let count = readings.len() as u32;
+    Some(ParticleMatter {
+        pm_25_level: (readings.iter().map(|pm| pm.pm_25_level as u32).sum::<u32>() / count) as u16,
+        pm_10_level: (readings.iter().map(|pm| pm.pm_10_level as u32).sum::<u32>() / count) as u16
+    })
+}
+
+fn pm_faults(sensors: &mut [LocatedParticleMatterSensor], location: Location) -> Vec<Fault> {
+    sensors.iter_mut()
+        .filter(|(sensor_location, _)| *sensor_location == location)
+        .flat_map(|(_, sensor)| sensor.device_faults())
+        .collect()
+}
+
+// time the reader waits between checks for messages from the server
+const RECEIVE_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+struct SharedData {
+    connection: ServerConnection,
+    thresholds: Thresholds,
+    co2_recalibration: Option<u16>,
+    closed: bool
+}
+
+// settings changed from the phone; their thresholds follow the ones sent by the server, so they
+// only differ from the ones in use when the phone set them, even to the value it had set before
+fn apply_settings(sd: &mut SharedData, applied: &DeviceSettings, settings: &DeviceSettings) {
+    sd.thresholds.pm_25 = settings.pm_25_threshold;
+    sd.thresholds.pm_10 = settings.pm_10_threshold;
+
+    if settings.server_url != applied.server_url {
+        match Url::parse(&settings.server_url) {
+            Ok(url) => sd.connection.set_url(url),
+            Err(err) => println!("Invalid server url {}: {}", settings.server_url, err)
+        }
+    }
+}
+
+/// runs the device until the process is stopped, `device` holds the settings changed from the phone
+pub fn run(config: Config, device: SharedDeviceConfiguration, clock: SharedClock) {
+    let simulation = Simulation::new(&config.simulation, clock.clone());
+
+    let recorder = Recorder::new(&config.recording, clock.clone());
+    let replay = config.replay.as_ref().map(|replay| Replay::load(replay, clock.clone()));
+
+    let mut rain_sensor: Box<dyn RainSensor> = match (&replay, &config.rain) {
+        (Some(replay), _) => Box::new(ReplayRainSensor::with_replay(replay, "rain")),
+        (None, RainSensorKind::Plate) => Box::new(RainSensorReal::new()),
+        (None, RainSensorKind::TippingBucket) => Box::new(TippingBucketRainSensorReal::new()),
+        (None, RainSensorKind::Simulator) => Box::new(RainSensorSimulator::with_simulation(&simulation)),
+        (None, RainSensorKind::TippingBucketSimulator) => Box::new(TippingBucketRainSensorSimulator::with_simulation(&simulation))
+    };
+
+    let mut pm_sensors = create_pm_sensors(&config, &simulation, &recorder, replay.as_ref());
+    let mut pm_history = PmHistory::new(clock.clone());
+    let mut health = HealthMonitor::new(&config.health, config.particle_matter.iter().any(|pm| pm.location == Location::Indoor), config.co2.is_some(), clock.clone());
+
+    let history = Arc::new(Mutex::new(History::open(&config.history.path, config.history.retention())
+        .unwrap()));
+
+    let mut wind_sensor: Box<dyn WindSensor> = match (&replay, &config.wind.sensor) {
+        (Some(replay), _) => Box::new(ReplayWindSensor::with_replay(replay, "wind")),
+        (None, WindSensorKind::Anemometer) => Box::new(AnemometerReal::with_pin(config.wind.pin).with_calibration(config.wind.calibration)),
+        (None, WindSensorKind::Simulator) => Box::new(AnemometerSimulator::with_simulation(&simulation))
+    };
+
+    let mut outdoor_env_sensor = create_env_sensor(&config.environment.outdoor, Location::Outdoor, &simulation, replay.as_ref());
+    let mut indoor_env_sensor = create_env_sensor(&config.environment.indoor, Location::Indoor, &simulation, replay.as_ref());
+
+    let mut co2_sensor: Option<Box<dyn Co2Sensor>> = config.co2.as_ref().map(|kind| -> Box<dyn Co2Sensor> {
+        match (&replay, kind) {
+            (Some(replay), _) => Box::new(ReplayCo2Sensor::with_replay(replay, "co2")),
+            (None, Co2SensorKind::Scd30) => Box::new(Scd30::new()),
+            (None, Co2SensorKind::Scd4x) => Box::new(Scd4x::new()),
+            (None, Co2SensorKind::Simulator) => Box::new(Co2SensorSimulator::with_simulation(&simulation))
+        }
+    });
+
+    if let Some(listen) = &config.api.listen {
+        api::serve(listen, history.clone(), clock.clone())
+            .unwrap_or_else(|err| println!("{}", err));
+    }
+
+    let mut settings = device.settings();
+    let mut generation = 0;
+
+    // timestamp and connection of the last status sent, the readings recorded since then are sent again
+    // on a new connection, starting with that status since it may have been lost with the old one
+    let mut last_sent: Option<(i64, u64)> = None;
+
+    let url = Url::parse(&settings.server_url)
+        .unwrap();
+
+    let config_pkt = serde_json::to_string(&PkConfiguration {
+        public_key: config.server.public_key.clone()
+    }).unwrap();
+
+    let proxy = config.server.proxy.clone();
+    let connection = ServerConnection::new(url, config_pkt, clock.clone())
+        .with_proxy(move || proxy.clone().or_else(wifi::http_proxy));
+
+    let shared_data = Arc::new(Mutex::new(SharedData {
+        connection,
+        thresholds: Thresholds {
+            pm_25: settings.pm_25_threshold,
+            pm_10: settings.pm_10_threshold,
+            ..Thresholds::default()
+        },
+        co2_recalibration: None,
+        closed: false
+    }));
+
+    let sd_cln = shared_data.clone();
+    let hist_cln = history.clone();
+    let device_cln = device.clone();
+    let clock_cln = clock.clone();
+    thread::spawn(move || {
+        loop {
+            {
+                let mut lkd = sd_cln.lock().unwrap();
+                match lkd.connection.receive() {
+                    Some(Message::Text(text)) => {
+                        match serde_json::from_str(&text) {
+                            Ok(ServerMessage::Thresholds(inc)) => {
+                                device_cln.record_thresholds(inc.pm_25_threshold, inc.pm_10_threshold);
+                                apply_thresholds(&mut lkd, inc);
+                            },
+                            Ok(ServerMessage::HistoryRequest(req)) => {
+                                let chunks = history_chunks(&hist_cln.lock().unwrap(), &req);
+                                for chunk in chunks {
+                                    lkd.connection.send(Message::Binary(chunk))
+                                        .unwrap_or_else(|err| println!("Failed to send history: {}", err));
+                                }
+                            },
+                            Err(err) => println!("Invalid message from the server: {}", err)
+                        }
+                    },
+                    Some(Message::Ping(payload)) => {
+                        lkd.connection.send(Message::Pong(payload))
+                            .unwrap_or_else(|err| println!("Failed to send pong: {}", err));
+                    },
+                    _ => {}
+                }
+            }
+
+            clock_cln.sleep(RECEIVE_INTERVAL);
+        }
+    });
+
+    loop {
+        let rain_val = health.rain.check(recorder.reading("rain", rain_sensor.read_value(), Event::Rain), &[]);
+
+        let outdoor_faults = pm_faults(&mut pm_sensors, Location::Outdoor);
+        let pm_val = health.outdoor_pm.check(read_pm(&mut pm_sensors, Location::Outdoor, &recorder), &outdoor_faults);
+
+        let indoor_pm_val = match &mut health.indoor_pm {
+            Some(check) => {
+                let indoor_faults = pm_faults(&mut pm_sensors, Location::Indoor);
+                check.check(read_pm(&mut pm_sensors, Location::Indoor, &recorder), &indoor_faults)
+            },
+            None => None
+        };
+
+        if let Some(pm_val) = &pm_val {
+            pm_history.record(pm_val);
+        }
+
+        let air_quality = pm_history.air_quality();
+
+        let wind_val = health.wind.check(recorder.reading("wind", wind_sensor.read_value(), Event::Wind), &[]);
+        let outdoor_val = health.outdoor.check(recorder.reading("outdoor_environment", outdoor_env_sensor.read_value(), Event::Environment), &[]);
+        let indoor_val = health.indoor.check(recorder.reading("indoor_environment", indoor_env_sensor.read_value(), Event::Environment), &[]);
+        let co2_val = match (&mut co2_sensor, &mut health.co2) {
+            (Some(sensor), Some(check)) => check.check(recorder.reading("co2", sensor.read_value(), Event::Co2), &[]),
+            _ => None
+        };
+        let health_report = health.report();
+
+        {
+            let mut sd = shared_data.lock().unwrap();
+            if let Some(changed) = device.changed(&mut generation) {
+                apply_settings(&mut sd, &settings, &changed);
+                settings = changed;
+            }
+
+            if let Some(reference) = sd.co2_recalibration.take() {
+                match co2_sensor.as_mut().map(|sensor| sensor.force_recalibration(reference)) {
+                    Some(Some(_)) => println!("co2 sensor recalibrated to {} ppm", reference),
+                    Some(None) => println!("co2 sensor recalibration failed"),
+                    None => println!("No co2 sensor to recalibrate")
+                }
+            }
+
+            let readings = Readings {
+                rain: rain_val.as_ref(),
+                pm: pm_val.as_ref(),
+                indoor_pm: indoor_pm_val.as_ref(),
+                wind: wind_val.as_ref(),
+                outdoor: outdoor_val.as_ref(),
+                indoor: indoor_val.as_ref(),
+                co2: co2_val.as_ref(),
+                air_quality: Some(&air_quality)
+            };
+
+            let timestamp = clock.unix_time();
+            let decision = match config.health.fail_safe.action(&health_report) {
+                FailSafeAction::Close => Decision::Close(CloseReason::SensorFailure),
+                FailSafeAction::Keep => Decision::Keep,
+                FailSafeAction::Ignore => controller::decide(&sd.thresholds, &readings, sd.closed)
+            };
+
+            let event = match decision {
+                Decision::Close(reason) if !sd.closed => {
+                    println!("Closing ({:?})...", reason);
+                    sd.closed = true;
+                    Some(format!("{:?}", reason))
+                },
+                Decision::Open(reason) if sd.closed => {
+                    println!("Opening ({:?})...", reason);
+                    sd.closed = false;
+                    Some(format!("{:?}", reason))
+                },
+                _ => None
+            };
+
+            if let Some(reason) = event {
+                history.lock().unwrap().record_window_event(timestamp, sd.closed, &reason)
+                    .unwrap_or_else(|err| println!("Failed to record window event: {}", err));
+            }
+
+            let status = Outgoing {
+                is_closed: sd.closed,
+                is_raining: rain_val.as_ref().is_some_and(|r| r.is_raining),
+                rain_accumulated: rain_val.as_ref().and_then(|r| r.intensity.as_ref()).map(|i| i.accumulated_mm),
+                rain_rate: rain_val.as_ref().and_then(|r| r.intensity.as_ref()).map(|i| i.rate_mm_h),
+                pm_25_level: pm_val.map(|pm| pm.pm_25_level as u32),
+                pm_10_level: pm_val.map(|pm| pm.pm_10_level as u32),
+                indoor_pm_25_level: indoor_pm_val.map(|pm| pm.pm_25_level as u32),
+                indoor_pm_10_level: indoor_pm_val.map(|pm| pm.pm_10_level as u32),
+                air_quality,
+                wind_speed: wind_val.as_ref().map(|w| w.speed),
+                wind_gust: wind_val.as_ref().map(|w| w.gust),
+                co2_level: co2_val.as_ref().map(|c| c.concentration),
+                indoor: indoor_val.as_ref().map(EnvironmentData::from),
+                outdoor: outdoor_val.as_ref().map(EnvironmentData::from),
+                health: health_report,
+                thresholds: Incoming {
+                    pm_25_threshold: sd.thresholds.pm_25,
+                    pm_10_threshold: sd.thresholds.pm_10,
+                    wind_threshold: Some(sd.thresholds.wind_speed),
+                    gust_threshold: Some(sd.thresholds.wind_gust),
+                    co2_threshold: Some(sd.thresholds.co2),
+                    co2_recalibration: None,
+                    compare_particle_matter: Some(sd.thresholds.compare_particle_matter),
+                    pm_margin: Some(sd.thresholds.pm_margin),
+                    aqi_threshold: sd.thresholds.aqi,
+                    signature: None
+                },
+                wifi: Wifi { ssid: "hello".to_string(), strength: 100 }
+            };
+
+            history.lock().unwrap().record(timestamp, &status.metrics())
+                .unwrap_or_else(|err| println!("Failed to record readings: {}", err));
+
+            let status_pkt = serde_json::to_string(&status)
+                .unwrap();
+
+            match sd.connection.send(Message::Text(status_pkt)) {
+                Ok(_) => {
+                    let connection = sd.connection.connections();
+                    if let Some((sent_at, sent_on)) = last_sent {
+                        if sent_on != connection && sent_at < timestamp {
+                            let chunks = backfill_chunks(&history.lock().unwrap(), sent_at, timestamp - 1);
+                            for chunk in chunks {
+                                sd.connection.send(Message::Binary(chunk))
+                                    .unwrap_or_else(|err| println!("Failed to send backfill: {}", err));
+                            }
+                        }
+                    }
+
+                    last_sent = Some((timestamp, connection));
+                },
+                Err(err) => println!("Failed to send status: {}", err)
+            }
+        }
+
+        // replays run the loop faster, so that no recorded reading is skipped
+        clock.sleep(time::Duration::from_millis(settings.sampling_interval_ms).div_f32(replay.as_ref().map_or(1.0, Replay::speed)))
+    }
+}
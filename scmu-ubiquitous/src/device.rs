@@ -0,0 +1,209 @@
+//! settings of the device that can be changed from the phone while it runs,
+//! kept in a file of their own so that the configuration file is never rewritten
+
+use std::{fs, sync::{Arc, Mutex}, time::Duration};
+
+use serde::{Serialize, Deserialize};
+use tokio::sync::watch;
+use url::Url;
+
+use crate::{config::Config, controller::Thresholds, util::Result};
+
+// the name has to fit in the advertisement next to the services
+const MAX_NAME_LEN: usize = 20;
+const MIN_SAMPLING_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_SAMPLING_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// the settings in use
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceSettings {
+    pub name: String,
+    pub server_url: String,
+    pub sampling_interval_ms: u64,
+    pub pm_25_threshold: u32,
+    pub pm_10_threshold: u32
+}
+
+/// the settings to change, the missing ones are left as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SettingsUpdate {
+    pub name: Option<String>,
+    pub server_url: Option<String>,
+    pub sampling_interval_ms: Option<u64>,
+    pub pm_25_threshold: Option<u32>,
+    pub pm_10_threshold: Option<u32>
+}
+
+impl SettingsUpdate {
+    fn validate(&self) -> Result<()> {
+        if let Some(name) = &self.name {
+            if name.is_empty() || name.len() > MAX_NAME_LEN {
+                return Err(format!("The name must have between 1 and {} bytes", MAX_NAME_LEN))
+            }
+        }
+
+        if let Some(server_url) = &self.server_url {
+            let url = Url::parse(server_url)
+                .map_err(|err| format!("Invalid server url {}: {}", server_url, err))?;
+
+            if !matches!(url.scheme(), "ws" | "wss") {
+                return Err(format!("The server url must be a websocket url, not {}", server_url))
+            }
+        }
+
+        if let Some(interval) = self.sampling_interval_ms {
+            let interval = Duration::from_millis(interval);
+            if interval < MIN_SAMPLING_INTERVAL || interval > MAX_SAMPLING_INTERVAL {
+                return Err(format!("The sampling interval must be between {:?} and {:?}", MIN_SAMPLING_INTERVAL, MAX_SAMPLING_INTERVAL))
+            }
+        }
+
+        if self.pm_25_threshold == Some(0) || self.pm_10_threshold == Some(0) {
+            return Err(String::from("The thresholds must be above 0"))
+        }
+
+        Ok(())
+    }
+
+    // later changes take the place of the earlier ones
+    fn merge(&mut self, update: SettingsUpdate) {
+        self.name = update.name.or(self.name.take());
+        self.server_url = update.server_url.or(self.server_url.take());
+        self.sampling_interval_ms = update.sampling_interval_ms.or(self.sampling_interval_ms);
+        self.pm_25_threshold = update.pm_25_threshold.or(self.pm_25_threshold);
+        self.pm_10_threshold = update.pm_10_threshold.or(self.pm_10_threshold);
+    }
+
+    fn apply(&self, settings: &mut DeviceSettings) {
+        if let Some(name) = &self.name {
+            settings.name = name.clone();
+        }
+
+        if let Some(server_url) = &self.server_url {
+            settings.server_url = server_url.clone();
+        }
+
+        if let Some(interval) = self.sampling_interval_ms {
+            settings.sampling_interval_ms = interval;
+        }
+
+        if let Some(threshold) = self.pm_25_threshold {
+            settings.pm_25_threshold = threshold;
+        }
+
+        if let Some(threshold) = self.pm_10_threshold {
+            settings.pm_10_threshold = threshold;
+        }
+    }
+}
+
+struct State {
+    settings: DeviceSettings,
+    // only what was changed from the phone is saved
+    saved: SettingsUpdate,
+    // counts the changes made from the phone
+    generation: u64
+}
+
+/// the settings shared by the daemon and the bluetooth service
+pub struct DeviceConfiguration {
+    path: String,
+    state: Mutex<State>,
+    changes: watch::Sender<DeviceSettings>,
+    // kept so that sending never fails for lack of receivers
+    receiver: watch::Receiver<DeviceSettings>
+}
+
+pub type SharedDeviceConfiguration = Arc<DeviceConfiguration>;
+
+impl DeviceConfiguration {
+    /// starts from the configuration, with the settings saved before on top of it
+    pub fn load(config: &Config) -> Self {
+        let thresholds = Thresholds::default();
+        let mut settings = DeviceSettings {
+            name: config.device.name.clone(),
+            server_url: config.server.url.clone(),
+            sampling_interval_ms: config.sampling_interval_ms,
+            pm_25_threshold: thresholds.pm_25,
+            pm_10_threshold: thresholds.pm_10
+        };
+
+        let path = config.device.settings_path.clone();
+        let saved = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str::<SettingsUpdate>(&contents)
+                .map_err(|err| err.to_string())
+                .and_then(|saved| saved.validate().map(|_| saved))
+                .unwrap_or_else(|err| {
+                    println!("Ignoring the invalid device settings at {}: {}", path, err);
+                    SettingsUpdate::default()
+                }),
+            Err(_) => SettingsUpdate::default()
+        };
+
+        saved.apply(&mut settings);
+
+        let (changes, receiver) = watch::channel(settings.clone());
+        Self {
+            path,
+            state: Mutex::new(State { settings, saved, generation: 0 }),
+            changes,
+            receiver
+        }
+    }
+
+    pub fn settings(&self) -> DeviceSettings {
+        self.state.lock().unwrap().settings.clone()
+    }
+
+    /// applies and saves the change, which the daemon picks up on its next reading
+    pub fn update(&self, update: SettingsUpdate) -> Result<()> {
+        update.validate()?;
+
+        let mut state = self.state.lock().unwrap();
+        let mut saved = state.saved.clone();
+        saved.merge(update.clone());
+
+        let contents = serde_json::to_string_pretty(&saved)
+            .map_err(|err| err.to_string())?;
+
+        // replaced at once, so that a crash never leaves half of the file
+        let tmp_path = format!("{}.tmp", self.path);
+        fs::write(&tmp_path, contents)
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .map_err(|err| format!("Failed to save the device settings: {}", err))?;
+
+        // only what changed, the thresholds sent by the server meanwhile are kept otherwise
+        update.apply(&mut state.settings);
+        state.saved = saved;
+        state.generation += 1;
+
+        self.changes.send(state.settings.clone()).unwrap_or_default();
+        Ok(())
+    }
+
+    /// thresholds sent by the server, which only last until the device restarts
+    pub fn record_thresholds(&self, pm_25_threshold: u32, pm_10_threshold: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.settings.pm_25_threshold = pm_25_threshold;
+        state.settings.pm_10_threshold = pm_10_threshold;
+
+        self.changes.send(state.settings.clone()).unwrap_or_default();
+    }
+
+    /// the settings when they were changed from the phone since `generation`, which is moved forward
+    pub fn changed(&self, generation: &mut u64) -> Option<DeviceSettings> {
+        let state = self.state.lock().unwrap();
+        if state.generation == *generation {
+            return None
+        }
+
+        *generation = state.generation;
+        Some(state.settings.clone())
+    }
+
+    /// notified of every change, including the thresholds sent by the server
+    pub fn subscribe(&self) -> watch::Receiver<DeviceSettings> {
+        self.receiver.clone()
+    }
+}
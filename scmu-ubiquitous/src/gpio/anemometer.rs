@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng};
+use serde::{Serialize, Deserialize};
+
+use crate::{clock::{self, SharedClock}, config::ReplayConfig};
+
+use super::{pulse::{GpioPulseSource, PulseSource, PulseSourceSimulator, PulseWindow}, recording::{Event, Replay, ReplayCursor}, simulation::Simulation};
+
+pub const ANEMOMETER_PIN: u8 = 26;
+
+/// one closure of the reed switch per second corresponds to 2.4 km/h
+pub const DEFAULT_CALIBRATION: f32 = 2.4 / 3.6;
+
+const DEBOUNCE: Duration = Duration::from_millis(5);
+
+// window used to compute the average wind speed
+const AVERAGE_WINDOW: Duration = Duration::from_secs(60);
+
+// gusts are the highest 3 second average speed seen in the last 10 minutes
+const GUST_DURATION: Duration = Duration::from_secs(3);
+const GUST_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Wind {
+    pub speed: f32,
+    pub gust: f32
+}
+
+/// a sensor of the wind speed and gusts
+pub trait WindSensor {
+    fn new() -> Self where Self: Sized;
+    fn read_value(&mut self) -> Option<Wind>;
+}
+
+pub struct Anemometer<P: PulseSource> {
+    source: P,
+    clock: SharedClock,
+    pulses: PulseWindow,
+    calibration: f32
+}
+
+pub type AnemometerReal = Anemometer<GpioPulseSource>;
+
+impl <P: PulseSource> Anemometer<P> {
+    pub fn with_source(source: P) -> Self {
+        Self {
+            source,
+            clock: clock::system(),
+            pulses: PulseWindow::new(GUST_WINDOW),
+            calibration: DEFAULT_CALIBRATION
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// sets the wind speed (m/s) that corresponds to one pulse per second
+    pub fn with_calibration(mut self, calibration: f32) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    /// the average speed of the last minute and the highest 3 second average of the last 10 minutes, in m/s
+    pub fn measure(&mut self) -> Wind {
+        let now = self.clock.now();
+        self.pulses.update(&mut self.source, now);
+
+        let average_hz = self.pulses.count_since(now, AVERAGE_WINDOW) as f32 / AVERAGE_WINDOW.as_secs_f32();
+        let gust_hz = self.pulses.max_count_in(GUST_DURATION) as f32 / GUST_DURATION.as_secs_f32();
+
+        let speed = average_hz * self.calibration;
+        Wind {
+            speed,
+            gust: (gust_hz * self.calibration).max(speed)
+        }
+    }
+}
+
+impl AnemometerReal {
+    pub fn with_pin(pin: u8) -> Self {
+        Self::with_source(GpioPulseSource::new(pin, DEBOUNCE))
+    }
+}
+
+impl WindSensor for AnemometerReal {
+    fn new() -> Self {
+        Self::with_pin(ANEMOMETER_PIN)
+    }
+
+    fn read_value(&mut self) -> Option<Wind> {
+        Some(self.measure())
+    }
+}
+
+pub struct AnemometerSimulator {
+    anemometer: Anemometer<PulseSourceSimulator>,
+    rng: StdRng,
+    base_speed: f32
+}
+
+impl AnemometerSimulator {
+    pub fn with_simulation(simulation: &Simulation) -> Self {
+        Self {
+            anemometer: Anemometer::with_source(PulseSourceSimulator::with_clock(0.0, simulation.clock()))
+                .with_clock(simulation.clock()),
+            rng: simulation.rng("wind"),
+            base_speed: 0.0
+        }
+    }
+}
+
+impl WindSensor for AnemometerSimulator {
+    fn new() -> Self {
+        Self::with_simulation(&Simulation::unseeded())
+    }
+
+    fn read_value(&mut self) -> Option<Wind> {
+        // the wind speed wanders around randomly, with sporadic gusts
+        self.base_speed = (self.base_speed + (self.rng.gen::<f32>() - 0.5) * 2.0).clamp(0.0, 20.0);
+
+        let mut speed = self.base_speed;
+        if self.rng.gen::<f32>() < 0.05 {
+            speed += self.rng.gen::<f32>() * 10.0;
+        }
+
+        let anemometer = &mut self.anemometer;
+        anemometer.source.set_rate_hz(speed / anemometer.calibration);
+
+        Some(anemometer.measure())
+    }
+}
+
+/// plays back the readings of a recorded anemometer
+pub struct ReplayWindSensor {
+    cursor: ReplayCursor
+}
+
+impl ReplayWindSensor {
+    pub fn with_replay(replay: &Replay, sensor: &str) -> Self {
+        Self {
+            cursor: replay.cursor(sensor)
+        }
+    }
+}
+
+impl WindSensor for ReplayWindSensor {
+    fn new() -> Self {
+        Self::with_replay(&Replay::load(&ReplayConfig::default(), clock::system()), "wind")
+    }
+
+    fn read_value(&mut self) -> Option<Wind> {
+        match self.cursor.next_event() {
+            Some(Event::Wind(wind)) => wind,
+            _ => None
+        }
+    }
+}
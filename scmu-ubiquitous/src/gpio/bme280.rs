@@ -0,0 +1,136 @@
+use std::{thread, time};
+
+use rppal::i2c::I2c;
+
+use super::environment::{Environment, EnvironmentSensor};
+
+const DEFAULT_ADDRESS: u16 = 0x76;
+
+const REG_CALIB_00: u8 = 0x88;
+const REG_CALIB_26: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_DATA: u8 = 0xF7;
+
+// 1x oversampling for every measurement
+const CTRL_HUM: u8 = 0b001;
+// 1x oversampling for temperature and pressure, forced mode
+const CTRL_MEAS_FORCED: u8 = 0b001 << 5 | 0b001 << 2 | 0b01;
+
+/// trimming parameters stored in the sensor when it is manufactured
+pub struct Calibration {
+    t1: f64, t2: f64, t3: f64,
+    p1: f64, p2: f64, p3: f64, p4: f64, p5: f64, p6: f64, p7: f64, p8: f64, p9: f64,
+    h1: f64, h2: f64, h3: f64, h4: f64, h5: f64, h6: f64
+}
+
+impl Calibration {
+    /// parses the registers 0x88 to 0xA1 and 0xE1 to 0xE7
+    pub fn parse(tp: &[u8; 26], h: &[u8; 7]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+        let i16_at = |i: usize| i16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+
+        Self {
+            t1: u16_at(0), t2: i16_at(2), t3: i16_at(4),
+            p1: u16_at(6), p2: i16_at(8), p3: i16_at(10), p4: i16_at(12), p5: i16_at(14),
+            p6: i16_at(16), p7: i16_at(18), p8: i16_at(20), p9: i16_at(22),
+            h1: tp[25] as f64,
+            h2: i16::from_le_bytes([h[0], h[1]]) as f64,
+            h3: h[2] as f64,
+            h4: ((h[3] as i8 as i16) << 4 | (h[4] & 0x0F) as i16) as f64,
+            h5: ((h[5] as i8 as i16) << 4 | (h[4] >> 4) as i16) as f64,
+            h6: h[6] as i8 as f64
+        }
+    }
+
+    /// converts the raw readings with the floating point formulas of the datasheet
+    pub fn compensate(&self, adc_t: f64, adc_p: f64, adc_h: f64) -> Environment {
+        let c = self;
+
+        let var1 = (adc_t / 16384.0 - c.t1 / 1024.0) * c.t2;
+        let var2 = (adc_t / 131072.0 - c.t1 / 8192.0).powi(2) * c.t3;
+        let t_fine = var1 + var2;
+        let temperature = t_fine / 5120.0;
+
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * c.p6 / 32768.0;
+        var2 += var1 * c.p5 * 2.0;
+        var2 = var2 / 4.0 + c.p4 * 65536.0;
+        var1 = (c.p3 * var1 * var1 / 524288.0 + c.p2 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * c.p1;
+
+        let pressure = if var1 == 0.0 {
+            None
+        } else {
+            let mut p = 1048576.0 - adc_p;
+            p = (p - var2 / 4096.0) * 6250.0 / var1;
+            let var1 = c.p9 * p * p / 2147483648.0;
+            let var2 = p * c.p8 / 32768.0;
+            Some((p + (var1 + var2 + c.p7) / 16.0) / 100.0)
+        };
+
+        let var_h = t_fine - 76800.0;
+        let var_h = (adc_h - (c.h4 * 64.0 + c.h5 / 16384.0 * var_h))
+            * (c.h2 / 65536.0 * (1.0 + c.h6 / 67108864.0 * var_h * (1.0 + c.h3 / 67108864.0 * var_h)));
+        let humidity = var_h * (1.0 - c.h1 * var_h / 524288.0);
+
+        Environment {
+            temperature: temperature as f32,
+            humidity: humidity.clamp(0.0, 100.0) as f32,
+            pressure: pressure.map(|p| p as f32)
+        }
+    }
+}
+
+pub struct Bme280 {
+    i2c: I2c,
+    calibration: Calibration
+}
+
+// implemented according to https://www.bosch-sensortec.com/media/boschsensortec/downloads/datasheets/bst-bme280-ds002.pdf
+impl Bme280 {
+    pub fn with_address(address: u16) -> Self {
+        let mut i2c = I2c::new()
+            .unwrap();
+
+        i2c.set_slave_address(address)
+            .unwrap();
+
+        let mut tp = [0; 26];
+        i2c.write_read(&[REG_CALIB_00], &mut tp)
+            .unwrap();
+
+        let mut h = [0; 7];
+        i2c.write_read(&[REG_CALIB_26], &mut h)
+            .unwrap();
+
+        // changes to ctrl_hum only become effective after writing ctrl_meas
+        i2c.smbus_write_byte(REG_CTRL_HUM, CTRL_HUM)
+            .unwrap();
+
+        Self {
+            i2c,
+            calibration: Calibration::parse(&tp, &h)
+        }
+    }
+}
+
+impl EnvironmentSensor for Bme280 {
+    fn new() -> Self {
+        Self::with_address(DEFAULT_ADDRESS)
+    }
+
+    fn read_value(&mut self) -> Option<Environment> {
+        self.i2c.smbus_write_byte(REG_CTRL_MEAS, CTRL_MEAS_FORCED).ok()?;
+        thread::sleep(time::Duration::from_millis(10));
+
+        let mut buf = [0; 8];
+        self.i2c.write_read(&[REG_DATA], &mut buf).ok()?;
+
+        let adc_p = (buf[0] as u32) << 12 | (buf[1] as u32) << 4 | (buf[2] as u32) >> 4;
+        let adc_t = (buf[3] as u32) << 12 | (buf[4] as u32) << 4 | (buf[5] as u32) >> 4;
+        let adc_h = (buf[6] as u32) << 8 | buf[7] as u32;
+
+        Some(self.calibration.compensate(adc_t as f64, adc_p as f64, adc_h as f64))
+    }
+}
@@ -0,0 +1,96 @@
+use std::time;
+
+use rand::{rngs::StdRng, Rng};
+use serde::{Serialize, Deserialize};
+
+use crate::{clock::{self, SharedClock}, config::ReplayConfig};
+
+use super::{recording::{Event, Replay, ReplayCursor}, simulation::Simulation};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Co2 {
+    /// parts per million
+    pub concentration: u16
+}
+
+/// a sensor of the co2 concentration
+pub trait Co2Sensor {
+    fn new() -> Self where Self: Sized;
+    fn read_value(&mut self) -> Option<Co2>;
+
+    // calibrates the sensor against a known reference concentration (e.g. ~420 ppm outdoors)
+    fn force_recalibration(&mut self, reference: u16) -> Option<()>;
+}
+
+pub struct Co2SensorSimulator {
+    rng: StdRng,
+    clock: SharedClock,
+    concentration: f32
+}
+
+impl Co2SensorSimulator {
+    pub fn with_simulation(simulation: &Simulation) -> Self {
+        Self {
+            rng: simulation.rng("co2"),
+            clock: simulation.clock(),
+            concentration: 600.0
+        }
+    }
+}
+
+impl Co2Sensor for Co2SensorSimulator {
+    fn new() -> Self {
+        Self::with_simulation(&Simulation::unseeded())
+    }
+
+    fn read_value(&mut self) -> Option<Co2> {
+        self.clock.sleep(time::Duration::from_millis(100));
+
+        // occupants slowly raise the concentration, with random drops when a door is opened
+        self.concentration += self.rng.gen::<f32>() * 40.0 - 10.0;
+        if self.rng.gen::<f32>() < 0.05 {
+            self.concentration -= self.rng.gen::<f32>() * 500.0;
+        }
+
+        self.concentration = self.concentration.clamp(400.0, 3000.0);
+        Some(Co2 {
+            concentration: self.concentration as u16
+        })
+    }
+
+    fn force_recalibration(&mut self, reference: u16) -> Option<()> {
+        self.concentration = reference as f32;
+        Some(())
+    }
+}
+
+/// plays back the readings of a recorded co2 sensor
+pub struct ReplayCo2Sensor {
+    cursor: ReplayCursor
+}
+
+impl ReplayCo2Sensor {
+    pub fn with_replay(replay: &Replay, sensor: &str) -> Self {
+        Self {
+            cursor: replay.cursor(sensor)
+        }
+    }
+}
+
+impl Co2Sensor for ReplayCo2Sensor {
+    fn new() -> Self {
+        Self::with_replay(&Replay::load(&ReplayConfig::default(), clock::system()), "co2")
+    }
+
+    fn read_value(&mut self) -> Option<Co2> {
+        match self.cursor.next_event() {
+            Some(Event::Co2(co2)) => co2,
+            _ => None
+        }
+    }
+
+    // the recording already holds the readings after any recalibration
+    fn force_recalibration(&mut self, _reference: u16) -> Option<()> {
+        None
+    }
+}
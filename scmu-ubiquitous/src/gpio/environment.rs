@@ -0,0 +1,95 @@
+use std::time;
+
+use rand::{rngs::StdRng, Rng};
+use serde::{Serialize, Deserialize};
+
+use crate::{clock::{self, SharedClock}, config::ReplayConfig};
+
+use super::{recording::{Event, Replay, ReplayCursor}, simulation::Simulation, Location};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Environment {
+    /// degrees celsius
+    pub temperature: f32,
+    /// relative humidity (%)
+    pub humidity: f32,
+    /// hPa, only available on sensors with a barometer
+    pub pressure: Option<f32>
+}
+
+/// a sensor of temperature and humidity, and possibly pressure
+pub trait EnvironmentSensor {
+    fn new() -> Self where Self: Sized;
+    fn read_value(&mut self) -> Option<Environment>;
+}
+
+pub struct EnvironmentSensorSimulator {
+    rng: StdRng,
+    clock: SharedClock,
+    temperature: f32,
+    humidity: f32,
+    pressure: f32
+}
+
+impl EnvironmentSensorSimulator {
+    pub fn with_simulation(simulation: &Simulation, location: Location) -> Self {
+        let name = match location {
+            Location::Outdoor => "outdoor_environment",
+            Location::Indoor => "indoor_environment"
+        };
+
+        Self {
+            rng: simulation.rng(name),
+            clock: simulation.clock(),
+            temperature: 18.0,
+            humidity: 60.0,
+            pressure: 1013.25
+        }
+    }
+}
+
+impl EnvironmentSensor for EnvironmentSensorSimulator {
+    fn new() -> Self {
+        Self::with_simulation(&Simulation::unseeded(), Location::Outdoor)
+    }
+
+    fn read_value(&mut self) -> Option<Environment> {
+        self.clock.sleep(time::Duration::from_millis(100));
+
+        self.temperature = (self.temperature + (self.rng.gen::<f32>() - 0.5) * 0.5).clamp(-10.0, 40.0);
+        self.humidity = (self.humidity + (self.rng.gen::<f32>() - 0.5) * 4.0).clamp(20.0, 100.0);
+        self.pressure = (self.pressure + (self.rng.gen::<f32>() - 0.5) * 0.4).clamp(950.0, 1050.0);
+
+        Some(Environment {
+            temperature: self.temperature,
+            humidity: self.humidity,
+            pressure: Some(self.pressure)
+        })
+    }
+}
+
+/// plays back the readings of a recorded environment sensor
+pub struct ReplayEnvironmentSensor {
+    cursor: ReplayCursor
+}
+
+impl ReplayEnvironmentSensor {
+    pub fn with_replay(replay: &Replay, sensor: &str) -> Self {
+        Self {
+            cursor: replay.cursor(sensor)
+        }
+    }
+}
+
+impl EnvironmentSensor for ReplayEnvironmentSensor {
+    fn new() -> Self {
+        Self::with_replay(&Replay::load(&ReplayConfig::default(), clock::system()), "outdoor_environment")
+    }
+
+    fn read_value(&mut self) -> Option<Environment> {
+        match self.cursor.next_event() {
+            Some(Event::Environment(environment)) => environment,
+            _ => None
+        }
+    }
+}
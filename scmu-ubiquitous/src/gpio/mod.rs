@@ -1,3 +1,26 @@
+pub mod anemometer;
+pub mod bme280;
+pub mod co2;
+pub mod environment;
 pub mod particle_matter;
+pub mod pulse;
 pub mod rain;
-// pub mod motor;
\ No newline at end of file
+pub mod rain_gauge;
+pub mod recording;
+pub mod scd30;
+pub mod scd4x;
+pub mod sensirion;
+pub mod sht3x;
+pub mod simulation;
+pub mod status_led;
+// pub mod motor;
+
+use serde::Deserialize;
+
+/// where a sensor is installed, relative to the window
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Location {
+    Indoor,
+    Outdoor
+}
\ No newline at end of file
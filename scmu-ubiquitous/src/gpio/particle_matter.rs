@@ -1,33 +1,82 @@
 use core::time;
-use std::thread;
+use std::{f32::consts::PI, thread, time::UNIX_EPOCH};
 
-use rand::random;
+use rand::{rngs::StdRng, Rng};
 use rppal::uart::Uart;
 
+use serde::{Serialize, Deserialize};
+
+use crate::{clock::{self, SharedClock}, config::ReplayConfig, health::Fault};
+
+use super::{recording::{Direction, Event, Recorder, Replay, ReplayCursor}, simulation::Simulation, Location};
+
+/// mass concentrations in µg/m³
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct ParticleMatter {
     pub pm_25_level: u16,
     pub pm_10_level: u16
 }
 
+// hygroscopic growth parameter of urban aerosols, see https://doi.org/10.5194/amt-11-709-2018
+const KAPPA: f32 = 0.4;
+
+impl ParticleMatter {
+    /// optical sensors also count the water absorbed by the particles, which inflates the
+    /// readings when the air is humid, so the mass concentration is corrected with k-Köhler theory
+    pub fn compensate_humidity(&self, humidity: f32) -> ParticleMatter {
+        if humidity <= 60.0 {
+            return *self
+        }
+
+        let water_activity = humidity.min(95.0) / 100.0;
+        let growth = 1.0 + (KAPPA / 1.65) / (1.0 / water_activity - 1.0);
+
+        ParticleMatter {
+            pm_25_level: (self.pm_25_level as f32 / growth).round() as u16,
+            pm_10_level: (self.pm_10_level as f32 / growth).round() as u16
+        }
+    }
+}
+
+/// a particle matter sensor, such as the sps30
 pub trait ParticleMatterSensor {
-    fn new() -> Self;
+    fn new() -> Self where Self: Sized;
     fn read_value(&mut self) -> Option<ParticleMatter>;
+
+    // problems reported by the sensor itself
+    fn device_faults(&mut self) -> Vec<Fault> {
+        Vec::new()
+    }
 }
 
+// bits of the device status register
+const STATUS_SPEED: u32 = 1 << 21;
+const STATUS_LASER: u32 = 1 << 5;
+const STATUS_FAN: u32 = 1 << 4;
+
 pub struct ParticleMatterSensorReal {
-    uart: Uart
+    uart: Uart,
+    recorder: Recorder,
+    name: String,
+    clock: SharedClock
 }
 
 // implemented according to https://sensirion.com/media/documents/8600FF88/616542B5/Sensirion_PM_Sensors_Datasheet_SPS30.pdf
-impl ParticleMatterSensor for ParticleMatterSensorReal {
-    fn new() -> Self {
-        let mut uart = Uart::new(
-            115200, 
-            rppal::uart::Parity::None, 
-            8, 
+impl ParticleMatterSensorReal {
+    /// used when more than one sensor is connected, e.g. through usb serial adapters
+    pub fn with_path(path: &str) -> Self {
+        let uart = Uart::with_path(
+            path,
+            115200,
+            rppal::uart::Parity::None,
+            8,
             1
         ).unwrap();
 
+        Self::init(uart)
+    }
+
+    fn init(mut uart: Uart) -> Self {
         uart.set_write_mode(true).unwrap();
         uart.set_read_mode(7, time::Duration::from_secs(0)).unwrap();
 
@@ -42,23 +91,62 @@ impl ParticleMatterSensor for ParticleMatterSensorReal {
             panic!()
         }
 
-        Self { uart }
+        Self {
+            uart,
+            recorder: Recorder::disabled(),
+            name: String::from("sps30"),
+            clock: clock::system()
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// records the shdlc frames exchanged with the sensor under the given name
+    pub fn with_recorder(mut self, recorder: &Recorder, name: &str) -> Self {
+        self.recorder = recorder.clone();
+        self.name = name.to_string();
+        self
+    }
+
+    // returns the number of bytes received, or None when the uart failed
+    fn exchange(&mut self, request: &[u8], response: &mut [u8]) -> Option<usize> {
+        self.recorder.record_frame(&self.name, Direction::Sent, request);
+        self.uart.write(request).ok()?;
+        self.uart.drain().ok()?;
+
+        let bytes_read = self.uart.read(response)
+            .ok()?;
+
+        self.recorder.record_frame(&self.name, Direction::Received, &response[..bytes_read]);
+        Some(bytes_read)
+    }
+}
+
+impl ParticleMatterSensor for ParticleMatterSensorReal {
+    fn new() -> Self {
+        let uart = Uart::new(
+            115200, 
+            rppal::uart::Parity::None, 
+            8, 
+            1
+        ).unwrap();
+
+        Self::init(uart)
     }
 
     fn read_value(&mut self) -> Option<ParticleMatter> {
-        thread::sleep(time::Duration::from_secs(1));
+        self.clock.sleep(time::Duration::from_secs(1));
             
         // Read Measured Values
-        self.uart.write(&[0x7E, 0x00, 0x03, 0x00, 0xFC, 0x7E]).unwrap();
-        self.uart.drain().unwrap();
-
         let mut buf: [u8; 27] = [0; 27];
-        let bytes_read = self.uart.read(&mut buf)
-            .unwrap();
+        let bytes_read = self.exchange(&[0x7E, 0x00, 0x03, 0x00, 0xFC, 0x7E], &mut buf);
 
         thread::sleep(time::Duration::from_millis(20));
 
-        if bytes_read < 27 {
+        if bytes_read? < 27 {
             return None
         }
 
@@ -70,21 +158,123 @@ impl ParticleMatterSensor for ParticleMatterSensorReal {
             pm_10_level: pm10
         })
     }
+
+    fn device_faults(&mut self) -> Vec<Fault> {
+        // Read Device Status Register, without clearing it
+        let mut buf: [u8; 12] = [0; 12];
+        let bytes_read = self.exchange(&[0x7E, 0x00, 0xD2, 0x01, 0x00, 0x2C, 0x7E], &mut buf);
+
+        thread::sleep(time::Duration::from_millis(20));
+
+        // a missing answer or a failed uart is already reported by the failed readings
+        if bytes_read.unwrap_or_default() < 12 || buf[3] != 0x00 {
+            return Vec::new()
+        }
+
+        let status = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]);
+        [(STATUS_SPEED, Fault::FanSpeed), (STATUS_LASER, Fault::LaserFailure), (STATUS_FAN, Fault::FanFailure)]
+            .into_iter()
+            .filter(|(bit, _)| status & bit != 0)
+            .map(|(_, fault)| fault)
+            .collect()
+    }
+}
+
+/// random walk around a baseline that follows the time of day, peaking in the evening
+pub struct ParticleMatterSensorSimulator {
+    simulation: Simulation,
+    location: Location,
+    rng: StdRng,
+    baseline: f32,
+    offset: f32,
+    // pm 10 to pm 2.5 ratio
+    coarse_ratio: f32
 }
 
-pub struct ParticleMatterSensorSimulator {}
+impl ParticleMatterSensorSimulator {
+    /// `index` tells apart the simulators installed at the same location
+    pub fn with_simulation(simulation: &Simulation, location: Location, index: usize) -> Self {
+        let (name, baseline) = match location {
+            Location::Outdoor => ("outdoor_pm", 15.0),
+            Location::Indoor => ("indoor_pm", 8.0)
+        };
+
+        Self {
+            simulation: simulation.clone(),
+            location,
+            rng: simulation.rng(&format!("{}_{}", name, index)),
+            baseline,
+            offset: 0.0,
+            coarse_ratio: 1.5
+        }
+    }
+
+    fn diurnal_factor(&self) -> f32 {
+        let seconds = self.simulation
+            .time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() % (24 * 60 * 60);
+
+        let hour = seconds as f32 / 3600.0;
+        1.0 + 0.4 * ((hour - 20.0) / 24.0 * 2.0 * PI).cos()
+    }
+}
 
 impl ParticleMatterSensor for ParticleMatterSensorSimulator {
     fn new() -> Self {
-        Self {}
+        Self::with_simulation(&Simulation::unseeded(), Location::Outdoor, 0)
     }
 
     fn read_value(&mut self) -> Option<ParticleMatter> {
-        thread::sleep(time::Duration::from_secs(1));
-        
-        Some(ParticleMatter {
-            pm_25_level: (random::<f32>() * 150.0).floor() as u16,
-            pm_10_level: (random::<f32>() * 150.0).floor() as u16
-        })
+        self.simulation.clock().sleep(time::Duration::from_secs(1));
+
+        // the offset slowly reverts to the baseline
+        self.offset = (self.offset + self.rng.gen_range(-2.0..2.0)) * 0.98;
+        self.coarse_ratio = (self.coarse_ratio + self.rng.gen_range(-0.05..0.05)).clamp(1.1, 2.5);
+
+        let pm_25 = (self.baseline * self.diurnal_factor() + self.offset).max(0.0);
+        let mut pm = ParticleMatter {
+            pm_25_level: pm_25.round() as u16,
+            pm_10_level: (pm_25 * self.coarse_ratio).round() as u16
+        };
+
+        if let Some(step) = self.simulation.scenario() {
+            let (pm_25, pm_10) = match self.location {
+                Location::Outdoor => (step.pm_25, step.pm_10),
+                Location::Indoor => (step.indoor_pm_25, step.indoor_pm_10)
+            };
+
+            pm.pm_25_level = pm_25.unwrap_or(pm.pm_25_level);
+            pm.pm_10_level = pm_10.unwrap_or(pm.pm_10_level);
+        }
+
+        Some(pm)
+    }
+}
+
+/// plays back the readings of a recorded particle matter sensor
+pub struct ReplayParticleMatterSensor {
+    cursor: ReplayCursor
+}
+
+impl ReplayParticleMatterSensor {
+    pub fn with_replay(replay: &Replay, sensor: &str) -> Self {
+        Self {
+            cursor: replay.cursor(sensor)
+        }
+    }
+}
+
+impl ParticleMatterSensor for ReplayParticleMatterSensor {
+    fn new() -> Self {
+        Self::with_replay(&Replay::load(&ReplayConfig::default(), clock::system()), "pm_0")
     }
-}
\ No newline at end of file
+
+    fn read_value(&mut self) -> Option<ParticleMatter> {
+        match self.cursor.next_event() {
+            Some(Event::ParticleMatter(pm)) => pm,
+            _ => None
+        }
+    }
+}
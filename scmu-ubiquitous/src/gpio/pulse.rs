@@ -0,0 +1,183 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use rppal::gpio::{Gpio, InputPin, Trigger};
+
+use crate::clock::{self, SharedClock};
+
+pub trait PulseSource {
+    fn take_pulses(&mut self) -> Vec<Instant>;
+}
+
+/// ignores the edges that follow an accepted one too closely, since reed switches bounce
+pub struct Debounce {
+    interval: Duration,
+    last_pulse: Option<Instant>
+}
+
+impl Debounce {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_pulse: None
+        }
+    }
+
+    /// whether the edge seen at `at` is a new pulse
+    pub fn accept(&mut self, at: Instant) -> bool {
+        if let Some(last) = self.last_pulse {
+            if at.duration_since(last) < self.interval {
+                return false
+            }
+        }
+
+        self.last_pulse = Some(at);
+        true
+    }
+}
+
+/// counts the falling edges of a reed switch connected between the pin and ground
+pub struct GpioPulseSource {
+    _pin: InputPin,
+    pulses: Arc<Mutex<Vec<Instant>>>
+}
+
+impl GpioPulseSource {
+    pub fn new(pin: u8, debounce: Duration) -> Self {
+        let gpio = Gpio::new()
+            .unwrap();
+
+        let mut pin = gpio.get(pin)
+            .unwrap()
+            .into_input_pullup();
+
+        let pulses = Arc::new(Mutex::new(Vec::new()));
+        let pulses_cln = pulses.clone();
+        let mut debounce = Debounce::new(debounce);
+
+        pin.set_async_interrupt(Trigger::FallingEdge, move |_| {
+            let now = Instant::now();
+            if !debounce.accept(now) {
+                return
+            }
+
+            pulses_cln.lock()
+                .unwrap()
+                .push(now);
+        }).unwrap();
+
+        Self {
+            _pin: pin,
+            pulses
+        }
+    }
+}
+
+impl PulseSource for GpioPulseSource {
+    fn take_pulses(&mut self) -> Vec<Instant> {
+        let mut pulses = self.pulses
+            .lock()
+            .unwrap();
+
+        std::mem::take(&mut *pulses)
+    }
+}
+
+/// emits evenly spaced pulses at a given frequency
+pub struct PulseSourceSimulator {
+    clock: SharedClock,
+    rate_hz: f32,
+    pending: f32,
+    last_update: Instant
+}
+
+impl PulseSourceSimulator {
+    pub fn new(rate_hz: f32) -> Self {
+        Self::with_clock(rate_hz, clock::system())
+    }
+
+    pub fn with_clock(rate_hz: f32, clock: SharedClock) -> Self {
+        Self {
+            rate_hz,
+            pending: 0.0,
+            last_update: clock.now(),
+            clock
+        }
+    }
+
+    pub fn rate_hz(&self) -> f32 {
+        self.rate_hz
+    }
+
+    pub fn set_rate_hz(&mut self, rate_hz: f32) {
+        self.rate_hz = rate_hz.max(0.0);
+    }
+}
+
+impl PulseSource for PulseSourceSimulator {
+    fn take_pulses(&mut self) -> Vec<Instant> {
+        let now = self.clock.now();
+        let elapsed = now.duration_since(self.last_update);
+
+        self.pending += self.rate_hz * elapsed.as_secs_f32();
+
+        let count = self.pending.floor() as u32;
+        self.pending -= count as f32;
+
+        let pulses = (1..=count)
+            .map(|i| self.last_update + elapsed.mul_f32(i as f32 / count as f32))
+            .collect();
+
+        self.last_update = now;
+        pulses
+    }
+}
+
+/// keeps the pulses received during the last `max_age`
+pub struct PulseWindow {
+    max_age: Duration,
+    pulses: VecDeque<Instant>
+}
+
+impl PulseWindow {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            pulses: VecDeque::new()
+        }
+    }
+
+    pub fn update<P: PulseSource>(&mut self, source: &mut P, now: Instant) {
+        self.pulses.extend(source.take_pulses());
+        while let Some(oldest) = self.pulses.front() {
+            if now.duration_since(*oldest) <= self.max_age {
+                break
+            }
+
+            self.pulses.pop_front();
+        }
+    }
+
+    pub fn count_since(&self, now: Instant, window: Duration) -> usize {
+        self.pulses
+            .iter()
+            .filter(|pulse| now.duration_since(**pulse) <= window)
+            .count()
+    }
+
+    /// highest number of pulses seen in any `window` long interval
+    pub fn max_count_in(&self, window: Duration) -> usize {
+        let pulses = &self.pulses;
+        let mut start = 0;
+        let mut max = 0;
+
+        for end in 0..pulses.len() {
+            while pulses[end].duration_since(pulses[start]) > window {
+                start += 1;
+            }
+
+            max = max.max(end - start + 1);
+        }
+
+        max
+    }
+}
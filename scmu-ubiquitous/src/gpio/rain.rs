@@ -1,14 +1,33 @@
-use std::{thread, time};
+use std::{thread, time::{self, Duration}};
 
-use rand::random;
+use rand::{rngs::StdRng, Rng};
 use rppal::gpio::{Gpio, InputPin, OutputPin};
+use serde::{Serialize, Deserialize};
 
+use crate::{clock, config::ReplayConfig};
+
+use super::{recording::{Event, Replay, ReplayCursor}, simulation::Simulation};
+
+// average length of the dry spells and of the rain episodes
+const MEAN_DRY_SPELL: Duration = Duration::from_secs(60 * 60);
+const MEAN_RAIN_EPISODE: Duration = Duration::from_secs(20 * 60);
+
+/// a reading of a rain sensor
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Rain {
-    pub is_raining: bool
+    pub is_raining: bool,
+    pub intensity: Option<RainIntensity>
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RainIntensity {
+    pub accumulated_mm: f32,
+    pub rate_mm_h: f32
+}
+
+/// anything that tells whether it is raining, and possibly how much
 pub trait RainSensor {
-    fn new() -> Self;
+    fn new() -> Self where Self: Sized;
     fn read_value(&mut self) -> Option<Rain>;
 }
 
@@ -24,7 +43,7 @@ impl RainSensor for RainSensorReal {
 
         let mut read_pin = gpio.get(20)
             .unwrap()
-            .into_input();
+            .into_input_pulldown();
 
         let mut vcc_pin = gpio.get(20)
             .unwrap()
@@ -39,28 +58,121 @@ impl RainSensor for RainSensorReal {
     }
 
     fn read_value(&mut self) -> Option<Rain> {
+        // unpowered, the module cannot drive the line, so a high line means it is shorted or miswired
+        if self.read_pin.is_high() {
+            return None
+        }
+
         self.vcc_pin.set_high();
         thread::sleep(time::Duration::from_millis(10));
         let is_raining = self.read_pin.is_high();
         self.vcc_pin.set_low();
 
         Some(Rain {
-            is_raining
+            is_raining,
+            intensity: None
         })
     }
 }
 
-pub struct RainSensorSimulator {}
+/// alternates dry spells and rain episodes with exponentially distributed durations,
+/// timed from the start of the simulation
+pub struct RainEpisodes {
+    rng: StdRng,
+    simulation: Simulation,
+    rate_mm_h: f32,
+    // simulated time of the next change
+    next_change: Duration
+}
+
+impl RainEpisodes {
+    pub fn new(mut rng: StdRng, simulation: &Simulation) -> Self {
+        let next_change = Self::duration(&mut rng, MEAN_DRY_SPELL);
+        Self {
+            rng,
+            simulation: simulation.clone(),
+            rate_mm_h: 0.0,
+            next_change
+        }
+    }
+
+    fn duration(rng: &mut StdRng, mean: Duration) -> Duration {
+        mean.mul_f32(-(1.0 - rng.gen::<f32>()).ln())
+    }
+
+    pub fn rate_mm_h(&mut self) -> f32 {
+        let elapsed = self.simulation.elapsed();
+        while self.next_change <= elapsed {
+            let mean = if self.rate_mm_h > 0.0 {
+                self.rate_mm_h = 0.0;
+                MEAN_DRY_SPELL
+            } else {
+                // mostly light rain, with the odd downpour
+                self.rate_mm_h = 0.5 + self.rng.gen::<f32>().powi(3) * 30.0;
+                MEAN_RAIN_EPISODE
+            };
+
+            self.next_change += Self::duration(&mut self.rng, mean);
+        }
+
+        self.rate_mm_h
+    }
+}
+
+pub struct RainSensorSimulator {
+    simulation: Simulation,
+    episodes: RainEpisodes
+}
+
+impl RainSensorSimulator {
+    pub fn with_simulation(simulation: &Simulation) -> Self {
+        Self {
+            simulation: simulation.clone(),
+            episodes: RainEpisodes::new(simulation.rng("rain"), simulation)
+        }
+    }
+}
 
 impl RainSensor for RainSensorSimulator {
     fn new() -> Self {
-        Self {}
+        Self::with_simulation(&Simulation::unseeded())
     }
 
     fn read_value(&mut self) -> Option<Rain> {
-        let val = random::<f32>() * 10.0;
+        let rate_mm_h = self.episodes.rate_mm_h();
+        let is_raining = self.simulation.scenario()
+            .and_then(|step| step.rain.or(step.rain_rate.map(|rate| rate > 0.0)))
+            .unwrap_or(rate_mm_h > 0.0);
+
         Some(Rain {
-            is_raining: val >= 7.0
+            is_raining,
+            intensity: None
         })
     }
-}
\ No newline at end of file
+}
+
+/// plays back the readings of a recorded rain sensor
+pub struct ReplayRainSensor {
+    cursor: ReplayCursor
+}
+
+impl ReplayRainSensor {
+    pub fn with_replay(replay: &Replay, sensor: &str) -> Self {
+        Self {
+            cursor: replay.cursor(sensor)
+        }
+    }
+}
+
+impl RainSensor for ReplayRainSensor {
+    fn new() -> Self {
+        Self::with_replay(&Replay::load(&ReplayConfig::default(), clock::system()), "rain")
+    }
+
+    fn read_value(&mut self) -> Option<Rain> {
+        match self.cursor.next_event() {
+            Some(Event::Rain(rain)) => rain,
+            _ => None
+        }
+    }
+}
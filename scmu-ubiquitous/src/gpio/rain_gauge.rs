@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use crate::clock::{self, SharedClock};
+
+use super::{
+    pulse::{GpioPulseSource, PulseSource, PulseSourceSimulator, PulseWindow},
+    rain::{Rain, RainEpisodes, RainIntensity, RainSensor},
+    simulation::Simulation
+};
+
+const GAUGE_PIN: u8 = 21;
+
+// each tip of the bucket corresponds to 0.01 in of rain
+const MM_PER_TIP: f32 = 0.2794;
+
+// reed switches bounce for a few milliseconds when the bucket tips
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+// window used to compute the accumulated rain
+const ACCUMULATION_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+// window used to compute the current rain rate
+const RATE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+pub struct TippingBucketRainSensor<P: PulseSource> {
+    source: P,
+    clock: SharedClock,
+    tips: PulseWindow
+}
+
+pub type TippingBucketRainSensorReal = TippingBucketRainSensor<GpioPulseSource>;
+
+impl <P: PulseSource> TippingBucketRainSensor<P> {
+    pub fn with_source(source: P) -> Self {
+        Self {
+            source,
+            clock: clock::system(),
+            tips: PulseWindow::new(ACCUMULATION_WINDOW)
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// the rain measured from the tips counted so far
+    pub fn measure(&mut self) -> Rain {
+        let now = self.clock.now();
+        self.tips.update(&mut self.source, now);
+
+        let accumulated_mm = self.tips.count_since(now, ACCUMULATION_WINDOW) as f32 * MM_PER_TIP;
+        let recent_tips = self.tips.count_since(now, RATE_WINDOW);
+        let rate_mm_h = recent_tips as f32 * MM_PER_TIP * (ACCUMULATION_WINDOW.as_secs_f32() / RATE_WINDOW.as_secs_f32());
+
+        Rain {
+            is_raining: recent_tips > 0,
+            intensity: Some(RainIntensity {
+                accumulated_mm,
+                rate_mm_h
+            })
+        }
+    }
+}
+
+impl RainSensor for TippingBucketRainSensorReal {
+    fn new() -> Self {
+        Self::with_source(GpioPulseSource::new(GAUGE_PIN, DEBOUNCE))
+    }
+
+    fn read_value(&mut self) -> Option<Rain> {
+        Some(self.measure())
+    }
+}
+
+// rain rate used when a scenario only says that it is raining
+const SCENARIO_RAIN_RATE: f32 = 2.0;
+
+pub struct TippingBucketRainSensorSimulator {
+    gauge: TippingBucketRainSensor<PulseSourceSimulator>,
+    simulation: Simulation,
+    episodes: Option<RainEpisodes>
+}
+
+impl TippingBucketRainSensorSimulator {
+    /// simulates a constant rain rate instead of random rain episodes
+    pub fn with_rate(rate_mm_h: f32) -> Self {
+        Self {
+            gauge: TippingBucketRainSensor::with_source(PulseSourceSimulator::new(rate_mm_h / MM_PER_TIP / 3600.0)),
+            simulation: Simulation::unseeded(),
+            episodes: None
+        }
+    }
+
+    pub fn with_simulation(simulation: &Simulation) -> Self {
+        Self {
+            gauge: TippingBucketRainSensor::with_source(PulseSourceSimulator::with_clock(0.0, simulation.clock()))
+                .with_clock(simulation.clock()),
+            simulation: simulation.clone(),
+            episodes: Some(RainEpisodes::new(simulation.rng("rain_gauge"), simulation))
+        }
+    }
+}
+
+impl RainSensor for TippingBucketRainSensorSimulator {
+    fn new() -> Self {
+        Self::with_simulation(&Simulation::unseeded())
+    }
+
+    fn read_value(&mut self) -> Option<Rain> {
+        let scenario_rate = self.simulation.scenario()
+            .and_then(|step| step.rain_rate.or(step.rain.map(|rain| if rain { SCENARIO_RAIN_RATE } else { 0.0 })));
+
+        let rate_mm_h = scenario_rate.or(self.episodes.as_mut().map(RainEpisodes::rate_mm_h));
+        if let Some(rate_mm_h) = rate_mm_h {
+            self.gauge.source.set_rate_hz(rate_mm_h / MM_PER_TIP / 3600.0);
+        }
+
+        Some(self.gauge.measure())
+    }
+}
@@ -0,0 +1,201 @@
+use std::{fs::{self, File, OpenOptions}, io::{BufWriter, Write}, sync::{Arc, Mutex}, time::{Duration, Instant, UNIX_EPOCH}};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{clock::{self, SharedClock}, config::{RecordingConfig, ReplayConfig}};
+
+use super::{anemometer::Wind, co2::Co2, environment::Environment, particle_matter::ParticleMatter, rain::Rain};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    Rain(Option<Rain>),
+    ParticleMatter(Option<ParticleMatter>),
+    Wind(Option<Wind>),
+    Environment(Option<Environment>),
+    Co2(Option<Co2>),
+    // raw frames exchanged with a sensor, hex encoded
+    Frame { direction: Direction, bytes: String }
+}
+
+/// one line of the recording file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// unix time in milliseconds
+    pub timestamp_ms: u64,
+    pub sensor: String,
+    #[serde(flatten)]
+    pub event: Event
+}
+
+/// writes every raw reading to a json lines file, does nothing when recording is disabled
+#[derive(Clone)]
+pub struct Recorder {
+    file: Option<Arc<Mutex<BufWriter<File>>>>,
+    clock: SharedClock
+}
+
+impl Recorder {
+    pub fn new(config: &RecordingConfig, clock: SharedClock) -> Self {
+        let file = config.path.as_ref().map(|path| {
+            // appends, so that restarting the device does not lose the earlier readings
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap_or_else(|err| panic!("Unable to open recording {}: {}", path, err));
+
+            println!("Recording sensor readings to {}", path);
+            Arc::new(Mutex::new(BufWriter::new(file)))
+        });
+
+        Self { file, clock }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            file: None,
+            clock: clock::system()
+        }
+    }
+
+    pub fn record(&self, sensor: &str, event: Event) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return
+        };
+
+        let entry = Entry {
+            timestamp_ms: self.clock
+                .system_time()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            sensor: sensor.to_string(),
+            event
+        };
+
+        let line = serde_json::to_string(&entry)
+            .unwrap();
+
+        let mut file = file.lock().unwrap();
+        if let Err(err) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            println!("Failed to record {} reading: {}", sensor, err);
+        }
+    }
+
+    /// records a reading as it comes from the sensor and passes it through
+    pub fn reading<T: Clone>(&self, sensor: &str, reading: Option<T>, event: fn(Option<T>) -> Event) -> Option<T> {
+        if self.file.is_some() {
+            self.record(sensor, event(reading.clone()));
+        }
+
+        reading
+    }
+
+    pub fn record_frame(&self, sensor: &str, direction: Direction, bytes: &[u8]) {
+        if self.file.is_some() {
+            let bytes = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            self.record(sensor, Event::Frame { direction, bytes });
+        }
+    }
+}
+
+/// a recording shared by the replay sensors, which all start at the same time
+#[derive(Clone)]
+pub struct Replay {
+    entries: Arc<Vec<Entry>>,
+    speed: f32,
+    clock: SharedClock,
+    start: Instant
+}
+
+impl Replay {
+    pub fn load(config: &ReplayConfig, clock: SharedClock) -> Self {
+        let contents = fs::read_to_string(&config.path)
+            .unwrap_or_else(|err| panic!("Unable to read recording {}: {}", config.path, err));
+
+        let entries: Vec<Entry> = contents.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line)
+                .unwrap_or_else(|err| panic!("Invalid recording {}: {}", config.path, err)))
+            .collect();
+
+        println!("Replaying {} entries from {} at {}x", entries.len(), config.path, config.speed);
+        Self {
+            entries: Arc::new(entries),
+            speed: config.speed,
+            start: clock.now(),
+            clock
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn cursor(&self, sensor: &str) -> ReplayCursor {
+        ReplayCursor {
+            replay: self.clone(),
+            sensor: sensor.to_string(),
+            next: 0,
+            last: None
+        }
+    }
+}
+
+/// plays back the readings of a single sensor
+pub struct ReplayCursor {
+    replay: Replay,
+    sensor: String,
+    next: usize,
+    last: Option<Event>
+}
+
+impl ReplayCursor {
+    /// waits until the next reading is due and returns it, the last reading is repeated once the recording ends;
+    /// when the readings are taken slower than they were recorded, the ones already due are skipped
+    pub fn next_event(&mut self) -> Option<Event> {
+        let mut index = match self.find_next(self.next) {
+            Some(index) => index,
+            None => return self.last.clone()
+        };
+
+        let elapsed = self.replay.clock.now().duration_since(self.replay.start);
+        while let Some(later) = self.find_next(index + 1) {
+            if self.due(later) > elapsed {
+                break
+            }
+
+            index = later;
+        }
+
+        if let Some(wait) = self.due(index).checked_sub(elapsed) {
+            self.replay.clock.sleep(wait);
+        }
+
+        self.next = index + 1;
+        self.last = Some(self.replay.entries[index].event.clone());
+        self.last.clone()
+    }
+
+    // index of the next reading of the sensor, starting at `from`
+    fn find_next(&self, from: usize) -> Option<usize> {
+        self.replay.entries[from..].iter()
+            .position(|entry| entry.sensor == self.sensor && !matches!(entry.event, Event::Frame { .. }))
+            .map(|position| from + position)
+    }
+
+    // time since the start of the replay at which an entry is played back
+    fn due(&self, index: usize) -> Duration {
+        let first = self.replay.entries[0].timestamp_ms;
+        Duration::from_millis(self.replay.entries[index].timestamp_ms - first).div_f32(self.replay.speed)
+    }
+}
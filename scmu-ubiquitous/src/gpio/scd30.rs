@@ -0,0 +1,72 @@
+use std::{thread, time};
+
+use rppal::i2c::I2c;
+
+use super::{co2::{Co2, Co2Sensor}, sensirion};
+
+const ADDRESS: u16 = 0x61;
+
+const CMD_START_CONTINUOUS_MEASUREMENT: u16 = 0x0010;
+const CMD_SET_MEASUREMENT_INTERVAL: u16 = 0x4600;
+const CMD_GET_DATA_READY: u16 = 0x0202;
+const CMD_READ_MEASUREMENT: u16 = 0x0300;
+const CMD_FORCED_RECALIBRATION: u16 = 0x5204;
+
+const MEASUREMENT_INTERVAL_SECS: u16 = 2;
+
+pub struct Scd30 {
+    i2c: I2c,
+    // measurements are only produced every few seconds
+    last_concentration: Option<u16>
+}
+
+// implemented according to https://sensirion.com/media/documents/D7CEEF4A/6165372F/Sensirion_CO2_Sensors_SCD30_Interface_Description.pdf
+impl Scd30 {
+    fn read_response(&mut self, command: u16, words: usize) -> Option<Vec<u16>> {
+        sensirion::write_command(&mut self.i2c, command, &[])?;
+        thread::sleep(time::Duration::from_millis(3));
+        sensirion::read_words(&mut self.i2c, words)
+    }
+}
+
+impl Co2Sensor for Scd30 {
+    fn new() -> Self {
+        let mut i2c = I2c::new()
+            .unwrap();
+
+        i2c.set_slave_address(ADDRESS)
+            .unwrap();
+
+        sensirion::write_command(&mut i2c, CMD_SET_MEASUREMENT_INTERVAL, &[MEASUREMENT_INTERVAL_SECS])
+            .unwrap();
+
+        // without ambient pressure compensation
+        sensirion::write_command(&mut i2c, CMD_START_CONTINUOUS_MEASUREMENT, &[0])
+            .unwrap();
+
+        Self {
+            i2c,
+            last_concentration: None
+        }
+    }
+
+    fn read_value(&mut self) -> Option<Co2> {
+        let ready = self.read_response(CMD_GET_DATA_READY, 1)?;
+        if ready[0] != 1 {
+            return self.last_concentration.map(|concentration| Co2 { concentration })
+        }
+
+        // co2, temperature and humidity are sent as big-endian floats split in two words
+        let words = self.read_response(CMD_READ_MEASUREMENT, 6)?;
+        let concentration = f32::from_bits((words[0] as u32) << 16 | words[1] as u32)
+            .round() as u16;
+
+        self.last_concentration = Some(concentration);
+
+        Some(Co2 { concentration })
+    }
+
+    fn force_recalibration(&mut self, reference: u16) -> Option<()> {
+        sensirion::write_command(&mut self.i2c, CMD_FORCED_RECALIBRATION, &[reference])
+    }
+}
@@ -0,0 +1,81 @@
+use std::{thread, time};
+
+use rppal::i2c::I2c;
+
+use super::{co2::{Co2, Co2Sensor}, sensirion};
+
+const ADDRESS: u16 = 0x62;
+
+const CMD_START_PERIODIC_MEASUREMENT: u16 = 0x21B1;
+const CMD_STOP_PERIODIC_MEASUREMENT: u16 = 0x3F86;
+const CMD_GET_DATA_READY_STATUS: u16 = 0xE4B8;
+const CMD_READ_MEASUREMENT: u16 = 0xEC05;
+const CMD_PERFORM_FORCED_RECALIBRATION: u16 = 0x362F;
+
+// returned by the sensor when the forced recalibration fails
+const FRC_FAILED: u16 = 0xFFFF;
+
+pub struct Scd4x {
+    i2c: I2c,
+    // measurements are only produced every few seconds
+    last_concentration: Option<u16>
+}
+
+// implemented according to https://sensirion.com/media/documents/E0F04247/631EF271/CD_DS_SCD40_SCD41_Datasheet_D1.pdf
+impl Scd4x {
+    fn read_response(&mut self, command: u16, delay: time::Duration, words: usize) -> Option<Vec<u16>> {
+        sensirion::write_command(&mut self.i2c, command, &[])?;
+        thread::sleep(delay);
+        sensirion::read_words(&mut self.i2c, words)
+    }
+}
+
+impl Co2Sensor for Scd4x {
+    fn new() -> Self {
+        let mut i2c = I2c::new()
+            .unwrap();
+
+        i2c.set_slave_address(ADDRESS)
+            .unwrap();
+
+        sensirion::write_command(&mut i2c, CMD_START_PERIODIC_MEASUREMENT, &[])
+            .unwrap();
+
+        Self {
+            i2c,
+            last_concentration: None
+        }
+    }
+
+    fn read_value(&mut self) -> Option<Co2> {
+        let status = self.read_response(CMD_GET_DATA_READY_STATUS, time::Duration::from_millis(1), 1)?;
+        if status[0] & 0x07FF == 0 {
+            return self.last_concentration.map(|concentration| Co2 { concentration })
+        }
+
+        let words = self.read_response(CMD_READ_MEASUREMENT, time::Duration::from_millis(1), 3)?;
+
+        self.last_concentration = Some(words[0]);
+
+        Some(Co2 {
+            concentration: words[0]
+        })
+    }
+
+    fn force_recalibration(&mut self, reference: u16) -> Option<()> {
+        // the recalibration can only be performed while the sensor is idle
+        sensirion::write_command(&mut self.i2c, CMD_STOP_PERIODIC_MEASUREMENT, &[])?;
+        thread::sleep(time::Duration::from_millis(500));
+
+        sensirion::write_command(&mut self.i2c, CMD_PERFORM_FORCED_RECALIBRATION, &[reference])?;
+        thread::sleep(time::Duration::from_millis(400));
+        let correction = sensirion::read_words(&mut self.i2c, 1);
+
+        sensirion::write_command(&mut self.i2c, CMD_START_PERIODIC_MEASUREMENT, &[])?;
+
+        match correction?[0] {
+            FRC_FAILED => None,
+            _ => Some(())
+        }
+    }
+}
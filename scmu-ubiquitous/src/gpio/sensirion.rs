@@ -0,0 +1,50 @@
+use rppal::i2c::I2c;
+
+/// CRC-8 used by Sensirion sensors to protect every 16-bit word (polynomial 0x31, init 0xFF)
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0xFF;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+
+    crc
+}
+
+/// reads `count` words, each one followed by its checksum
+pub fn read_words(i2c: &mut I2c, count: usize) -> Option<Vec<u16>> {
+    let mut buf = vec![0; count * 3];
+    let bytes_read = i2c.read(&mut buf).ok()?;
+    if bytes_read != buf.len() {
+        return None
+    }
+
+    buf.chunks(3)
+        .map(|chunk| {
+            if crc8(&chunk[..2]) != chunk[2] {
+                return None
+            }
+
+            Some((chunk[0] as u16) << 8 | chunk[1] as u16)
+        })
+        .collect()
+}
+
+/// sends a 16-bit command followed by its arguments, each one with its checksum
+pub fn write_command(i2c: &mut I2c, command: u16, args: &[u16]) -> Option<()> {
+    let mut buf = command.to_be_bytes().to_vec();
+    for arg in args {
+        let bytes = arg.to_be_bytes();
+        buf.extend_from_slice(&bytes);
+        buf.push(crc8(&bytes));
+    }
+
+    let bytes_written = i2c.write(&buf).ok()?;
+    if bytes_written != buf.len() {
+        return None
+    }
+
+    Some(())
+}
@@ -0,0 +1,46 @@
+use std::{thread, time};
+
+use rppal::i2c::I2c;
+
+use super::{environment::{Environment, EnvironmentSensor}, sensirion};
+
+const DEFAULT_ADDRESS: u16 = 0x44;
+
+// single shot measurement, high repeatability, without clock stretching
+const MEASURE_HIGH_REPEATABILITY: [u8; 2] = [0x24, 0x00];
+
+pub struct Sht3x {
+    i2c: I2c
+}
+
+// implemented according to https://sensirion.com/media/documents/213E6A3B/63A5A569/Datasheet_SHT3x_DIS.pdf
+impl Sht3x {
+    pub fn with_address(address: u16) -> Self {
+        let mut i2c = I2c::new()
+            .unwrap();
+
+        i2c.set_slave_address(address)
+            .unwrap();
+
+        Self { i2c }
+    }
+}
+
+impl EnvironmentSensor for Sht3x {
+    fn new() -> Self {
+        Self::with_address(DEFAULT_ADDRESS)
+    }
+
+    fn read_value(&mut self) -> Option<Environment> {
+        self.i2c.write(&MEASURE_HIGH_REPEATABILITY).ok()?;
+        thread::sleep(time::Duration::from_millis(16));
+
+        let words = sensirion::read_words(&mut self.i2c, 2)?;
+
+        Some(Environment {
+            temperature: -45.0 + 175.0 * words[0] as f32 / 65535.0,
+            humidity: 100.0 * words[1] as f32 / 65535.0,
+            pressure: None
+        })
+    }
+}
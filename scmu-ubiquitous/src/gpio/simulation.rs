@@ -0,0 +1,125 @@
+use std::{fs, sync::Arc, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use rand::{random, rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+
+use crate::{clock::{self, SharedClock}, config::SimulationConfig};
+
+// simulated runs start on 2024-01-01 00:00 utc, at a time of day picked by the seed,
+// so that the simulated values do not depend on when the run started
+const EPOCH_SECS: u64 = 1_704_067_200;
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// values of the simulated sensors from a point of the scenario onwards,
+/// values that are not given are kept from the previous steps
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ScenarioStep {
+    /// seconds since the start of the scenario
+    pub at_secs: u64,
+    pub rain: Option<bool>,
+    pub rain_rate: Option<f32>,
+    pub pm_25: Option<u16>,
+    pub pm_10: Option<u16>,
+    pub indoor_pm_25: Option<u16>,
+    pub indoor_pm_10: Option<u16>
+}
+
+#[derive(Deserialize)]
+pub struct Scenario {
+    /// the scenario starts over after this many seconds, otherwise the last values are kept
+    pub repeat_after_secs: Option<u64>,
+    pub steps: Vec<ScenarioStep>
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Self {
+        let contents = fs::read_to_string(path)
+            .unwrap_or_else(|err| panic!("Unable to read scenario {}: {}", path, err));
+
+        let mut scenario: Scenario = serde_json::from_str(&contents)
+            .unwrap_or_else(|err| panic!("Invalid scenario {}: {}", path, err));
+
+        scenario.steps.sort_by_key(|step| step.at_secs);
+        scenario
+    }
+
+    pub fn at(&self, elapsed_secs: u64) -> ScenarioStep {
+        let elapsed_secs = match self.repeat_after_secs {
+            Some(period) if period > 0 => elapsed_secs % period,
+            _ => elapsed_secs
+        };
+
+        self.steps.iter()
+            .take_while(|step| step.at_secs <= elapsed_secs)
+            .fold(ScenarioStep::default(), |state, step| ScenarioStep {
+                at_secs: step.at_secs,
+                rain: step.rain.or(state.rain),
+                rain_rate: step.rain_rate.or(state.rain_rate),
+                pm_25: step.pm_25.or(state.pm_25),
+                pm_10: step.pm_10.or(state.pm_10),
+                indoor_pm_25: step.indoor_pm_25.or(state.indoor_pm_25),
+                indoor_pm_10: step.indoor_pm_10.or(state.indoor_pm_10)
+            })
+    }
+}
+
+/// shared by every simulator, so that a run can be reproduced from its seed and scenario
+#[derive(Clone)]
+pub struct Simulation {
+    seed: u64,
+    scenario: Option<Arc<Scenario>>,
+    clock: SharedClock,
+    start: Instant
+}
+
+impl Simulation {
+    pub fn new(config: &SimulationConfig, clock: SharedClock) -> Self {
+        let seed = config.seed.unwrap_or_else(random);
+        println!("Simulation seed: {}", seed);
+
+        Self {
+            seed,
+            scenario: config.scenario.as_deref().map(|path| Arc::new(Scenario::load(path))),
+            start: clock.now(),
+            clock
+        }
+    }
+
+    /// used by simulators that are created without a configuration
+    pub fn unseeded() -> Self {
+        let clock = clock::system();
+        Self {
+            seed: random(),
+            scenario: None,
+            start: clock.now(),
+            clock
+        }
+    }
+
+    pub fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    /// every simulator gets its own generator, so adding a sensor does not change the others
+    pub fn rng(&self, name: &str) -> StdRng {
+        // fnv-1a, since the hashers of the standard library are not guaranteed to be stable
+        let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+        StdRng::seed_from_u64(self.seed ^ hash)
+    }
+
+    /// time simulated since the start of the run
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now().duration_since(self.start)
+    }
+
+    /// wall clock time of the simulated world, derived from the seed rather than the system clock
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(EPOCH_SECS + self.seed % DAY_SECS) + self.elapsed()
+    }
+
+    pub fn scenario(&self) -> Option<ScenarioStep> {
+        self.scenario.as_ref()
+            .map(|scenario| scenario.at(self.elapsed().as_secs()))
+    }
+}
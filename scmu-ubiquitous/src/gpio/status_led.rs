@@ -0,0 +1,91 @@
+use std::{sync::{mpsc::{self, Receiver, RecvTimeoutError, Sender}, Mutex}, thread, time::Duration};
+
+use rppal::gpio::{Gpio, OutputPin};
+
+use crate::util::{Result, ToErrString};
+
+const BLINK: Duration = Duration::from_millis(250);
+const DIGIT_PAUSE: Duration = Duration::from_millis(1000);
+const REPEAT_PAUSE: Duration = Duration::from_millis(3000);
+
+enum Command {
+    Digits(Vec<u8>),
+    Off
+}
+
+/// led that can blink numbers, one burst of blinks per digit (ten for a zero)
+pub struct StatusLed {
+    commands: Mutex<Sender<Command>>
+}
+
+// keeps the led as it is for a while, stopping early with the next command,
+// or with None once the led has been dropped
+fn pause(commands: &Receiver<Command>, duration: Duration) -> std::result::Result<(), Option<Command>> {
+    match commands.recv_timeout(duration) {
+        Ok(command) => Err(Some(command)),
+        Err(RecvTimeoutError::Timeout) => Ok(()),
+        Err(RecvTimeoutError::Disconnected) => Err(None)
+    }
+}
+
+fn blink_digits(pin: &mut OutputPin, commands: &Receiver<Command>, digits: &[u8]) -> std::result::Result<(), Option<Command>> {
+    for digit in digits {
+        let blinks = if *digit == 0 { 10 } else { *digit };
+        for _ in 0..blinks {
+            pin.set_high();
+            pause(commands, BLINK)?;
+            pin.set_low();
+            pause(commands, BLINK)?;
+        }
+
+        pause(commands, DIGIT_PAUSE)?;
+    }
+
+    pause(commands, REPEAT_PAUSE)
+}
+
+impl StatusLed {
+    pub fn new(pin: u8) -> Result<Self> {
+        let mut pin = Gpio::new()
+            .and_then(|gpio| gpio.get(pin))
+            .or_err_str()?
+            .into_output();
+
+        pin.set_low();
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut digits: Option<Vec<u8>> = None;
+            loop {
+                let interrupted = match &digits {
+                    Some(digits) => blink_digits(&mut pin, &rx, digits),
+                    None => Err(rx.recv().ok())
+                };
+
+                pin.set_low();
+                match interrupted {
+                    Ok(_) => {},
+                    Err(Some(Command::Digits(next))) => digits = Some(next),
+                    Err(Some(Command::Off)) => digits = None,
+                    Err(None) => return
+                }
+            }
+        });
+
+        Ok(Self { commands: Mutex::new(tx) })
+    }
+
+    /// blinks the number over and over, padded with zeros to the given amount of digits
+    pub fn show_number(&self, number: u32, digits: usize) {
+        let digits = format!("{:0width$}", number, width = digits)
+            .bytes()
+            .map(|digit| digit - b'0')
+            .collect();
+
+        self.commands.lock().unwrap().send(Command::Digits(digits)).unwrap_or_default();
+    }
+
+    pub fn off(&self) {
+        self.commands.lock().unwrap().send(Command::Off).unwrap_or_default();
+    }
+}
@@ -0,0 +1,319 @@
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{clock::SharedClock, config::HealthConfig, gpio::{anemometer::Wind, co2::Co2, environment::Environment, particle_matter::ParticleMatter, rain::Rain}};
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    Ok,
+    Degraded,
+    Failed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    ReadError,
+    OutOfRange,
+    Stuck,
+    Timeout,
+    // reported by the sensor itself
+    FanSpeed,
+    FanFailure,
+    LaserFailure
+}
+
+impl Fault {
+    fn severity(&self) -> HealthState {
+        match self {
+            Fault::Stuck | Fault::FanSpeed => HealthState::Degraded,
+            Fault::FanFailure | Fault::LaserFailure => HealthState::Failed,
+            // read errors only fail the sensor once they keep happening
+            Fault::ReadError | Fault::OutOfRange | Fault::Timeout => HealthState::Degraded
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SensorHealth {
+    pub state: HealthState,
+    pub fault: Option<Fault>
+}
+
+impl SensorHealth {
+    const OK: SensorHealth = SensorHealth { state: HealthState::Ok, fault: None };
+
+    fn worsen(&mut self, state: HealthState, fault: Fault) {
+        if state >= self.state {
+            self.state = state;
+            self.fault = Some(fault);
+        }
+    }
+}
+
+/// values of a reading that are checked, in the same order as the valid ranges
+pub trait Measurement {
+    const RANGES: &'static [(f64, f64)];
+    fn values(&self) -> Vec<f64>;
+}
+
+impl Measurement for Rain {
+    const RANGES: &'static [(f64, f64)] = &[(0.0, 1.0), (0.0, 1000.0), (0.0, 500.0)];
+
+    fn values(&self) -> Vec<f64> {
+        let mut values = vec![self.is_raining as u8 as f64];
+        if let Some(intensity) = &self.intensity {
+            values.extend([intensity.accumulated_mm as f64, intensity.rate_mm_h as f64]);
+        }
+
+        values
+    }
+}
+
+impl Measurement for ParticleMatter {
+    // measurement range of the sps30
+    const RANGES: &'static [(f64, f64)] = &[(0.0, 1000.0), (0.0, 1000.0)];
+
+    fn values(&self) -> Vec<f64> {
+        vec![self.pm_25_level as f64, self.pm_10_level as f64]
+    }
+}
+
+impl Measurement for Wind {
+    const RANGES: &'static [(f64, f64)] = &[(0.0, 75.0), (0.0, 100.0)];
+
+    fn values(&self) -> Vec<f64> {
+        vec![self.speed as f64, self.gust as f64]
+    }
+}
+
+impl Measurement for Environment {
+    const RANGES: &'static [(f64, f64)] = &[(-40.0, 85.0), (0.0, 100.0), (300.0, 1100.0)];
+
+    fn values(&self) -> Vec<f64> {
+        let mut values = vec![self.temperature as f64, self.humidity as f64];
+        values.extend(self.pressure.map(f64::from));
+        values
+    }
+}
+
+impl Measurement for Co2 {
+    const RANGES: &'static [(f64, f64)] = &[(0.0, 40000.0)];
+
+    fn values(&self) -> Vec<f64> {
+        vec![self.concentration as f64]
+    }
+}
+
+pub struct HealthCheck {
+    clock: SharedClock,
+    max_errors: u32,
+    timeout: Duration,
+    // number of identical readings after which the sensor is considered stuck
+    stuck_readings: Option<u32>,
+    // time without a change after which the sensor is considered stuck
+    stuck_duration: Option<Duration>,
+    last_values: Vec<f64>,
+    repeats: u32,
+    last_change: Instant,
+    errors: u32,
+    last_valid: Instant,
+    health: SensorHealth
+}
+
+impl HealthCheck {
+    pub fn new(config: &HealthConfig, clock: SharedClock) -> Self {
+        Self {
+            last_valid: clock.now(),
+            last_change: clock.now(),
+            clock,
+            max_errors: config.max_read_errors,
+            timeout: Duration::from_secs(config.timeout_secs),
+            stuck_readings: None,
+            stuck_duration: None,
+            last_values: Vec::new(),
+            repeats: 0,
+            errors: 0,
+            health: SensorHealth::OK
+        }
+    }
+
+    /// only used for sensors whose readings are expected to fluctuate
+    pub fn with_stuck_detection(mut self, readings: u32) -> Self {
+        self.stuck_readings = Some(readings);
+        self
+    }
+
+    /// used for sensors whose readings only change once in a while, such as the rain sensors
+    pub fn with_stuck_duration(mut self, duration: Duration) -> Self {
+        self.stuck_duration = Some(duration);
+        self
+    }
+
+    pub fn health(&self) -> SensorHealth {
+        self.health
+    }
+
+    /// updates the health of the sensor, returning the reading only if it can be trusted
+    pub fn check<T: Measurement>(&mut self, reading: Option<T>, faults: &[Fault]) -> Option<T> {
+        let now = self.clock.now();
+        let fault = match &reading {
+            None => Some(Fault::ReadError),
+            Some(reading) => {
+                let values = reading.values();
+                let out_of_range = values.iter()
+                    .zip(T::RANGES)
+                    .any(|(value, (min, max))| value < min || value > max);
+
+                if values == self.last_values {
+                    self.repeats += 1;
+                } else {
+                    self.repeats = 0;
+                    self.last_values = values;
+                    self.last_change = now;
+                }
+
+                if out_of_range { Some(Fault::OutOfRange) } else { None }
+            }
+        };
+
+        let mut health = SensorHealth::OK;
+        match fault {
+            None => {
+                self.errors = 0;
+                self.last_valid = now;
+            },
+            Some(fault) => {
+                self.errors += 1;
+                let state = if self.errors >= self.max_errors { HealthState::Failed } else { fault.severity() };
+                health.worsen(state, fault);
+
+                if now.duration_since(self.last_valid) > self.timeout {
+                    health.worsen(HealthState::Failed, Fault::Timeout);
+                }
+            }
+        }
+
+        let stuck = self.stuck_readings.is_some_and(|readings| self.repeats >= readings)
+            || self.stuck_duration.is_some_and(|duration| now.duration_since(self.last_change) >= duration);
+
+        if stuck {
+            health.worsen(Fault::Stuck.severity(), Fault::Stuck);
+        }
+
+        for fault in faults {
+            health.worsen(fault.severity(), *fault);
+        }
+
+        self.health = health;
+        reading.filter(|_| fault.is_none() && health.state != HealthState::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub rain: SensorHealth,
+    pub outdoor_pm: SensorHealth,
+    pub indoor_pm: Option<SensorHealth>,
+    pub wind: SensorHealth,
+    pub outdoor: SensorHealth,
+    pub indoor: SensorHealth,
+    pub co2: Option<SensorHealth>
+}
+
+pub struct HealthMonitor {
+    pub rain: HealthCheck,
+    pub outdoor_pm: HealthCheck,
+    /// only present when there is an indoor particle matter sensor
+    pub indoor_pm: Option<HealthCheck>,
+    pub wind: HealthCheck,
+    pub outdoor: HealthCheck,
+    pub indoor: HealthCheck,
+    /// only present when there is a co2 sensor
+    pub co2: Option<HealthCheck>
+}
+
+impl HealthMonitor {
+    pub fn new(config: &HealthConfig, indoor_pm: bool, co2: bool, clock: SharedClock) -> Self {
+        let stuck = config.stuck_readings;
+        let check = || HealthCheck::new(config, clock.clone());
+        Self {
+            // a disconnected rain sensor reads dry forever, which is only told apart from a dry spell by its length
+            rain: check().with_stuck_duration(Duration::from_secs(config.rain_stuck_days * 24 * 60 * 60)),
+            outdoor_pm: check().with_stuck_detection(stuck),
+            indoor_pm: indoor_pm.then(|| check().with_stuck_detection(stuck)),
+            wind: check(),
+            outdoor: check().with_stuck_detection(stuck),
+            indoor: check().with_stuck_detection(stuck),
+            co2: co2.then(|| check().with_stuck_detection(stuck))
+        }
+    }
+
+    pub fn report(&self) -> HealthReport {
+        HealthReport {
+            rain: self.rain.health(),
+            outdoor_pm: self.outdoor_pm.health(),
+            indoor_pm: self.indoor_pm.as_ref().map(HealthCheck::health),
+            wind: self.wind.health(),
+            outdoor: self.outdoor.health(),
+            indoor: self.indoor.health(),
+            co2: self.co2.as_ref().map(HealthCheck::health)
+        }
+    }
+}
+
+/// what to do with the window when a sensor fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailSafeAction {
+    // keep deciding without the sensor
+    Ignore,
+    // leave the window as it is
+    Keep,
+    Close
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FailSafePolicy {
+    pub rain: FailSafeAction,
+    pub particle_matter: FailSafeAction,
+    pub wind: FailSafeAction,
+    pub environment: FailSafeAction,
+    pub co2: FailSafeAction
+}
+
+impl Default for FailSafePolicy {
+    fn default() -> Self {
+        Self {
+            rain: FailSafeAction::Close,
+            particle_matter: FailSafeAction::Close,
+            wind: FailSafeAction::Close,
+            environment: FailSafeAction::Ignore,
+            co2: FailSafeAction::Ignore
+        }
+    }
+}
+
+impl FailSafePolicy {
+    /// the most conservative action of the failed sensors
+    pub fn action(&self, report: &HealthReport) -> FailSafeAction {
+        let sensors = [
+            (Some(report.rain), self.rain),
+            (Some(report.outdoor_pm), self.particle_matter),
+            (report.indoor_pm, self.particle_matter),
+            (Some(report.wind), self.wind),
+            (Some(report.outdoor), self.environment),
+            (Some(report.indoor), self.environment),
+            (report.co2, self.co2)
+        ];
+
+        sensors.into_iter()
+            .filter(|(health, _)| matches!(health, Some(health) if health.state == HealthState::Failed))
+            .map(|(_, action)| action)
+            .max()
+            .unwrap_or(FailSafeAction::Ignore)
+    }
+}
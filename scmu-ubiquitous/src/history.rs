@@ -0,0 +1,222 @@
+use std::{str::FromStr, time::Duration};
+
+use rusqlite::{params, Connection};
+use serde::{Serialize, Deserialize};
+
+use crate::util::{Result, ToErrString};
+
+// pruning is only done once in a while, since it is not needed on every reading
+const PRUNE_INTERVAL: i64 = 10 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Raw,
+    Minute,
+    Hour
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(resolution: &str) -> Result<Self> {
+        match resolution {
+            "raw" => Ok(Resolution::Raw),
+            "minute" => Ok(Resolution::Minute),
+            "hour" => Ok(Resolution::Hour),
+            _ => Err(format!("Unknown resolution {}", resolution))
+        }
+    }
+}
+
+impl Resolution {
+    fn bucket_size(&self) -> i64 {
+        match self {
+            Resolution::Raw => 1,
+            Resolution::Minute => 60,
+            Resolution::Hour => 60 * 60
+        }
+    }
+}
+
+pub struct Retention {
+    pub raw: Duration,
+    pub minute: Duration,
+    pub hour: Duration
+}
+
+/// for raw readings the minimum, average and maximum are the reading itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Point {
+    pub timestamp: i64,
+    pub metric: String,
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowEvent {
+    pub timestamp: i64,
+    pub closed: bool,
+    pub reason: String
+}
+
+pub struct History {
+    conn: Connection,
+    retention: Retention,
+    last_prune: i64
+}
+
+impl History {
+    pub fn open(path: &str, retention: Retention) -> Result<Self> {
+        let conn = Connection::open(path)
+            .or_err_str()?;
+
+        conn.execute_batch("
+            CREATE TABLE IF NOT EXISTS readings (
+                timestamp INTEGER NOT NULL,
+                metric TEXT NOT NULL,
+                value REAL NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS readings_timestamp ON readings (timestamp);
+            CREATE TABLE IF NOT EXISTS rollups (
+                resolution INTEGER NOT NULL,
+                bucket INTEGER NOT NULL,
+                metric TEXT NOT NULL,
+                min REAL NOT NULL,
+                max REAL NOT NULL,
+                sum REAL NOT NULL,
+                count INTEGER NOT NULL,
+                PRIMARY KEY (resolution, bucket, metric)
+            );
+            CREATE TABLE IF NOT EXISTS window_events (
+                timestamp INTEGER NOT NULL,
+                closed INTEGER NOT NULL,
+                reason TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS window_events_timestamp ON window_events (timestamp);
+        ").or_err_str()?;
+
+        Ok(Self {
+            conn,
+            retention,
+            last_prune: 0
+        })
+    }
+
+    /// stores the readings and updates the minute and hour rollups
+    pub fn record(&mut self, timestamp: i64, metrics: &[(&str, f64)]) -> Result<()> {
+        let tx = self.conn.transaction()
+            .or_err_str()?;
+
+        for (metric, value) in metrics {
+            tx.execute(
+                "INSERT INTO readings (timestamp, metric, value) VALUES (?1, ?2, ?3)",
+                params![timestamp, metric, value]
+            ).or_err_str()?;
+
+            for resolution in [Resolution::Minute, Resolution::Hour] {
+                let size = resolution.bucket_size();
+                tx.execute(
+                    "INSERT INTO rollups (resolution, bucket, metric, min, max, sum, count)
+                        VALUES (?1, ?2, ?3, ?4, ?4, ?4, 1)
+                        ON CONFLICT (resolution, bucket, metric) DO UPDATE SET
+                            min = min(min, excluded.min),
+                            max = max(max, excluded.max),
+                            sum = sum + excluded.sum,
+                            count = count + 1",
+                    params![size, timestamp - timestamp.rem_euclid(size), metric, value]
+                ).or_err_str()?;
+            }
+        }
+
+        tx.commit()
+            .or_err_str()?;
+
+        if timestamp - self.last_prune >= PRUNE_INTERVAL {
+            self.prune(timestamp)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn record_window_event(&mut self, timestamp: i64, closed: bool, reason: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO window_events (timestamp, closed, reason) VALUES (?1, ?2, ?3)",
+            params![timestamp, closed, reason]
+        ).or_err_str()?;
+
+        Ok(())
+    }
+
+    /// readings between `from` and `to` (inclusive), optionally restricted to a single metric
+    pub fn query(&self, from: i64, to: i64, resolution: Resolution, metric: Option<&str>) -> Result<Vec<Point>> {
+        let sql = match resolution {
+            Resolution::Raw => "
+                SELECT timestamp, metric, value, value, value FROM readings
+                WHERE timestamp BETWEEN ?1 AND ?2 AND (?3 IS NULL OR metric = ?3)
+                ORDER BY timestamp, metric",
+            _ => "
+                SELECT bucket, metric, min, sum / count, max FROM rollups
+                WHERE resolution = ?4 AND bucket BETWEEN ?1 AND ?2 AND (?3 IS NULL OR metric = ?3)
+                ORDER BY bucket, metric"
+        };
+
+        let mut stmt = self.conn.prepare(sql)
+            .or_err_str()?;
+
+        let size = resolution.bucket_size();
+        let rows = match resolution {
+            Resolution::Raw => stmt.query(params![from, to, metric]),
+            _ => stmt.query(params![from - from.rem_euclid(size), to, metric, size])
+        };
+
+        rows.and_then(|rows| {
+            rows.mapped(|row| Ok(Point {
+                timestamp: row.get(0)?,
+                metric: row.get(1)?,
+                min: row.get(2)?,
+                avg: row.get(3)?,
+                max: row.get(4)?
+            })).collect()
+        }).or_err_str()
+    }
+
+    pub fn window_events(&self, from: i64, to: i64) -> Result<Vec<WindowEvent>> {
+        let mut stmt = self.conn.prepare("
+            SELECT timestamp, closed, reason FROM window_events
+            WHERE timestamp BETWEEN ?1 AND ?2
+            ORDER BY timestamp
+        ").or_err_str()?;
+
+        let events = stmt.query_map(params![from, to], |row| Ok(WindowEvent {
+            timestamp: row.get(0)?,
+            closed: row.get(1)?,
+            reason: row.get(2)?
+        })).and_then(|rows| rows.collect())
+            .or_err_str()?;
+
+        Ok(events)
+    }
+
+    fn prune(&mut self, now: i64) -> Result<()> {
+        let raw_limit = now - self.retention.raw.as_secs() as i64;
+        let minute_limit = now - self.retention.minute.as_secs() as i64;
+        let hour_limit = now - self.retention.hour.as_secs() as i64;
+
+        self.conn.execute("DELETE FROM readings WHERE timestamp < ?1", params![raw_limit])
+            .or_err_str()?;
+
+        self.conn.execute(
+            "DELETE FROM rollups WHERE (resolution = ?1 AND bucket < ?2) OR (resolution = ?3 AND bucket < ?4)",
+            params![Resolution::Minute.bucket_size(), minute_limit, Resolution::Hour.bucket_size(), hour_limit]
+        ).or_err_str()?;
+
+        self.conn.execute("DELETE FROM window_events WHERE timestamp < ?1", params![hour_limit])
+            .or_err_str()?;
+
+        self.last_prune = now;
+        Ok(())
+    }
+}
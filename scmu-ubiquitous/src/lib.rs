@@ -0,0 +1,26 @@
+//! software of the scmu ubiquitous device, which opens and closes a window
+//! depending on the weather and the air quality
+//!
+//! - [`gpio`] holds the sensor drivers, their simulators and the recording and replay of readings
+//! - [`controller`] decides whether the window should be open from the latest readings
+//! - [`protocol`] and [`transport`] define how the device talks to the scmu server
+//! - [`history`] stores the readings on the device, which [`api`] serves to the local network
+//! - [`bt`] and [`wifi`] provide the bluetooth provisioning of the wifi network
+//! - [`device`] holds the settings that can be changed from the phone while the device runs
+//! - [`daemon`] ties everything together into the loop run by the binary
+
+pub(crate) mod util;
+pub mod api;
+pub mod aqi;
+pub mod clock;
+pub mod config;
+pub mod controller;
+pub mod daemon;
+pub mod device;
+pub mod health;
+pub mod history;
+pub mod protocol;
+pub mod transport;
+pub mod wifi;
+pub mod bt;
+pub mod gpio;
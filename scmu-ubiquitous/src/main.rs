@@ -1,153 +1,83 @@
-use std::{thread, sync::{Arc, Mutex}, time, net::TcpStream};
-
-use gpio::{rain::{RainSensorSimulator, RainSensor, Rain}, particle_matter::{ParticleMatterSensorSimulator, ParticleMatterSensor, ParticleMatter}};
-use serde::{Serialize, Deserialize};
-use tungstenite::{connect, handshake::client::Request, Message, WebSocket, stream::MaybeTlsStream};
-use url::Url;
-
-mod util;
-mod wifi;
-mod bt;
-mod gpio;
-
-#[derive(Serialize, Deserialize)]
-struct PkConfiguration {
-    public_key: String
-}
-
-#[derive(Serialize, Deserialize)]
-struct Incoming {
-    pm_25_threshold: u32,
-    pm_10_threshold: u32,
-    signature: Option<String>
-}
-
-#[derive(Serialize, Deserialize)]
-struct Wifi {
-    ssid: String,
-    strength: u32
-}
+use std::sync::Arc;
+
+use bluer::{adv::Advertisement, gatt::local::Application};
+use scmu_ubiquitous::{
+    bt::{pairing::{ConsoleDisplay, PasskeyDisplay}, security::ProvisioningSecurity, services::{bonds::BondedDevicesService, device::DeviceConfigurationService, wifi::WifiConfigurationService}, Bluetooth},
+    clock::{self, SharedClock},
+    config::{BluetoothConfig, Config},
+    daemon,
+    device::{DeviceConfiguration, SharedDeviceConfiguration},
+    gpio::status_led::StatusLed,
+    wifi::{portal, WifiManager}
+};
+use tokio::runtime::Runtime;
+
+// advertises the device under its name and serves the provisioning services, until the process stops
+async fn serve_bluetooth(config: BluetoothConfig, wm: &'static WifiManager, device: SharedDeviceConfiguration, clock: SharedClock) -> Result<(), String> {
+    let security: &'static ProvisioningSecurity = Box::leak(Box::new(ProvisioningSecurity::new(&config.proof_of_possession, clock.clone())));
+
+    let display: Arc<dyn PasskeyDisplay> = match config.status_led_pin {
+        Some(pin) => Arc::new(StatusLed::new(pin)?),
+        None => Arc::new(ConsoleDisplay)
+    };
+
+    let mut bt = Bluetooth::new(Advertisement {
+        discoverable: Some(true),
+        local_name: Some(device.settings().name),
+        ..Default::default()
+    }, display, clock.clone()).await?;
+
+    bt.start_app(Application {
+        services: vec![
+            WifiConfigurationService::create_service(wm, security, clock)?,
+            DeviceConfigurationService::create_service(device.clone(), security)?,
+            BondedDevicesService::create_service(bt.bonds(), security)?
+        ],
+        ..Default::default()
+    }).await?;
+
+    // a name changed from the phone is advertised right away
+    let mut names = device.subscribe();
+    let mut name = names.borrow().name.clone();
+    while names.changed().await.is_ok() {
+        let next = names.borrow().name.clone();
+        if next == name {
+            continue
+        }
 
-#[derive(Serialize, Deserialize)]
-struct Outgoing {
-    thresholds: Incoming,
-    wifi: Wifi,
-    is_closed: bool,
-    is_raining: bool,
-    pm_25_level: u32,
-    pm_10_level: u32
-}
+        match bt.set_name(&next).await {
+            Ok(_) => name = next,
+            Err(err) => println!("Failed to advertise the name {}: {}", next, err)
+        }
+    }
 
-struct SharedData {
-    ws: WebSocket<MaybeTlsStream<TcpStream>>,
-    pm25_threshold: u32,
-    pm10_threshold: u32,
-    closed: bool
+    Ok(())
 }
 
 fn main() {
-    // let wm: &'static WifiManager = Box::leak(Box::new(WifiManager::new()));
-
-    // let mut bt = Bluetooth::new(Advertisement {
-    //     discoverable: Some(true),
-    //     local_name: Some("joe".to_string()),
-    //     ..Default::default()
-    // }).await.unwrap();
+    let clock = clock::system();
+    let config = Config::load();
+    let device = Arc::new(DeviceConfiguration::load(&config));
 
-    // bt.start_app(Application {
-    //     services: vec![
-    //         WifiConfigurationService::create_service(wm)?
-    //     ],
-    //     ..Default::default()
-    // }).await.unwrap();
-
-    // let rainSensor = RainSensorReal::new();
-    let mut rain_sensor = RainSensorSimulator::new();
-
-    // let pmSensor = ParticleMatterSensorReal::new();
-    let mut pm_sensor = ParticleMatterSensorSimulator::new();
-
-    let req = Url::parse("ws://192.168.0.232:8080/ubiquitous")
+    // the provisioning runs on its own runtime, next to the threads of the daemon
+    let runtime = Runtime::new()
         .unwrap();
 
-    let (mut ws, _) = connect(req)
-        .unwrap();
+    if config.wifi.enabled {
+        let wm: &'static WifiManager = Box::leak(Box::new(WifiManager::new()
+            .with_certificate_dir(&config.wifi.certificate_dir)));
 
-    let config_pkt = serde_json::to_string(&PkConfiguration {
-        public_key: "test1_pk".to_string()
-    }).unwrap();
+        runtime.spawn(portal::run(wm, config.wifi.hotspot.clone(), config.bluetooth.proof_of_possession.clone(), clock.clone()));
 
-    ws.write_message(tungstenite::Message::Text(config_pkt))
-        .unwrap();
-
-    let shared_data = Arc::new(Mutex::new(SharedData {
-        ws,
-        pm25_threshold: 100,
-        pm10_threshold: 100,
-        closed: false
-    }));
-
-    let sd_cln = shared_data.clone();
-    thread::spawn(move || {
-        loop {
-            {
-                let mut lkd = sd_cln.lock().unwrap();
-                let msg = lkd.ws.read_message().unwrap();
-                match msg {
-                    Message::Text(text) => {
-                        let inc: Incoming = serde_json::from_str(&text)
-                            .unwrap();
-
-                        lkd.pm25_threshold = inc.pm_25_threshold;
-                        lkd.pm10_threshold = inc.pm_10_threshold;
-
-                        println!("pm 2.5 threshold: {}", inc.pm_25_threshold);
-                        println!("pm 10 threshold: {}", inc.pm_10_threshold);
-                    },
-                    Message::Ping(payload) => {
-                        lkd.ws.write_message(Message::Pong(payload))
-                            .unwrap()
-                    }
-                    _ => {}
-                }
-            }
-
-            thread::sleep(time::Duration::from_secs(2));
-        }
-    });
-
-    loop {
-        let rain_val = rain_sensor.read_value()
-           .unwrap();
-
-        let pm_val = pm_sensor.read_value()
-            .unwrap();
-
-        {
-            let mut sd = shared_data.lock().unwrap();
-            if rain_val.is_raining || (pm_val.pm_25_level as u32) > sd.pm25_threshold || (pm_val.pm_10_level as u32) > sd.pm10_threshold {
-                if !sd.closed {
-                    println!("Closing...");
-                    sd.closed = true;
+        if config.bluetooth.enabled {
+            let bluetooth = serve_bluetooth(config.bluetooth.clone(), wm, device.clone(), clock.clone());
+            runtime.spawn(async move {
+                if let Err(err) = bluetooth.await {
+                    println!("The bluetooth provisioning is unavailable: {}", err);
                 }
-            } else if sd.closed {
-                println!("Opening...");
-                sd.closed = false;
-            }
-
-            let status_pkt = serde_json::to_string(&Outgoing {
-                is_closed: sd.closed,
-                is_raining: rain_val.is_raining,
-                pm_25_level: pm_val.pm_25_level as u32,
-                pm_10_level: pm_val.pm_10_level as u32,
-                thresholds: Incoming { pm_25_threshold: sd.pm25_threshold, pm_10_threshold: sd.pm10_threshold, signature: None },
-                wifi: Wifi { ssid: "hello".to_string(), strength: 100 }
-            }).unwrap();
-
-            sd.ws.write_message(tungstenite::Message::Text(status_pkt))
-                .unwrap();
+            });
         }
-
-        thread::sleep(time::Duration::from_secs(5))
     }
-}
\ No newline at end of file
+
+    daemon::run(config, device, clock);
+}
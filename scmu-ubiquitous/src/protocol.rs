@@ -0,0 +1,207 @@
+//! messages exchanged with the scmu server over the websocket connection
+
+use serde::{Serialize, Deserialize};
+
+use crate::{aqi::{AirQuality, AqiThreshold}, gpio::environment::Environment, health::{HealthReport, HealthState}, history::{History, Point, Resolution, WindowEvent}};
+
+/// first message sent on every connection, identifying the device
+#[derive(Serialize, Deserialize)]
+pub struct PkConfiguration {
+    pub public_key: String
+}
+
+/// thresholds set from the mobile app, also echoed back in every status
+#[derive(Serialize, Deserialize)]
+pub struct Incoming {
+    pub pm_25_threshold: u32,
+    pub pm_10_threshold: u32,
+    pub wind_threshold: Option<f32>,
+    pub gust_threshold: Option<f32>,
+    pub co2_threshold: Option<u16>,
+    pub co2_recalibration: Option<u16>,
+    pub compare_particle_matter: Option<bool>,
+    pub pm_margin: Option<u32>,
+    pub aqi_threshold: Option<AqiThreshold>,
+    pub signature: Option<String>
+}
+
+/// network the device is connected to
+#[derive(Serialize, Deserialize)]
+pub struct Wifi {
+    pub ssid: String,
+    pub strength: u32
+}
+
+/// temperature, humidity and pressure of one location
+#[derive(Serialize, Deserialize)]
+pub struct EnvironmentData {
+    pub temperature: f32,
+    pub humidity: f32,
+    pub pressure: Option<f32>
+}
+
+impl From<&Environment> for EnvironmentData {
+    fn from(env: &Environment) -> Self {
+        Self {
+            temperature: env.temperature,
+            humidity: env.humidity,
+            pressure: env.pressure
+        }
+    }
+}
+
+/// status sent to the server after every sampling interval
+#[derive(Serialize, Deserialize)]
+pub struct Outgoing {
+    pub thresholds: Incoming,
+    pub wifi: Wifi,
+    pub is_closed: bool,
+    pub is_raining: bool,
+    pub rain_accumulated: Option<f32>,
+    pub rain_rate: Option<f32>,
+    /// left out while the outdoor particle matter sensor has failed
+    pub pm_25_level: Option<u32>,
+    pub pm_10_level: Option<u32>,
+    pub indoor_pm_25_level: Option<u32>,
+    pub indoor_pm_10_level: Option<u32>,
+    pub air_quality: AirQuality,
+    pub wind_speed: Option<f32>,
+    pub wind_gust: Option<f32>,
+    pub co2_level: Option<u16>,
+    pub indoor: Option<EnvironmentData>,
+    pub outdoor: Option<EnvironmentData>,
+    pub health: HealthReport
+}
+
+impl Outgoing {
+    /// numeric values stored in the history, metrics without a reading are skipped
+    pub fn metrics(&self) -> Vec<(&'static str, f64)> {
+        let mut metrics = vec![("is_closed", self.is_closed as u8 as f64)];
+
+        // the rain is always sent, so it is only stored while its sensor works
+        let rain_ok = self.health.rain.state != HealthState::Failed;
+
+        let optional = [
+            ("is_raining", rain_ok.then_some(self.is_raining as u8 as f64)),
+            ("pm_25_level", self.pm_25_level.map(f64::from)),
+            ("pm_10_level", self.pm_10_level.map(f64::from)),
+            ("rain_accumulated", self.rain_accumulated.map(f64::from)),
+            ("rain_rate", self.rain_rate.map(f64::from)),
+            ("indoor_pm_25_level", self.indoor_pm_25_level.map(f64::from)),
+            ("indoor_pm_10_level", self.indoor_pm_10_level.map(f64::from)),
+            ("us_epa_aqi", self.air_quality.us_epa.map(|index| index.value as f64)),
+            ("caqi", self.air_quality.caqi.map(|index| index.value as f64)),
+            ("wind_speed", self.wind_speed.map(f64::from)),
+            ("wind_gust", self.wind_gust.map(f64::from)),
+            ("co2_level", self.co2_level.map(f64::from)),
+            ("indoor_temperature", self.indoor.as_ref().map(|env| env.temperature as f64)),
+            ("indoor_humidity", self.indoor.as_ref().map(|env| env.humidity as f64)),
+            ("indoor_pressure", self.indoor.as_ref().and_then(|env| env.pressure).map(f64::from)),
+            ("outdoor_temperature", self.outdoor.as_ref().map(|env| env.temperature as f64)),
+            ("outdoor_humidity", self.outdoor.as_ref().map(|env| env.humidity as f64)),
+            ("outdoor_pressure", self.outdoor.as_ref().and_then(|env| env.pressure).map(f64::from))
+        ];
+
+        metrics.extend(optional.into_iter().filter_map(|(metric, value)| value.map(|value| (metric, value))));
+        metrics
+    }
+}
+
+/// sent by the server to fetch the readings stored on the device
+#[derive(Deserialize)]
+pub struct HistoryRequest {
+    pub request_id: u32,
+    pub from: i64,
+    pub to: i64,
+    pub resolution: Resolution,
+    pub metric: Option<String>,
+    pub page_size: Option<usize>
+}
+
+/// answers to history requests are sent as messagepack encoded binary messages
+#[derive(Serialize)]
+pub struct HistoryChunk<'a> {
+    pub request_id: u32,
+    pub page: usize,
+    pub pages: usize,
+    pub points: &'a [Point],
+    /// window events are only sent with the first page
+    pub events: &'a [WindowEvent],
+    pub error: Option<String>
+}
+
+/// any text message the server may send
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ServerMessage {
+    HistoryRequest(HistoryRequest),
+    Thresholds(Incoming)
+}
+
+/// request id of the chunks sent unprompted after a reconnection, with the readings the server missed
+pub const BACKFILL_REQUEST_ID: u32 = 0;
+
+// longer gaps are backfilled with the minute rollups, to keep the number of chunks down
+const BACKFILL_RAW_LIMIT: i64 = 60 * 60;
+
+/// points per chunk when the request does not specify it
+pub const HISTORY_PAGE_SIZE: usize = 500;
+pub const HISTORY_MAX_PAGE_SIZE: usize = 5000;
+
+/// answers a history request, failures are reported in a single chunk
+pub fn history_chunks(history: &History, req: &HistoryRequest) -> Vec<Vec<u8>> {
+    let result = history.query(req.from, req.to, req.resolution, req.metric.as_deref())
+        .and_then(|points| Ok((points, history.window_events(req.from, req.to)?)));
+
+    let (points, events) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            let chunk = HistoryChunk {
+                request_id: req.request_id,
+                page: 0,
+                pages: 0,
+                points: &[],
+                events: &[],
+                error: Some(err)
+            };
+
+            return vec![rmp_serde::to_vec_named(&chunk).unwrap()]
+        }
+    };
+
+    let page_size = req.page_size
+        .unwrap_or(HISTORY_PAGE_SIZE)
+        .clamp(1, HISTORY_MAX_PAGE_SIZE);
+
+    // an empty result is still answered with a single page
+    let pages = points.len().div_ceil(page_size).max(1);
+    (0..pages)
+        .map(|page| {
+            let start = (page * page_size).min(points.len());
+            let end = (start + page_size).min(points.len());
+            let chunk = HistoryChunk {
+                request_id: req.request_id,
+                page,
+                pages,
+                points: &points[start..end],
+                events: if page == 0 { &events } else { &[] },
+                error: None
+            };
+
+            rmp_serde::to_vec_named(&chunk).unwrap()
+        })
+        .collect()
+}
+
+/// readings recorded between `from` and `to` (inclusive), sent after the connection to the server was lost
+pub fn backfill_chunks(history: &History, from: i64, to: i64) -> Vec<Vec<u8>> {
+    let resolution = if to - from <= BACKFILL_RAW_LIMIT { Resolution::Raw } else { Resolution::Minute };
+    history_chunks(history, &HistoryRequest {
+        request_id: BACKFILL_REQUEST_ID,
+        from,
+        to,
+        resolution,
+        metric: None,
+        page_size: None
+    })
+}
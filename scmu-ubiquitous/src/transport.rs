@@ -0,0 +1,252 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{mpsc::{self, Receiver, TryRecvError}, Arc},
+    thread,
+    time::{Duration, Instant}
+};
+
+use tungstenite::{client_tls, error::Error, stream::MaybeTlsStream, Message, WebSocket};
+use url::Url;
+
+use crate::{clock::SharedClock, util::Result};
+
+// how long a read waits for a message, so that the connection is not held while idle
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+// how long connecting and each read or write of the handshake may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// resolves the http proxy to go through, looked up again on every new connection
+/// by the thread that connects, since it may take a while
+pub type ProxyLookup = Arc<dyn Fn() -> Option<String> + Send + Sync>;
+
+type ServerSocket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// websocket connection to the server, re-established with exponential backoff whenever it is lost
+pub struct ServerConnection {
+    url: Url,
+    // first message sent on every new connection
+    handshake: String,
+    clock: SharedClock,
+    ws: Option<ServerSocket>,
+    // attempt running on its own thread, so that the callers are not blocked while the server is slow to answer
+    attempt: Option<Receiver<Result<ServerSocket>>>,
+    proxy: ProxyLookup,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+    connections: u64
+}
+
+impl ServerConnection {
+    /// `handshake` is sent first on every new connection
+    pub fn new(url: Url, handshake: String, clock: SharedClock) -> Self {
+        Self {
+            url,
+            handshake,
+            clock,
+            ws: None,
+            attempt: None,
+            proxy: Arc::new(|| None),
+            backoff: MIN_BACKOFF,
+            retry_at: None,
+            connections: 0
+        }
+    }
+
+    /// goes through the http proxy returned by `proxy`, when there is one
+    pub fn with_proxy(mut self, proxy: impl Fn() -> Option<String> + Send + Sync + 'static) -> Self {
+        self.proxy = Arc::new(proxy);
+        self
+    }
+
+    /// connects to another server from the next message on
+    pub fn set_url(&mut self, url: Url) {
+        if url == self.url {
+            return
+        }
+
+        println!("Switching from {} to {}", self.url, url);
+        self.url = url;
+        self.ws = None;
+        self.attempt = None;
+        self.backoff = MIN_BACKOFF;
+        self.retry_at = None;
+    }
+
+    // starts connecting on another thread, the outcome is picked up by `connection`
+    fn start_attempt(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        let url = self.url.clone();
+        let handshake = self.handshake.clone();
+        let proxy = self.proxy.clone();
+
+        thread::spawn(move || {
+            sender.send(connect(&url, &handshake, proxy().as_deref()))
+                .unwrap_or_default();
+        });
+
+        self.attempt = Some(receiver);
+    }
+
+    // connects when there is no connection, unless the next attempt is not due yet
+    fn connection(&mut self) -> Option<&mut ServerSocket> {
+        if self.ws.is_none() {
+            let now = self.clock.now();
+            let result = match &self.attempt {
+                Some(attempt) => match attempt.try_recv() {
+                    Ok(result) => result,
+                    Err(TryRecvError::Empty) => return None,
+                    Err(TryRecvError::Disconnected) => Err(String::from("The connection attempt was aborted"))
+                },
+                None => {
+                    match self.retry_at {
+                        Some(retry_at) if now < retry_at => {},
+                        _ => self.start_attempt()
+                    }
+
+                    return None
+                }
+            };
+
+            self.attempt = None;
+            match result {
+                Ok(ws) => {
+                    println!("Connected to {}", self.url);
+                    self.ws = Some(ws);
+                    self.backoff = MIN_BACKOFF;
+                    self.retry_at = None;
+                    self.connections += 1;
+                },
+                Err(err) => {
+                    println!("Failed to connect to {} ({}), retrying in {:?}", self.url, err, self.backoff);
+                    self.retry_at = Some(now + self.backoff);
+                    self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                    return None
+                }
+            }
+        }
+
+        self.ws.as_mut()
+    }
+
+    /// number of connections established so far, which changes whenever the connection is replaced
+    pub fn connections(&self) -> u64 {
+        self.connections
+    }
+
+    fn disconnect(&mut self, err: &Error) {
+        println!("Lost the connection to {} ({})", self.url, err);
+        self.ws = None;
+    }
+
+    /// fails when there is no connection, which is then retried on the next call
+    pub fn send(&mut self, msg: Message) -> Result<()> {
+        let ws = self.connection()
+            .ok_or_else(|| String::from("Not connected"))?;
+
+        if let Err(err) = ws.write_message(msg) {
+            self.disconnect(&err);
+            return Err(err.to_string())
+        }
+
+        Ok(())
+    }
+
+    /// waits a short while for a message, returning None when nothing arrived
+    pub fn receive(&mut self) -> Option<Message> {
+        let ws = self.connection()?;
+        match ws.read_message() {
+            Ok(msg) => Some(msg),
+            Err(Error::Io(err)) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => None,
+            Err(err) => {
+                self.disconnect(&err);
+                None
+            }
+        }
+    }
+}
+
+// asks the proxy for a tunnel to the server, over which the websocket handshake is done
+fn tunnel(url: &Url, proxy: &str) -> Result<TcpStream> {
+    let proxy = Url::parse(proxy)
+        .map_err(|err| err.to_string())?;
+
+    let proxy_address = proxy.socket_addrs(|| Some(80))
+        .map_err(|err| err.to_string())?;
+
+    let host = url.host_str()
+        .ok_or_else(|| format!("{} has no host", url))?;
+
+    let port = url.port_or_known_default()
+        .ok_or_else(|| format!("{} has no port", url))?;
+
+    let mut stream = connect_any(&proxy_address)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+
+    write!(stream, "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n\r\n")
+        .map_err(|err| err.to_string())?;
+
+    // the reader is dropped at the end of the response headers, nothing is sent after them until the handshake
+    let mut reader = BufReader::new(&stream);
+    let mut status = String::new();
+    reader.read_line(&mut status)
+        .map_err(|err| err.to_string())?;
+
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("The proxy refused the tunnel: {}", status.trim()))
+    }
+
+    let mut line = String::new();
+    while line != "\r\n" {
+        line.clear();
+        if reader.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Err(String::from("The proxy closed the connection"))
+        }
+    }
+
+    Ok(stream)
+}
+
+// tries every address in turn
+fn connect_any(addresses: &[SocketAddr]) -> Result<TcpStream> {
+    let mut last_err = String::from("No address to connect to");
+    for address in addresses {
+        match TcpStream::connect_timeout(address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err.to_string()
+        }
+    }
+
+    Err(last_err)
+}
+
+fn connect(url: &Url, handshake: &str, proxy: Option<&str>) -> Result<ServerSocket> {
+    let stream = match proxy {
+        Some(proxy) => tunnel(url, proxy)?,
+        None => connect_any(&url.socket_addrs(|| None).map_err(|err| err.to_string())?)?
+    };
+
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(CONNECT_TIMEOUT)))
+        .map_err(|err| err.to_string())?;
+
+    // the timeouts belong to the socket, so they can still be changed once it is wrapped by tls
+    let socket = stream.try_clone()
+        .map_err(|err| err.to_string())?;
+
+    // tls is negotiated with the server itself, also through the tunnel of a proxy
+    let (mut ws, _) = client_tls(url.clone(), stream)
+        .map_err(|err| err.to_string())?;
+
+    socket.set_read_timeout(Some(READ_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+
+    ws.write_message(Message::Text(handshake.to_string()))
+        .map_err(|err| err.to_string())?;
+
+    Ok(ws)
+}
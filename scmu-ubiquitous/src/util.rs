@@ -32,6 +32,24 @@ impl <T> ToErrString<T> for network_manager::errors::Result<T> {
     }
 }
 
+impl <T> ToErrString<T> for std::result::Result<T, dbus::Error> {
+    fn or_err_str(self) -> Result<T> {
+        self.map_err(|err| err.to_string())
+    }
+}
+
+impl <T> ToErrString<T> for rppal::gpio::Result<T> {
+    fn or_err_str(self) -> Result<T> {
+        self.map_err(|err| err.to_string())
+    }
+}
+
+impl <T> ToErrString<T> for rusqlite::Result<T> {
+    fn or_err_str(self) -> Result<T> {
+        self.map_err(|err| err.to_string())
+    }
+}
+
 pub fn parse_uuid(uuid: &str) -> Result<Uuid> {
     Ok(Uuid::parse_str(uuid).or_err_str()?)
 }
\ No newline at end of file
@@ -0,0 +1,66 @@
+use std::{fs::{self, OpenOptions}, io::Write, os::unix::fs::OpenOptionsExt, path::PathBuf};
+
+use crate::util::Result;
+
+pub(super) const DEFAULT_CERTIFICATE_DIR: &str = "certificates";
+
+// certificates and keys of enterprise networks, uploaded in chunks since they
+// do not fit in a single bluetooth write
+#[derive(Clone)]
+pub(super) struct CertificateStore {
+    dir: PathBuf
+}
+
+impl CertificateStore {
+    pub fn new(dir: &str) -> Self {
+        Self { dir: PathBuf::from(dir) }
+    }
+
+    // names end up in file paths, so anything that could leave the directory is rejected
+    fn file(&self, name: &str) -> Result<PathBuf> {
+        let valid = !name.is_empty() && !name.starts_with('.') && name.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+        if !valid {
+            return Err(format!("Invalid certificate name {}", name))
+        }
+
+        Ok(self.dir.join(format!("{}.pem", name)))
+    }
+
+    // the first chunk starts a new file, the following ones must continue where the last one ended
+    pub fn write_chunk(&self, name: &str, offset: u64, data: &str) -> Result<()> {
+        let path = self.file(name)?;
+        fs::create_dir_all(&self.dir)
+            .map_err(|err| err.to_string())?;
+
+        let mut options = OpenOptions::new();
+        // private keys are stored here too
+        options.create(true).write(true).mode(0o600);
+
+        if offset == 0 {
+            options.truncate(true);
+        } else {
+            let len = fs::metadata(&path)
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+
+            if len != offset {
+                return Err(format!("Expected the chunk of {} at {}, not {}", name, len, offset))
+            }
+
+            options.append(true);
+        }
+
+        options.open(&path)
+            .and_then(|mut file| file.write_all(data.as_bytes()))
+            .map_err(|err| err.to_string())
+    }
+
+    // certificates are given to NetworkManager as absolute paths
+    pub fn path(&self, name: &str) -> Result<PathBuf> {
+        let path = self.file(name)?;
+        fs::canonicalize(&path)
+            .map_err(|_| format!("The certificate {} has not been uploaded", name))
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fs, io::ErrorKind, path::Path};
+
+use dbus::arg::PropMap;
+
+use crate::util::Result;
+
+use super::{profile::{variant, WIFI_CONNECTION}, settings::Settings};
+
+// profiles with this id are the hotspot, left behind if the daemon stopped while it was up
+pub(super) const HOTSPOT_ID: &str = "scmu-hotspot";
+pub(super) const HOTSPOT_MODE: &str = "ap";
+
+// address of the device in the hotspot, where the configuration page is served
+const HOTSPOT_ADDRESS: &str = "10.42.0.1";
+const HOTSPOT_PREFIX: u32 = 24;
+
+// read by the dnsmasq that NetworkManager starts for shared connections
+const DNSMASQ_SHARED_DIR: &str = "/etc/NetworkManager/dnsmasq-shared.d";
+const DNS_REDIRECT_FILE: &str = "scmu-hotspot.conf";
+
+/// access point the device opens when it has no other way to be configured
+#[derive(Debug, Clone)]
+pub struct Hotspot {
+    pub ssid: String,
+    /// at least 8 characters
+    pub password: String
+}
+
+/// answers every name with the address of the device while the hotspot is open,
+/// so that phones and laptops detect the captive portal and open the configuration page
+pub(super) fn redirect_dns() -> Result<()> {
+    fs::create_dir_all(DNSMASQ_SHARED_DIR)
+        .and_then(|_| fs::write(Path::new(DNSMASQ_SHARED_DIR).join(DNS_REDIRECT_FILE), format!("address=/#/{}\n", HOTSPOT_ADDRESS)))
+        .map_err(|err| format!("Unable to redirect the dns of the hotspot: {}", err))
+}
+
+pub(super) fn stop_redirecting_dns() {
+    match fs::remove_file(Path::new(DNSMASQ_SHARED_DIR).join(DNS_REDIRECT_FILE)) {
+        Err(err) if err.kind() != ErrorKind::NotFound => println!("Unable to remove the dns redirect of the hotspot: {}", err),
+        _ => {}
+    }
+}
+
+impl Hotspot {
+    pub(super) fn settings(&self) -> Result<Settings> {
+        let mut connection = PropMap::new();
+        connection.insert(String::from("id"), variant(HOTSPOT_ID.to_string()));
+        connection.insert(String::from("type"), variant(WIFI_CONNECTION.to_string()));
+        connection.insert(String::from("autoconnect"), variant(false));
+
+        let mut wireless = PropMap::new();
+        wireless.insert(String::from("ssid"), variant(self.ssid.as_bytes().to_vec()));
+        wireless.insert(String::from("mode"), variant(HOTSPOT_MODE.to_string()));
+        wireless.insert(String::from("band"), variant(String::from("bg")));
+
+        if self.password.len() < 8 {
+            return Err(String::from("The password of the hotspot needs at least 8 characters"))
+        }
+
+        let mut security = PropMap::new();
+        security.insert(String::from("key-mgmt"), variant(String::from("wpa-psk")));
+        security.insert(String::from("psk"), variant(self.password.clone()));
+        wireless.insert(String::from("security"), variant(String::from("802-11-wireless-security")));
+
+        let mut address = PropMap::new();
+        address.insert(String::from("address"), variant(HOTSPOT_ADDRESS.to_string()));
+        address.insert(String::from("prefix"), variant(HOTSPOT_PREFIX));
+
+        // NetworkManager hands out addresses to the clients and resolves their names
+        let mut ipv4 = PropMap::new();
+        ipv4.insert(String::from("method"), variant(String::from("shared")));
+        ipv4.insert(String::from("address-data"), variant(vec![address]));
+
+        let mut ipv6 = PropMap::new();
+        ipv6.insert(String::from("method"), variant(String::from("ignore")));
+
+        let mut settings = Settings::new();
+        settings.insert(String::from("802-11-wireless-security"), security);
+        settings.insert(String::from("connection"), connection);
+        settings.insert(String::from(WIFI_CONNECTION), wireless);
+        settings.insert(String::from("ipv4"), ipv4);
+        settings.insert(String::from("ipv6"), ipv6);
+        Ok(settings)
+    }
+}
@@ -1,97 +1,557 @@
-use std::sync::Arc;
+use std::{cmp::Reverse, sync::Mutex, thread};
 
 use network_manager::*;
-use tokio::time::*;
+use serde::Serialize;
+use tokio::{sync::{broadcast, watch}, task, time::*};
 
 use crate::util::{Result, ToErrString};
 
-use self::worker::NmWorker;
+use self::{certificates::CertificateStore, hotspot::{HOTSPOT_ID, HOTSPOT_MODE}, profile::WIFI_CONNECTION, settings::{Activation, NmSettings, ACTIVATION_FAILED, ACTIVATION_TOO_LONG}, status::Provisioning, worker::NmWorker};
 
+pub use self::{
+    hotspot::Hotspot,
+    profile::{EapMethod, EnterpriseSettings, IpSettings, NetworkProfile, Phase2Auth, StaticAddress, WifiSecurity},
+    settings::IpDetails,
+    signals::WifiEvent,
+    status::{ProvisioningState, ProvisioningStatus}
+};
+
+mod certificates;
+mod hotspot;
+pub mod portal;
+mod profile;
+mod settings;
+mod signals;
+mod status;
 mod worker;
 
+// events that were not received by a slow subscriber are dropped, which is reported as a lag
+const EVENT_CAPACITY: usize = 32;
+
+const TASK_TIMEOUT: Duration = Duration::from_secs(15);
+const ACTIVATION_TIMEOUT: Duration = Duration::from_secs(60);
+// long enough for the activation of the hotspot to either succeed or fail
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+// the state of a connection being activated is checked on every signal of NetworkManager, and at least this often
+const ACTIVATION_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// the access points show up again a few seconds after the hotspot goes down
+const SCAN_TIMEOUT: Duration = Duration::from_secs(15);
+const SCAN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// NMConnectivityState
+const CONNECTIVITY_NONE: u32 = 1;
+const CONNECTIVITY_PORTAL: u32 = 2;
+const CONNECTIVITY_LIMITED: u32 = 3;
+
+/// an access point found by a scan
+#[derive(Debug, Clone)]
+pub struct ScannedNetwork {
+    pub access_point: AccessPoint,
+    pub security: WifiSecurity
+}
+
+/// a wifi profile stored by NetworkManager, which connects to it automatically when it is in range
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedNetwork {
+    pub ssid: String,
+    /// the available network with the highest priority is preferred
+    pub priority: i32,
+    pub active: bool
+}
+
+/// the addresses the wifi device ended up with, either static or obtained automatically
+#[derive(Debug, Clone, Serialize)]
+pub struct IpConfiguration {
+    pub ipv4: IpDetails,
+    pub ipv6: IpDetails,
+    /// http proxy provisioned for the active connection
+    pub proxy: Option<String>
+}
+
+/// the http proxy provisioned for the connection currently in use, if any
+pub fn http_proxy() -> Option<String> {
+    NmSettings::new()
+        .and_then(|settings| settings.http_proxy())
+        .unwrap_or_default()
+}
+
+struct ActiveHotspot {
+    hotspot: Hotspot,
+    path: String,
+    // the device cannot scan while it is the access point
+    networks: Vec<ScannedNetwork>
+}
+
+/// provisions the wifi network of the device through NetworkManager: scans, connects, saves
+/// and forgets networks and opens a hotspot; the calls to NetworkManager are made by a worker thread,
+/// so the async methods do not block the runtime
 pub struct WifiManager {
-    nm_worker: NmWorker
+    nm_worker: NmWorker,
+    events: broadcast::Sender<WifiEvent>,
+    certificates: CertificateStore,
+    hotspot: Mutex<Option<ActiveHotspot>>,
+    provisioning: Provisioning
+}
+
+impl Default for WifiManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
     
 impl WifiManager {
+    /// starts the worker thread and watches the signals of NetworkManager for [`WifiManager::subscribe`]
     pub fn new() -> Self {
         let nm_worker = NmWorker::new();
-        Self { nm_worker }
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        signals::watch(events.clone());
+
+        Self {
+            nm_worker,
+            events,
+            certificates: CertificateStore::new(certificates::DEFAULT_CERTIFICATE_DIR),
+            hotspot: Mutex::new(None),
+            provisioning: Provisioning::new()
+        }
     }
 
-    pub async fn get_access_points(&self) -> Result<Vec<AccessPoint>> {
-        let access_points = self.nm_worker.do_task(Box::new(move |nm| {
+    /// directory where the certificates of enterprise networks are kept
+    pub fn with_certificate_dir(mut self, dir: &str) -> Self {
+        self.certificates = CertificateStore::new(dir);
+        self
+    }
+
+    /// stores a chunk of a certificate or private key, which starts over when `offset` is 0
+    pub fn store_certificate(&self, name: &str, offset: u64, data: &str) -> Result<()> {
+        self.certificates.write_chunk(name, offset, data)
+    }
+
+    /// notifies about the changes of NetworkManager from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<WifiEvent> {
+        self.events.subscribe()
+    }
+
+    /// the progress of the last connection attempt, updated on every step
+    pub fn provisioning_status(&self) -> watch::Receiver<ProvisioningStatus> {
+        self.provisioning.subscribe()
+    }
+
+    /// scans for access points and returns them once the scan is done
+    pub async fn get_access_points(&self) -> Result<Vec<ScannedNetwork>> {
+        self.nm_worker.do_task(TASK_TIMEOUT, move |nm, settings| {
             let device = WifiManager::find_wifi_device(nm)?;
             let wd = device.as_wifi_device().unwrap();
 
             wd.request_scan().or_err_str()?;
             
             let access_points = wd.get_access_points().or_err_str()?;
-            Ok(Box::new(access_points))
-        })).await?;
+            Ok(WifiManager::scanned_networks(settings, access_points))
+        }).await
+    }
+
+    /// the access points found by the last scan, which NetworkManager repeats periodically
+    pub async fn list_access_points(&self) -> Result<Vec<ScannedNetwork>> {
+        self.nm_worker.do_task(TASK_TIMEOUT, move |nm, settings| {
+            let device = WifiManager::find_wifi_device(nm)?;
+            let access_points = device.as_wifi_device()
+                .unwrap()
+                .get_access_points()
+                .or_err_str()?;
 
-        Ok(access_points)
+            Ok(WifiManager::scanned_networks(settings, access_points))
+        }).await
     }
 
-    pub async fn connect_to_ap(&self, ap: &Arc<AccessPoint>, credentials: &Arc<AccessPointCredentials>) -> Result<()> {
-        let ap_c = ap.clone();
-        let credentials_c = credentials.clone();
-        
-        self.nm_worker.do_task(Box::new(move |nm| {
-            WifiManager::try_delete_connection_by_ssid(nm, ap_c.ssid.as_str().unwrap());
+    /// creates a profile for the network and waits until it is connected,
+    /// the profiles saved before for the network are only replaced when it succeeds;
+    /// the hotspot is closed to connect and opened again when the connection fails
+    pub async fn connect_to_ap(&self, profile: NetworkProfile) -> Result<()> {
+        let hotspot = self.hotspot.lock()
+            .unwrap()
+            .as_ref()
+            .map(|active| active.hotspot.clone());
+
+        let ssid = profile.ssid.clone();
+        let provisioning = self.provisioning.clone();
+        provisioning.report(&ssid, ProvisioningState::Connecting, None);
+
+        if hotspot.is_some() {
+            if let Err(err) = self.stop_hotspot().await {
+                provisioning.fail(&ssid, None, &err);
+                return Err(err)
+            }
+        }
+
+        let result = self.activate(profile, hotspot.is_some()).await;
+
+        // timeouts and failures before the activation started are reported here
+        if let Err(err) = &result {
+            if self.provisioning.state() != ProvisioningState::Failed {
+                self.provisioning.fail(&ssid, None, err);
+            }
+        }
+
+        if let (Err(_), Some(hotspot)) = (&result, hotspot) {
+            self.start_hotspot(hotspot)
+                .await
+                .unwrap_or_else(|err| println!("Failed to open the hotspot again: {}", err));
+        }
+
+        result
+    }
+
+    // the worker only starts the activation and checks on it whenever NetworkManager signals a change,
+    // so that it is not held up while the device authenticates and obtains an address
+    async fn activate(&self, profile: NetworkProfile, rescan: bool) -> Result<()> {
+        let ssid = profile.ssid.clone();
+        let certificates = self.certificates.clone();
+        let provisioning = self.provisioning.clone();
+
+        // subscribed before the activation starts, so that none of its changes are missed
+        let mut events = self.subscribe();
+        let (path, active, device) = self.nm_worker.do_task(TASK_TIMEOUT + SCAN_TIMEOUT, move |nm, settings| {
+            let device = WifiManager::find_wifi_device(nm)?;
+
+            // visible networks are joined through the access point with the strongest signal
+            let access_point = match profile.hidden {
+                true => None,
+                false => match WifiManager::strongest_access_point(&device, &profile.ssid, rescan) {
+                    Ok(access_point) => Some(access_point),
+                    Err(err) => {
+                        provisioning.fail(&profile.ssid, Some(status::REASON_SSID_NOT_FOUND), &err);
+                        return Err(err)
+                    }
+                }
+            };
+
+            let sae = matches!(profile.security, WifiSecurity::Wpa3 | WifiSecurity::Wpa2Wpa3) && settings.supports_sae(device.interface());
+            let (path, active) = settings.add_and_activate(profile.settings(&certificates, sae)?, device.path(), access_point.as_ref().map(|ap| ap.path.as_str()))?;
+            Ok((path, active, device.path().to_string()))
+        }).await?;
+
+        let deadline = Instant::now() + ACTIVATION_TIMEOUT;
+        let activation = loop {
+            let (active, device) = (active.clone(), device.clone());
+            // a check that failed is tried again until the activation takes too long
+            let (activation, device_state) = self.nm_worker.do_task(TASK_TIMEOUT, move |_, settings| {
+                Ok((settings.activation(&active), settings.device_state(&device)))
+            }).await.unwrap_or_else(|err| (Activation::Activating, Err(err)));
+
+            if let Some(state) = device_state.ok().and_then(|(state, _)| ProvisioningState::from_device_state(state)) {
+                self.provisioning.report(&ssid, state, None);
+            }
+
+            match activation {
+                Activation::Activated => break Ok(()),
+                Activation::Failed => break Err(String::from(ACTIVATION_FAILED)),
+                Activation::Activating => {}
+            }
+
+            // checked again now and then, in case the signals cannot be received
+            if timeout_at(deadline, timeout(ACTIVATION_CHECK_INTERVAL, events.recv())).await.is_err() {
+                break Err(String::from(ACTIVATION_TOO_LONG))
+            }
+        };
+
+        let provisioning = self.provisioning.clone();
+        let saved_ssid = ssid.clone();
+        self.nm_worker.do_task(TASK_TIMEOUT, move |nm, settings| {
+            if let Err(err) = activation {
+                // the device keeps the reason of the failure until it tries again
+                let reason = settings.device_state(&device)
+                    .ok()
+                    .map(|(_, reason)| reason);
+
+                provisioning.fail(&saved_ssid, reason, &err);
+                settings.delete(&path).unwrap_or_default();
+                return Err(err)
+            }
+
+            WifiManager::replace_saved_networks(nm, settings, &saved_ssid, &path);
+            Ok(())
+        }).await?;
+
+        // the check downloads a page from the internet, which would hold up the worker
+        let connectivity = task::spawn_blocking(|| NmSettings::new()?.check_connectivity())
+            .await
+            .map_err(|err| err.to_string())
+            .and_then(|connectivity| connectivity);
+
+        // NMConnectivityState, which is unknown when the checks are disabled
+        let (state, message) = match connectivity.unwrap_or_default() {
+            CONNECTIVITY_NONE => (ProvisioningState::LimitedConnectivity, Some(String::from("No internet access"))),
+            CONNECTIVITY_PORTAL => (ProvisioningState::LimitedConnectivity, Some(String::from("Behind a captive portal"))),
+            CONNECTIVITY_LIMITED => (ProvisioningState::LimitedConnectivity, Some(String::from("Limited internet access"))),
+            _ => (ProvisioningState::Connected, None)
+        };
+
+        self.provisioning.report(&ssid, state, message);
+        Ok(())
+    }
+
+    /// turns the wifi device into an access point, so that the device can be configured without bluetooth
+    pub async fn start_hotspot(&self, hotspot: Hotspot) -> Result<()> {
+        if self.hotspot_active() {
+            return Ok(())
+        }
 
-            let wd = WifiManager::find_wifi_device(nm)?;
-            let (_, con_state) = wd.as_wifi_device()
+        let settings = hotspot.settings()?;
+        let (path, networks) = self.nm_worker.do_task(CONNECT_TIMEOUT, move |nm, nm_settings| {
+            let device = WifiManager::find_wifi_device(nm)?;
+            let access_points = device.as_wifi_device()
                 .unwrap()
-                .connect(&ap_c, &credentials_c)
+                .get_access_points()
                 .or_err_str()?;
-    
-            match con_state {
-                ConnectionState::Unknown |
-                ConnectionState::Deactivated |
-                ConnectionState::Deactivating => {
-                    WifiManager::try_delete_connection_by_ssid(nm, ap_c.ssid.as_str().unwrap());
-                    Err(String::from("Failed to establish connection"))
-                },
-                _ => Ok(Box::new(()))
+
+            let networks = WifiManager::scanned_networks(nm_settings, access_points);
+
+            // a hotspot left behind by an earlier run would be activated twice
+            for con in nm.get_connections().or_err_str()? {
+                if con.settings().id == HOTSPOT_ID {
+                    con.delete().unwrap_or_default();
+                }
             }
-        })).await?;
 
+            // the page is still reachable through the address of the device without the redirect
+            hotspot::redirect_dns()
+                .unwrap_or_else(|err| println!("{}", err));
+
+            let path = nm_settings.add_and_activate(settings, device.path(), None)
+                .and_then(|(path, active)| match nm_settings.wait_for_activation(&active, ACTIVATION_TIMEOUT) {
+                    Ok(_) => Ok(path),
+                    Err(err) => {
+                        nm_settings.delete(&path).unwrap_or_default();
+                        Err(err)
+                    }
+                })
+                .inspect_err(|_| hotspot::stop_redirecting_dns())?;
+
+            Ok((path, networks))
+        }).await?;
+
+        *self.hotspot.lock().unwrap() = Some(ActiveHotspot { hotspot, path, networks });
         Ok(())
     }
 
+    /// closes the hotspot when it is open and deletes its profile
+    pub async fn stop_hotspot(&self) -> Result<()> {
+        let active = match self.hotspot.lock().unwrap().take() {
+            Some(active) => active,
+            None => return Ok(())
+        };
+
+        // deleting the profile also deactivates it
+        self.nm_worker.do_task(TASK_TIMEOUT, move |_, settings| {
+            hotspot::stop_redirecting_dns();
+            settings.delete(&active.path)
+        }).await
+    }
+
+    /// whether the hotspot opened by [`WifiManager::start_hotspot`] is still open
+    pub fn hotspot_active(&self) -> bool {
+        self.hotspot.lock().unwrap().is_some()
+    }
+
+    /// the networks that were in range when the hotspot was opened
+    pub fn hotspot_networks(&self) -> Vec<ScannedNetwork> {
+        self.hotspot.lock()
+            .unwrap()
+            .as_ref()
+            .map(|active| active.networks.clone())
+            .unwrap_or_default()
+    }
+
+    /// saved networks, from the highest to the lowest priority
+    pub async fn saved_networks(&self) -> Result<Vec<SavedNetwork>> {
+        self.nm_worker.do_task(TASK_TIMEOUT, move |nm, settings| {
+            let active: Vec<String> = nm.get_active_connections()
+                .unwrap_or_default()
+                .iter()
+                .map(|con| con.settings().uuid.clone())
+                .collect();
+
+            let mut networks = WifiManager::wifi_connections(nm)?
+                .iter()
+                .map(|con| Ok(SavedNetwork {
+                    ssid: con.settings().ssid.as_str().unwrap_or_default().to_string(),
+                    priority: settings::autoconnect_priority(&settings.get(con.path())?),
+                    active: active.contains(&con.settings().uuid)
+                }))
+                .collect::<Result<Vec<SavedNetwork>>>()?;
+
+            networks.sort_by_key(|network| Reverse(network.priority));
+            Ok(networks)
+        }).await
+    }
+
+    /// changes the priority of every profile saved for the network, failing when there is none
+    pub async fn set_network_priority(&self, ssid: &str, priority: i32) -> Result<()> {
+        let ssid = ssid.to_string();
+        self.nm_worker.do_task(TASK_TIMEOUT, move |nm, settings| {
+            for con in WifiManager::saved_connections(nm, &ssid)? {
+                settings.update(con.path(), |settings| settings::set_autoconnect_priority(settings, priority))?;
+            }
+
+            Ok(())
+        }).await
+    }
+
+    /// deletes every profile saved for the network, failing when there is none
+    pub async fn forget_network(&self, ssid: &str) -> Result<()> {
+        let ssid = ssid.to_string();
+        self.nm_worker.do_task(TASK_TIMEOUT, move |nm, _| {
+            for con in WifiManager::saved_connections(nm, &ssid)? {
+                con.delete().or_err_str()?;
+            }
+
+            Ok(())
+        }).await
+    }
+
+    /// addresses of the wifi device and the http proxy of the connection in use
+    pub async fn ip_configuration(&self) -> Result<IpConfiguration> {
+        self.nm_worker.do_task(TASK_TIMEOUT, move |nm, settings| {
+            let device = WifiManager::find_wifi_device(nm)?;
+            let (ipv4, ipv6) = settings.ip_configuration(device.path())?;
+
+            Ok(IpConfiguration {
+                ipv4,
+                ipv6,
+                proxy: settings.http_proxy().unwrap_or_default()
+            })
+        }).await
+    }
+
+    /// whether NetworkManager reports full connectivity, i.e. the internet is reachable
     pub async fn is_connected(&self) -> Result<bool> {
-        let is_connected = self.nm_worker.do_task(Box::new(move |nm| {
+        let is_connected = self.nm_worker.do_task(TASK_TIMEOUT, move |nm, _| {
             let connectivity = nm.get_connectivity()
                 .or_err_str()?;
 
-            Ok(Box::new(connectivity == Connectivity::Full))
-        })).await?;
+            Ok(connectivity == Connectivity::Full)
+        }).await?;
 
         Ok(is_connected)
     }
 
+    /// whether the wifi device is connected to a network other than the hotspot,
+    /// even one without internet access
+    pub async fn is_activated(&self) -> Result<bool> {
+        if self.hotspot_active() {
+            return Ok(false)
+        }
+
+        self.nm_worker.do_task(TASK_TIMEOUT, move |nm, _| {
+            let state = WifiManager::find_wifi_device(nm)?
+                .get_state()
+                .or_err_str()?;
+
+            Ok(state == DeviceState::Activated)
+        }).await
+    }
+
+    /// waits until [`WifiManager::is_connected`] holds
     pub async fn wait_for_connection(&self) {
         while !self.is_connected().await.unwrap_or_default() {
             sleep(Duration::from_millis(200)).await;
         }
     }
 
-    fn try_delete_connection_by_ssid(network_manager: &NetworkManager, ssid: &str) {
-        let existent = network_manager.get_connections()
+    fn wifi_connections(network_manager: &NetworkManager) -> Result<Vec<Connection>> {
+        let connections = network_manager.get_connections()
+            .or_err_str()?
+            .into_iter()
+            .filter(|con| con.settings().kind == WIFI_CONNECTION && con.settings().mode != HOTSPOT_MODE)
+            .collect();
+
+        Ok(connections)
+    }
+
+    fn saved_connections(network_manager: &NetworkManager, ssid: &str) -> Result<Vec<Connection>> {
+        let connections: Vec<Connection> = WifiManager::wifi_connections(network_manager)?
+            .into_iter()
+            .filter(|con| con.settings().ssid.as_str() == Ok(ssid))
+            .collect();
+
+        if connections.is_empty() {
+            return Err(format!("The network {} is not saved", ssid))
+        }
+
+        Ok(connections)
+    }
+
+    // the new profile of a network takes the place of the older ones, keeping their priority
+    fn replace_saved_networks(network_manager: &NetworkManager, settings: &NmSettings, ssid: &str, path: &str) {
+        let previous: Vec<Connection> = WifiManager::saved_connections(network_manager, ssid)
             .unwrap_or_default()
             .into_iter()
-            .find(|con| {
-                con.settings()
-                    .ssid
-                    .as_str()
-                    .unwrap() == ssid
-            });
+            .filter(|con| con.path() != path)
+            .collect();
+
+        if previous.is_empty() {
+            return
+        }
+
+        let priority = previous.iter()
+            .filter_map(|con| settings.get(con.path()).ok())
+            .map(|con_settings| settings::autoconnect_priority(&con_settings))
+            .max()
+            .unwrap_or_default();
+
+        settings.update(path, |settings| settings::set_autoconnect_priority(settings, priority))
+            .unwrap_or_else(|err| println!("Failed to keep the priority of {}: {}", ssid, err));
+
+        for con in previous {
+            con.delete().unwrap_or_default();
+        }
+    }
+
+    // waits for a scan to find the network when `rescan` is set
+    fn strongest_access_point(device: &Device, ssid: &str, rescan: bool) -> Result<AccessPoint> {
+        let wd = device.as_wifi_device().unwrap();
+        let start = Instant::now();
+
+        if rescan {
+            wd.request_scan().unwrap_or_default();
+        }
+
+        loop {
+            let access_point = wd.get_access_points()
+                .or_err_str()?
+                .into_iter()
+                .filter(|ap| ap.ssid.as_str() == Ok(ssid))
+                .max_by_key(|ap| ap.strength);
+
+            match access_point {
+                Some(access_point) => return Ok(access_point),
+                None if rescan && start.elapsed() < SCAN_TIMEOUT => thread::sleep(SCAN_POLL_INTERVAL),
+                None => return Err(format!("The network {} is not in range", ssid))
+            }
+        }
+    }
+
+    fn scanned_networks(settings: &NmSettings, access_points: Vec<AccessPoint>) -> Vec<ScannedNetwork> {
+        access_points.into_iter()
+            .map(|access_point| {
+                let security = settings.access_point_security(&access_point.path)
+                    .ok()
+                    .unwrap_or_else(|| WifiManager::security_of(&access_point));
+
+                ScannedNetwork { access_point, security }
+            })
+            .collect()
+    }
 
-        if existent.is_some() {
-            existent.unwrap()
-                .delete()
-                .unwrap_or_default();
+    fn security_of(access_point: &AccessPoint) -> WifiSecurity {
+        let security = access_point.security;
+        if security.contains(Security::ENTERPRISE) {
+            WifiSecurity::Enterprise
+        } else if security.intersects(Security::WPA | Security::WPA2) {
+            WifiSecurity::Wpa
+        } else if security.contains(Security::WEP) {
+            WifiSecurity::Wep
+        } else {
+            WifiSecurity::None
         }
     }
 
@@ -0,0 +1,304 @@
+use std::{cmp::Reverse, collections::HashSet, time::Duration};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, time::timeout};
+use url::form_urlencoded;
+
+use crate::{clock::SharedClock, config::HotspotConfig, util::Result};
+
+use super::{Hotspot, NetworkProfile, ScannedNetwork, WifiManager};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// the saved networks cannot be joined while the hotspot is open, so it is closed this often to try them
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// how long the saved networks have to connect before the hotspot is opened again
+const RETRY_WAIT: Duration = Duration::from_secs(60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+struct Request {
+    method: String,
+    path: String,
+    body: String
+}
+
+/// opens the hotspot whenever the device stays without a network for too long and serves a page
+/// to pick the network to join from a browser, until the device is connected
+/// the password of the hotspot is the proof of possession unless the configuration gives one
+pub async fn run(wifi: &'static WifiManager, config: HotspotConfig, proof_of_possession: String, clock: SharedClock) {
+    let password = config.password.clone()
+        .unwrap_or(proof_of_possession);
+
+    // anyone nearby could pick the network of the device otherwise
+    if password.len() < 8 {
+        println!("The hotspot is disabled, it needs a password or a proof of possession of at least 8 characters");
+        return
+    }
+
+    let offline_limit = Duration::from_secs(config.after_minutes * 60);
+    let hotspot = Hotspot {
+        ssid: config.ssid.clone(),
+        password
+    };
+
+    let mut offline_since = None;
+    loop {
+        // a failed connection from the page opens the hotspot again, which has to be served right away
+        if wifi.hotspot_active() {
+            serve(wifi, &hotspot, config.port, &clock).await;
+            offline_since = None;
+        } else if wifi.is_activated().await.unwrap_or_default() {
+            // a network without internet access is still what the device was configured for
+            offline_since = None;
+        } else {
+            let now = clock.now();
+            let since = *offline_since.get_or_insert(now);
+
+            if now - since >= offline_limit {
+                println!("Without a network for {:?}, opening the hotspot {}", offline_limit, hotspot.ssid);
+                match wifi.start_hotspot(hotspot.clone()).await {
+                    Ok(_) => serve(wifi, &hotspot, config.port, &clock).await,
+                    Err(err) => println!("Failed to open the hotspot: {}", err)
+                }
+
+                offline_since = None;
+            }
+        }
+
+        clock.sleep_async(CHECK_INTERVAL).await;
+    }
+}
+
+// the page is only reachable while the hotspot is open
+async fn serve(wifi: &'static WifiManager, hotspot: &Hotspot, port: u16, clock: &SharedClock) {
+    let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("Unable to serve the configuration page: {}", err);
+            return
+        }
+    };
+
+    let mut retry_at = clock.now() + RETRY_INTERVAL;
+    while wifi.hotspot_active() {
+        tokio::select! {
+            accepted = listener.accept() => {
+                if let Ok((stream, _)) = accepted {
+                    tokio::spawn(handle(wifi, stream));
+                }
+            },
+            _ = clock.sleep_async(CHECK_INTERVAL) => {}
+        }
+
+        if clock.now() >= retry_at {
+            if retry_saved_networks(wifi, hotspot, clock).await {
+                return
+            }
+
+            retry_at = clock.now() + RETRY_INTERVAL;
+        }
+    }
+}
+
+// closes the hotspot for a while, so that NetworkManager connects to a saved network that came in range,
+// returning true when one did
+async fn retry_saved_networks(wifi: &'static WifiManager, hotspot: &Hotspot, clock: &SharedClock) -> bool {
+    if wifi.saved_networks().await.unwrap_or_default().is_empty() {
+        return false
+    }
+
+    println!("Closing the hotspot to retry the saved networks");
+    if let Err(err) = wifi.stop_hotspot().await {
+        println!("Failed to close the hotspot: {}", err);
+        return false
+    }
+
+    let deadline = clock.now() + RETRY_WAIT;
+    while clock.now() < deadline {
+        clock.sleep_async(CHECK_INTERVAL).await;
+        if wifi.is_activated().await.unwrap_or_default() {
+            println!("Connected to a saved network, the hotspot stays closed");
+            return true
+        }
+    }
+
+    if let Err(err) = wifi.start_hotspot(hotspot.clone()).await {
+        println!("Failed to open the hotspot again: {}", err);
+    }
+
+    false
+}
+
+async fn handle(wifi: &'static WifiManager, mut stream: TcpStream) {
+    let request = match timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => request,
+        _ => return
+    };
+
+    let (response, profile) = match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/connect") => match network_profile(wifi, &request.body) {
+            Ok(profile) => {
+                let message = format!("Connecting to {}, the hotspot closes once connected.", escape(&profile.ssid));
+                (response("200 OK", &page(&[], Some(&message))), Some(profile))
+            },
+            Err(err) => (response("400 Bad Request", &page(&wifi.hotspot_networks(), Some(&escape(&err)))), None)
+        },
+        // every name resolves to the device while the hotspot is open, so the captive portal checks
+        // of phones and laptops get the page too and open it by themselves
+        _ => (response("200 OK", &page(&wifi.hotspot_networks(), None)), None)
+    };
+
+    stream.write_all(response.as_bytes()).await.unwrap_or_default();
+    stream.shutdown().await.unwrap_or_default();
+
+    // the hotspot goes down while connecting, so the answer is sent first
+    if let Some(profile) = profile {
+        let ssid = profile.ssid.clone();
+        match wifi.connect_to_ap(profile).await {
+            Ok(_) => println!("Connected to {} from the configuration page", ssid),
+            Err(err) => println!("Failed to connect to {} from the configuration page: {}", ssid, err)
+        }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    let header_end = loop {
+        if let Some(pos) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break pos + 4
+        }
+
+        if data.len() > MAX_REQUEST_SIZE {
+            return Err(String::from("The request is too large"))
+        }
+
+        let read = stream.read(&mut buf).await
+            .map_err(|err| err.to_string())?;
+
+        if read == 0 {
+            return Err(String::from("The request ended early"))
+        }
+
+        data.extend_from_slice(&buf[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&data[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()
+        .unwrap_or_default()
+        .split_whitespace();
+
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or_default();
+
+    if header_end + content_length > MAX_REQUEST_SIZE {
+        return Err(String::from("The request is too large"))
+    }
+
+    while data.len() < header_end + content_length {
+        let read = stream.read(&mut buf).await
+            .map_err(|err| err.to_string())?;
+
+        if read == 0 {
+            return Err(String::from("The request ended early"))
+        }
+
+        data.extend_from_slice(&buf[..read]);
+    }
+
+    let body = String::from_utf8_lossy(&data[header_end..header_end + content_length]).to_string();
+    Ok(Request { method, path, body })
+}
+
+fn network_profile(wifi: &WifiManager, body: &str) -> Result<NetworkProfile> {
+    let field = |name: &str| form_urlencoded::parse(body.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty());
+
+    let ssid = field("ssid")
+        .ok_or_else(|| String::from("Choose a network"))?;
+
+    let security = wifi.hotspot_networks()
+        .into_iter()
+        .find(|network| network.access_point.ssid.as_str() == Ok(ssid.as_str()))
+        .map(|network| network.security)
+        .ok_or_else(|| format!("The network {} is not in range", ssid))?;
+
+    Ok(NetworkProfile {
+        ssid,
+        hidden: false,
+        security,
+        password: field("password"),
+        identity: field("identity"),
+        enterprise: None,
+        ip: Default::default()
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn page(networks: &[ScannedNetwork], message: Option<&str>) -> String {
+    let mut networks: Vec<&ScannedNetwork> = networks.iter()
+        .filter(|network| !network.access_point.ssid.as_str().unwrap_or_default().is_empty())
+        .collect();
+
+    // one entry per network, with the strongest signal first
+    let mut seen = HashSet::new();
+    networks.sort_by_key(|network| Reverse(network.access_point.strength));
+    networks.retain(|network| seen.insert(network.access_point.ssid.as_str().unwrap_or_default().to_string()));
+
+    let options: String = networks.iter()
+        .map(|network| {
+            let ssid = escape(network.access_point.ssid.as_str().unwrap_or_default());
+            format!("<option value=\"{ssid}\">{ssid} ({}%)</option>", network.access_point.strength)
+        })
+        .collect();
+
+    let form = match networks.is_empty() {
+        true => String::new(),
+        false => format!(
+            "<form method=\"post\" action=\"/connect\">\
+            <p><select name=\"ssid\">{options}</select></p>\
+            <p><input name=\"identity\" placeholder=\"Identity (enterprise networks)\"></p>\
+            <p><input name=\"password\" type=\"password\" placeholder=\"Password\"></p>\
+            <p><button type=\"submit\">Connect</button></p>\
+            </form>"
+        )
+    };
+
+    let message = message
+        .map(|message| format!("<p>{message}</p>"))
+        .unwrap_or_default();
+
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+        <title>Wi-Fi setup</title></head>\
+        <body><h1>Wi-Fi setup</h1>{message}{form}</body></html>"
+    )
+}
+
+fn response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: text/html; charset=utf-8\r\n\
+        Content-Length: {}\r\n\
+        Cache-Control: no-store\r\n\
+        Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
@@ -0,0 +1,348 @@
+use std::{collections::HashMap, net::{IpAddr, Ipv4Addr, Ipv6Addr}};
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use serde::{Serialize, Deserialize};
+use url::Url;
+
+use crate::util::Result;
+
+use super::{certificates::CertificateStore, settings::Settings};
+
+pub(super) const WIFI_CONNECTION: &str = "802-11-wireless";
+
+// key of the connection user data that holds the http proxy of the network
+pub(super) const HTTP_PROXY_KEY: &str = "scmu.http-proxy";
+
+// NM80211ApFlags and NM80211ApSecurityFlags
+const AP_FLAGS_PRIVACY: u32 = 0x1;
+const AP_SEC_KEY_MGMT_PSK: u32 = 0x100;
+const AP_SEC_KEY_MGMT_802_1X: u32 = 0x200;
+const AP_SEC_KEY_MGMT_SAE: u32 = 0x400;
+
+// NMSettingWirelessSecurityPmf
+const PMF_OPTIONAL: i32 = 2;
+
+/// how a network is secured, as advertised by the access point or given for a hidden network
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WifiSecurity {
+    None,
+    Wep,
+    /// WPA or WPA2 personal
+    Wpa,
+    /// WPA3 personal (SAE)
+    Wpa3,
+    /// transition networks that accept both WPA2 and WPA3 personal
+    Wpa2Wpa3,
+    Enterprise
+}
+
+impl WifiSecurity {
+    pub(super) fn from_flags(flags: u32, wpa_flags: u32, rsn_flags: u32) -> Self {
+        let key_mgmt = wpa_flags | rsn_flags;
+        let psk = key_mgmt & AP_SEC_KEY_MGMT_PSK != 0;
+        let sae = key_mgmt & AP_SEC_KEY_MGMT_SAE != 0;
+
+        if key_mgmt & AP_SEC_KEY_MGMT_802_1X != 0 {
+            WifiSecurity::Enterprise
+        } else if psk && sae {
+            WifiSecurity::Wpa2Wpa3
+        } else if sae {
+            WifiSecurity::Wpa3
+        } else if psk {
+            WifiSecurity::Wpa
+        } else if flags & AP_FLAGS_PRIVACY != 0 {
+            WifiSecurity::Wep
+        } else {
+            WifiSecurity::None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EapMethod {
+    #[default]
+    Peap,
+    Ttls,
+    /// authenticates with a client certificate instead of a password
+    Tls
+}
+
+/// inner authentication of the PEAP and TTLS tunnels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase2Auth {
+    #[default]
+    Mschapv2,
+    Pap
+}
+
+/// 802.1x options of an enterprise network, certificates are referred to by the
+/// name they were uploaded with
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EnterpriseSettings {
+    pub method: EapMethod,
+    pub phase2: Phase2Auth,
+    /// sent in the clear instead of the real identity, which is only sent inside the tunnel
+    pub anonymous_identity: Option<String>,
+    /// the server certificate must be issued for a domain ending with this suffix
+    pub domain_suffix_match: Option<String>,
+    pub ca_certificate: Option<String>,
+    pub client_certificate: Option<String>,
+    pub private_key: Option<String>,
+    pub private_key_password: Option<String>
+}
+
+/// address assigned by hand, for networks without DHCP (or router advertisements)
+#[derive(Debug, Clone, Deserialize)]
+pub struct StaticAddress {
+    pub address: String,
+    pub prefix: u32,
+    pub gateway: Option<String>
+}
+
+/// addressing of the device in the network, which is automatic unless an address is given
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IpSettings {
+    pub ipv4: Option<StaticAddress>,
+    pub ipv6: Option<StaticAddress>,
+    /// used instead of the servers given by DHCP
+    pub dns: Vec<String>,
+    /// http proxy the server is reached through, e.g. http://proxy.local:3128
+    pub proxy: Option<String>
+}
+
+/// everything needed to create the NetworkManager profile of a network
+#[derive(Debug, Clone)]
+pub struct NetworkProfile {
+    pub ssid: String,
+    /// hidden networks are probed for by name, since they are not in the scan results
+    pub hidden: bool,
+    pub security: WifiSecurity,
+    pub password: Option<String>,
+    pub identity: Option<String>,
+    /// PEAP with MSCHAPv2 is used for enterprise networks when absent
+    pub enterprise: Option<EnterpriseSettings>,
+    pub ip: IpSettings
+}
+
+pub(super) fn variant<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value))
+}
+
+// NetworkManager takes certificates as a NUL terminated file:// url
+fn certificate(certificates: &CertificateStore, name: &str) -> Result<Variant<Box<dyn RefArg>>> {
+    let path = certificates.path(name)?;
+    Ok(variant(format!("file://{}\0", path.display()).into_bytes()))
+}
+
+fn parse<T: std::str::FromStr>(address: &str) -> Result<T> {
+    address.parse()
+        .map_err(|_| format!("Invalid address {}", address))
+}
+
+fn address_data(address: &StaticAddress) -> Vec<PropMap> {
+    let mut data = PropMap::new();
+    data.insert(String::from("address"), variant(address.address.clone()));
+    data.insert(String::from("prefix"), variant(address.prefix));
+    vec![data]
+}
+
+fn ipv4_settings(ip: &IpSettings, dns: &[Ipv4Addr]) -> Result<PropMap> {
+    let mut ipv4 = PropMap::new();
+    match &ip.ipv4 {
+        Some(address) => {
+            parse::<Ipv4Addr>(&address.address)?;
+            ipv4.insert(String::from("method"), variant(String::from("manual")));
+            ipv4.insert(String::from("address-data"), variant(address_data(address)));
+
+            if let Some(gateway) = &address.gateway {
+                ipv4.insert(String::from("gateway"), variant(parse::<Ipv4Addr>(gateway)?.to_string()));
+            }
+        },
+        None => {
+            ipv4.insert(String::from("method"), variant(String::from("auto")));
+        }
+    }
+
+    if !dns.is_empty() {
+        // in network byte order
+        let servers: Vec<u32> = dns.iter().map(|server| u32::from_ne_bytes(server.octets())).collect();
+        ipv4.insert(String::from("dns"), variant(servers));
+        ipv4.insert(String::from("ignore-auto-dns"), variant(true));
+    }
+
+    Ok(ipv4)
+}
+
+fn ipv6_settings(ip: &IpSettings, dns: &[Ipv6Addr]) -> Result<PropMap> {
+    let mut ipv6 = PropMap::new();
+    match &ip.ipv6 {
+        Some(address) => {
+            parse::<Ipv6Addr>(&address.address)?;
+            ipv6.insert(String::from("method"), variant(String::from("manual")));
+            ipv6.insert(String::from("address-data"), variant(address_data(address)));
+
+            if let Some(gateway) = &address.gateway {
+                ipv6.insert(String::from("gateway"), variant(parse::<Ipv6Addr>(gateway)?.to_string()));
+            }
+        },
+        None => {
+            ipv6.insert(String::from("method"), variant(String::from("auto")));
+        }
+    }
+
+    if !dns.is_empty() {
+        let servers: Vec<Vec<u8>> = dns.iter().map(|server| server.octets().to_vec()).collect();
+        ipv6.insert(String::from("dns"), variant(servers));
+        ipv6.insert(String::from("ignore-auto-dns"), variant(true));
+    }
+
+    Ok(ipv6)
+}
+
+impl NetworkProfile {
+    fn password(&self) -> Result<String> {
+        self.password.clone()
+            .ok_or_else(|| format!("A password is required for {}", self.ssid))
+    }
+
+    fn eap_settings(&self, certificates: &CertificateStore) -> Result<PropMap> {
+        let enterprise = self.enterprise.clone().unwrap_or_default();
+        let identity = self.identity.clone()
+            .ok_or_else(|| format!("An identity is required for {}", self.ssid))?;
+
+        let mut eap = PropMap::new();
+        eap.insert(String::from("identity"), variant(identity));
+
+        match enterprise.method {
+            EapMethod::Peap | EapMethod::Ttls => {
+                let (method, phase2) = match (enterprise.method, enterprise.phase2) {
+                    (EapMethod::Peap, Phase2Auth::Mschapv2) => ("peap", "mschapv2"),
+                    (EapMethod::Peap, Phase2Auth::Pap) => ("peap", "pap"),
+                    (_, Phase2Auth::Mschapv2) => ("ttls", "mschapv2"),
+                    (_, Phase2Auth::Pap) => ("ttls", "pap")
+                };
+
+                eap.insert(String::from("eap"), variant(vec![method.to_string()]));
+                eap.insert(String::from("phase2-auth"), variant(phase2.to_string()));
+                eap.insert(String::from("password"), variant(self.password()?));
+            },
+            EapMethod::Tls => {
+                let client_certificate = enterprise.client_certificate.as_deref()
+                    .ok_or_else(|| format!("A client certificate is required for {}", self.ssid))?;
+
+                let private_key = enterprise.private_key.as_deref()
+                    .ok_or_else(|| format!("A private key is required for {}", self.ssid))?;
+
+                eap.insert(String::from("eap"), variant(vec![String::from("tls")]));
+                eap.insert(String::from("client-cert"), certificate(certificates, client_certificate)?);
+                eap.insert(String::from("private-key"), certificate(certificates, private_key)?);
+
+                if let Some(password) = enterprise.private_key_password {
+                    eap.insert(String::from("private-key-password"), variant(password));
+                }
+            }
+        }
+
+        if let Some(anonymous_identity) = enterprise.anonymous_identity {
+            eap.insert(String::from("anonymous-identity"), variant(anonymous_identity));
+        }
+
+        if let Some(domain_suffix_match) = enterprise.domain_suffix_match {
+            eap.insert(String::from("domain-suffix-match"), variant(domain_suffix_match));
+        }
+
+        if let Some(ca_certificate) = enterprise.ca_certificate.as_deref() {
+            eap.insert(String::from("ca-cert"), certificate(certificates, ca_certificate)?);
+        }
+
+        Ok(eap)
+    }
+
+    /// `sae` tells whether the wifi of the device can join WPA3 networks
+    pub(super) fn settings(&self, certificates: &CertificateStore, sae: bool) -> Result<Settings> {
+        let mut connection = PropMap::new();
+        connection.insert(String::from("id"), variant(self.ssid.clone()));
+        connection.insert(String::from("type"), variant(WIFI_CONNECTION.to_string()));
+        connection.insert(String::from("autoconnect"), variant(true));
+
+        let mut wireless = PropMap::new();
+        wireless.insert(String::from("ssid"), variant(self.ssid.as_bytes().to_vec()));
+        wireless.insert(String::from("mode"), variant(String::from("infrastructure")));
+        wireless.insert(String::from("hidden"), variant(self.hidden));
+
+        let mut settings = Settings::new();
+        let mut security = PropMap::new();
+        match self.security {
+            WifiSecurity::None => {},
+            WifiSecurity::Wep => {
+                security.insert(String::from("key-mgmt"), variant(String::from("none")));
+                security.insert(String::from("wep-key0"), variant(self.password()?));
+                // the key is given as is, either in hex or ascii
+                security.insert(String::from("wep-key-type"), variant(1u32));
+            },
+            WifiSecurity::Wpa => {
+                security.insert(String::from("key-mgmt"), variant(String::from("wpa-psk")));
+                security.insert(String::from("psk"), variant(self.password()?));
+            },
+            WifiSecurity::Wpa3 | WifiSecurity::Wpa2Wpa3 if sae => {
+                security.insert(String::from("key-mgmt"), variant(String::from("sae")));
+                security.insert(String::from("psk"), variant(self.password()?));
+            },
+            WifiSecurity::Wpa3 => {
+                return Err(format!("{} only accepts WPA3, which the wifi of this device does not support", self.ssid))
+            },
+            WifiSecurity::Wpa2Wpa3 => {
+                // joins with WPA2 when the wifi driver has no SAE support,
+                // with management frame protection enabled whenever the access point offers it
+                security.insert(String::from("key-mgmt"), variant(String::from("wpa-psk")));
+                security.insert(String::from("psk"), variant(self.password()?));
+                security.insert(String::from("pmf"), variant(PMF_OPTIONAL));
+            },
+            WifiSecurity::Enterprise => {
+                security.insert(String::from("key-mgmt"), variant(String::from("wpa-eap")));
+                settings.insert(String::from("802-1x"), self.eap_settings(certificates)?);
+            }
+        }
+
+        if !security.is_empty() {
+            wireless.insert(String::from("security"), variant(String::from("802-11-wireless-security")));
+            settings.insert(String::from("802-11-wireless-security"), security);
+        }
+
+        let mut ipv4_dns = Vec::new();
+        let mut ipv6_dns = Vec::new();
+        for server in &self.ip.dns {
+            match server.parse() {
+                Ok(IpAddr::V4(server)) => ipv4_dns.push(server),
+                Ok(IpAddr::V6(server)) => ipv6_dns.push(server),
+                Err(_) => return Err(format!("Invalid address {}", server))
+            }
+        }
+
+        // NetworkManager can only hand out a proxy auto-config url, so the proxy is
+        // kept with the connection for the server connection to pick up
+        if let Some(proxy) = &self.ip.proxy {
+            let url = Url::parse(proxy)
+                .map_err(|err| format!("Invalid proxy {}: {}", proxy, err))?;
+
+            if url.scheme() != "http" {
+                return Err(format!("Only http proxies are supported, not {}", proxy))
+            }
+
+            let mut user = PropMap::new();
+            user.insert(String::from("data"), variant(HashMap::from([(HTTP_PROXY_KEY.to_string(), proxy.clone())])));
+            settings.insert(String::from("user"), user);
+        }
+
+        settings.insert(String::from("connection"), connection);
+        settings.insert(String::from(WIFI_CONNECTION), wireless);
+        settings.insert(String::from("ipv4"), ipv4_settings(&self.ip, &ipv4_dns)?);
+        settings.insert(String::from("ipv6"), ipv6_settings(&self.ip, &ipv6_dns)?);
+        Ok(settings)
+    }
+}
@@ -0,0 +1,281 @@
+use std::{collections::HashMap, net::Ipv6Addr, thread, time::{Duration, Instant}};
+
+use dbus::{arg::{PropMap, RefArg, Variant}, blocking::{Connection, stdintf::org_freedesktop_dbus::Properties}, Path};
+
+use serde::Serialize;
+
+use crate::util::{Result, ToErrString};
+
+use super::profile::{HTTP_PROXY_KEY, WifiSecurity};
+
+const NM_BUS_NAME: &str = "org.freedesktop.NetworkManager";
+const NM_PATH: &str = "/org/freedesktop/NetworkManager";
+const NM_INTERFACE: &str = "org.freedesktop.NetworkManager";
+const CONNECTION_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings.Connection";
+const ACTIVE_INTERFACE: &str = "org.freedesktop.NetworkManager.Connection.Active";
+const ACCESS_POINT_INTERFACE: &str = "org.freedesktop.NetworkManager.AccessPoint";
+const DEVICE_INTERFACE: &str = "org.freedesktop.NetworkManager.Device";
+const IP4_CONFIG_INTERFACE: &str = "org.freedesktop.NetworkManager.IP4Config";
+const IP6_CONFIG_INTERFACE: &str = "org.freedesktop.NetworkManager.IP6Config";
+const SUPPLICANT_BUS_NAME: &str = "fi.w1.wpa_supplicant1";
+const SUPPLICANT_PATH: &str = "/fi/w1/wpa_supplicant1";
+const SUPPLICANT_INTERFACE: &str = "fi.w1.wpa_supplicant1";
+const SUPPLICANT_IFACE_INTERFACE: &str = "fi.w1.wpa_supplicant1.Interface";
+const TIMEOUT: Duration = Duration::from_secs(5);
+// the check downloads a page from the internet
+const CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// NMActiveConnectionState
+const ACTIVE_ACTIVATED: u32 = 2;
+const ACTIVE_DEACTIVATING: u32 = 3;
+const ACTIVE_DEACTIVATED: u32 = 4;
+
+const ACTIVATION_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub(super) const ACTIVATION_FAILED: &str = "Failed to establish connection";
+pub(super) const ACTIVATION_TOO_LONG: &str = "The connection took too long to establish";
+
+// settings whose secrets are not returned along with the other settings
+const SECRET_SETTINGS: [&str; 2] = ["802-11-wireless-security", "802-1x"];
+
+pub(super) type Settings = HashMap<String, PropMap>;
+
+// how far the activation of a connection got
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Activation {
+    Activating,
+    Activated,
+    Failed
+}
+
+// access to the saved connections and their activation, which the network-manager crate does not expose
+pub(super) struct NmSettings {
+    bus: Connection
+}
+
+impl NmSettings {
+    pub fn new() -> Result<Self> {
+        let bus = Connection::new_system()
+            .or_err_str()?;
+
+        Ok(Self { bus })
+    }
+
+    pub fn get(&self, path: &str) -> Result<Settings> {
+        let (settings,): (Settings,) = self.bus.with_proxy(NM_BUS_NAME, path, TIMEOUT)
+            .method_call(CONNECTION_INTERFACE, "GetSettings", ())
+            .or_err_str()?;
+
+        Ok(settings)
+    }
+
+    // NetworkManager replaces every setting on update, so the secrets are fetched first to keep them
+    pub fn update(&self, path: &str, change: impl FnOnce(&mut Settings)) -> Result<()> {
+        let proxy = self.bus.with_proxy(NM_BUS_NAME, path, TIMEOUT);
+        let mut settings = self.get(path)?;
+
+        for setting in SECRET_SETTINGS {
+            if !settings.contains_key(setting) {
+                continue
+            }
+
+            // fails when the connection has no secrets
+            if let Ok((secrets,)) = proxy.method_call::<(Settings,), _, _, _>(CONNECTION_INTERFACE, "GetSecrets", (setting,)) {
+                for (name, values) in secrets {
+                    settings.entry(name)
+                        .or_default()
+                        .extend(values);
+                }
+            }
+        }
+
+        change(&mut settings);
+
+        proxy.method_call(CONNECTION_INTERFACE, "Update", (settings,))
+            .or_err_str()
+    }
+
+    pub fn delete(&self, path: &str) -> Result<()> {
+        self.bus.with_proxy(NM_BUS_NAME, path, TIMEOUT)
+            .method_call(CONNECTION_INTERFACE, "Delete", ())
+            .or_err_str()
+    }
+
+    // saves the connection and starts activating it on the device,
+    // returning the paths of the saved and of the active connection
+    pub fn add_and_activate(&self, settings: Settings, device: &str, access_point: Option<&str>) -> Result<(String, String)> {
+        let device = Path::new(device)?;
+        let access_point = Path::new(access_point.unwrap_or("/"))?;
+
+        let (path, active): (Path, Path) = self.bus.with_proxy(NM_BUS_NAME, NM_PATH, TIMEOUT)
+            .method_call(NM_INTERFACE, "AddAndActivateConnection", (settings, device, access_point))
+            .or_err_str()?;
+
+        Ok((path.to_string(), active.to_string()))
+    }
+
+    pub fn activation(&self, active: &str) -> Activation {
+        // the active connection goes away when the activation fails
+        let state: u32 = self.bus.with_proxy(NM_BUS_NAME, active, TIMEOUT)
+            .get(ACTIVE_INTERFACE, "State")
+            .unwrap_or(ACTIVE_DEACTIVATED);
+
+        match state {
+            ACTIVE_ACTIVATED => Activation::Activated,
+            ACTIVE_DEACTIVATING | ACTIVE_DEACTIVATED => Activation::Failed,
+            _ => Activation::Activating
+        }
+    }
+
+    pub fn wait_for_activation(&self, active: &str, limit: Duration) -> Result<()> {
+        let start = Instant::now();
+
+        loop {
+            match self.activation(active) {
+                Activation::Activated => return Ok(()),
+                Activation::Failed => return Err(String::from(ACTIVATION_FAILED)),
+                Activation::Activating if start.elapsed() > limit => return Err(String::from(ACTIVATION_TOO_LONG)),
+                Activation::Activating => thread::sleep(ACTIVATION_POLL_INTERVAL)
+            }
+        }
+    }
+
+    // the state of the device and the reason it got there, which tells why an activation failed
+    pub fn device_state(&self, device: &str) -> Result<(u32, u32)> {
+        self.bus.with_proxy(NM_BUS_NAME, device, TIMEOUT)
+            .get(DEVICE_INTERFACE, "StateReason")
+            .or_err_str()
+    }
+
+    // checks again whether the internet is reachable, returning the NMConnectivityState
+    pub fn check_connectivity(&self) -> Result<u32> {
+        let (connectivity,): (u32,) = self.bus.with_proxy(NM_BUS_NAME, NM_PATH, CONNECTIVITY_TIMEOUT)
+            .method_call(NM_INTERFACE, "CheckConnectivity", ())
+            .or_err_str()?;
+
+        Ok(connectivity)
+    }
+
+    // the network-manager crate does not know about WPA3, so the flags are read again
+    pub fn access_point_security(&self, path: &str) -> Result<WifiSecurity> {
+        let proxy = self.bus.with_proxy(NM_BUS_NAME, path, TIMEOUT);
+        let flags: u32 = proxy.get(ACCESS_POINT_INTERFACE, "Flags").or_err_str()?;
+        let wpa_flags: u32 = proxy.get(ACCESS_POINT_INTERFACE, "WpaFlags").or_err_str()?;
+        let rsn_flags: u32 = proxy.get(ACCESS_POINT_INTERFACE, "RsnFlags").or_err_str()?;
+
+        Ok(WifiSecurity::from_flags(flags, wpa_flags, rsn_flags))
+    }
+
+    // NetworkManager does not tell whether the wifi driver can join WPA3 networks, so wpa_supplicant is asked;
+    // it is assumed that it cannot when wpa_supplicant does not answer
+    pub fn supports_sae(&self, interface: &str) -> bool {
+        let capabilities: Option<PropMap> = self.bus.with_proxy(SUPPLICANT_BUS_NAME, SUPPLICANT_PATH, TIMEOUT)
+            .method_call::<(Path,), _, _, _>(SUPPLICANT_INTERFACE, "GetInterface", (interface,))
+            .ok()
+            .and_then(|(path,)| self.bus.with_proxy(SUPPLICANT_BUS_NAME, path, TIMEOUT)
+                .get(SUPPLICANT_IFACE_INTERFACE, "Capabilities")
+                .ok());
+
+        capabilities.as_ref()
+            .and_then(|capabilities| capabilities.get("KeyMgmt"))
+            .and_then(|key_mgmt| key_mgmt.0.as_iter())
+            .is_some_and(|mut key_mgmt| key_mgmt.any(|method| method.as_str() == Some("sae")))
+    }
+
+    fn ip_details(&self, path: &Path, interface: &str) -> IpDetails {
+        // devices without a configuration for the version point to "/"
+        if &**path == "/" {
+            return IpDetails::default()
+        }
+
+        let proxy = self.bus.with_proxy(NM_BUS_NAME, path, TIMEOUT);
+        let address_data: Vec<PropMap> = proxy.get(interface, "AddressData").unwrap_or_default();
+        let addresses = address_data.iter()
+            .filter_map(|data| Some(format!("{}/{}", data.get("address")?.0.as_str()?, data.get("prefix")?.0.as_u64()?)))
+            .collect();
+
+        let gateway: String = proxy.get(interface, "Gateway").unwrap_or_default();
+        let dns = match interface {
+            IP4_CONFIG_INTERFACE => proxy.get::<Vec<PropMap>>(interface, "NameserverData")
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|data| Some(data.get("address")?.0.as_str()?.to_string()))
+                .collect(),
+            _ => proxy.get::<Vec<Vec<u8>>>(interface, "Nameservers")
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|server| <[u8; 16]>::try_from(server).ok())
+                .map(|server| Ipv6Addr::from(server).to_string())
+                .collect()
+        };
+
+        IpDetails {
+            addresses,
+            gateway: Some(gateway).filter(|gateway| !gateway.is_empty()),
+            dns
+        }
+    }
+
+    pub fn ip_configuration(&self, device: &str) -> Result<(IpDetails, IpDetails)> {
+        let proxy = self.bus.with_proxy(NM_BUS_NAME, device, TIMEOUT);
+        let ip4_config: Path = proxy.get(DEVICE_INTERFACE, "Ip4Config").or_err_str()?;
+        let ip6_config: Path = proxy.get(DEVICE_INTERFACE, "Ip6Config").or_err_str()?;
+
+        Ok((self.ip_details(&ip4_config, IP4_CONFIG_INTERFACE), self.ip_details(&ip6_config, IP6_CONFIG_INTERFACE)))
+    }
+
+    // the proxy provisioned for the connection that currently routes the traffic
+    pub fn http_proxy(&self) -> Result<Option<String>> {
+        let primary: Path = self.bus.with_proxy(NM_BUS_NAME, NM_PATH, TIMEOUT)
+            .get(NM_INTERFACE, "PrimaryConnection")
+            .or_err_str()?;
+
+        if &*primary == "/" {
+            return Ok(None)
+        }
+
+        let connection: Path = self.bus.with_proxy(NM_BUS_NAME, &primary, TIMEOUT)
+            .get(ACTIVE_INTERFACE, "Connection")
+            .or_err_str()?;
+
+        let proxy = self.get(&connection)?
+            .get("user")
+            .and_then(|user| user.get("data"))
+            .and_then(|data| data.0.as_iter())
+            .and_then(|mut data| loop {
+                // dictionaries are iterated as alternating keys and values
+                let key = data.next()?;
+                let value = data.next()?;
+                if key.as_str() == Some(HTTP_PROXY_KEY) {
+                    break value.as_str().map(String::from)
+                }
+            });
+
+        Ok(proxy)
+    }
+}
+
+/// addresses currently used by the device on one ip version
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IpDetails {
+    /// in cidr notation, e.g. 192.168.1.20/24
+    pub addresses: Vec<String>,
+    pub gateway: Option<String>,
+    pub dns: Vec<String>
+}
+
+// connections with a higher priority are preferred when more than one network is available
+pub(super) fn autoconnect_priority(settings: &Settings) -> i32 {
+    settings.get("connection")
+        .and_then(|connection| connection.get("autoconnect-priority"))
+        .and_then(|priority| priority.0.as_i64())
+        .unwrap_or_default() as i32
+}
+
+pub(super) fn set_autoconnect_priority(settings: &mut Settings, priority: i32) {
+    let connection = settings.entry(String::from("connection"))
+        .or_default();
+
+    connection.insert(String::from("autoconnect"), Variant(Box::new(true)));
+    connection.insert(String::from("autoconnect-priority"), Variant(Box::new(priority)));
+}
@@ -0,0 +1,115 @@
+use std::{thread, time::Duration};
+
+use dbus::{blocking::Connection, message::MatchRule, Message};
+use tokio::sync::broadcast::Sender;
+
+use crate::util::{Result, ToErrString};
+
+const NM_BUS_NAME: &str = "org.freedesktop.NetworkManager";
+const NM_PATH: &str = "/org/freedesktop/NetworkManager";
+const NM_INTERFACE: &str = "org.freedesktop.NetworkManager";
+const NM_WIRELESS_INTERFACE: &str = "org.freedesktop.NetworkManager.Device.Wireless";
+const NM_SETTINGS_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings";
+const NM_CONNECTION_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings.Connection";
+const NM_ACCESS_POINT_INTERFACE: &str = "org.freedesktop.NetworkManager.AccessPoint";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+// while the signals cannot be received, the subscribers read everything again this often
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// something that changed in NetworkManager
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WifiEvent {
+    // the state of the manager, a device or an active connection
+    StateChanged,
+    AccessPointsChanged,
+    SavedNetworksChanged
+}
+
+const ALL_EVENTS: [WifiEvent; 3] = [WifiEvent::StateChanged, WifiEvent::AccessPointsChanged, WifiEvent::SavedNetworksChanged];
+
+// forwards the NetworkManager signals to the subscribers; while the system bus is unavailable,
+// every event is sent periodically instead and the signals are subscribed to again
+pub(super) fn watch(events: Sender<WifiEvent>) {
+    thread::spawn(move || {
+        loop {
+            match subscribe(events.clone()) {
+                Ok(conn) => {
+                    let err = loop {
+                        if let Err(err) = conn.process(Duration::from_secs(60)) {
+                            break err
+                        }
+                    };
+
+                    println!("Stopped watching NetworkManager, polling instead: {}", err);
+                },
+                Err(err) => println!("Unable to watch NetworkManager, polling instead: {}", err)
+            }
+
+            thread::sleep(POLL_INTERVAL);
+            for event in ALL_EVENTS {
+                events.send(event).unwrap_or_default();
+            }
+        }
+    });
+}
+
+fn subscribe(events: Sender<WifiEvent>) -> Result<Connection> {
+    let conn = Connection::new_system()
+        .or_err_str()?;
+
+    let state_rule = MatchRule::new_signal(NM_INTERFACE, "StateChanged")
+        .with_sender(NM_BUS_NAME)
+        .with_path(NM_PATH);
+
+    let tx = events.clone();
+    conn.add_match(state_rule, move |(): (), _: &Connection, _: &Message| {
+        tx.send(WifiEvent::StateChanged).unwrap_or_default();
+        true
+    }).or_err_str()?;
+
+    // the first argument is the interface whose properties changed
+    let properties_rule = MatchRule::new_signal(PROPERTIES_INTERFACE, "PropertiesChanged")
+        .with_sender(NM_BUS_NAME)
+        .with_namespaced_path(NM_PATH);
+
+    let tx = events.clone();
+    conn.add_match(properties_rule, move |(interface,): (String,), _: &Connection, _: &Message| {
+        // the strength of the access points changes all the time, the list is refreshed when they come and go
+        if interface != NM_ACCESS_POINT_INTERFACE {
+            tx.send(WifiEvent::StateChanged).unwrap_or_default();
+        }
+
+        true
+    }).or_err_str()?;
+
+    for member in ["AccessPointAdded", "AccessPointRemoved"] {
+        let ap_rule = MatchRule::new_signal(NM_WIRELESS_INTERFACE, member)
+            .with_sender(NM_BUS_NAME);
+
+        let tx = events.clone();
+        conn.add_match(ap_rule, move |(): (), _: &Connection, _: &Message| {
+            tx.send(WifiEvent::AccessPointsChanged).unwrap_or_default();
+            true
+        }).or_err_str()?;
+    }
+
+    let saved_rules = [
+        (NM_SETTINGS_INTERFACE, "NewConnection"),
+        (NM_SETTINGS_INTERFACE, "ConnectionRemoved"),
+        (NM_CONNECTION_INTERFACE, "Updated")
+    ];
+
+    for (interface, member) in saved_rules {
+        let saved_rule = MatchRule::new_signal(interface, member)
+            .with_sender(NM_BUS_NAME);
+
+        let tx = events.clone();
+        conn.add_match(saved_rule, move |(): (), _: &Connection, _: &Message| {
+            tx.send(WifiEvent::SavedNetworksChanged).unwrap_or_default();
+            true
+        }).or_err_str()?;
+    }
+
+    Ok(conn)
+}
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+// NMDeviceState
+const DEVICE_PREPARE: u32 = 40;
+const DEVICE_CONFIG: u32 = 50;
+const DEVICE_NEED_AUTH: u32 = 60;
+const DEVICE_IP_CONFIG: u32 = 70;
+const DEVICE_IP_CHECK: u32 = 80;
+const DEVICE_SECONDARIES: u32 = 90;
+
+// NMDeviceStateReason given when the network to connect to is not in range
+pub(super) const REASON_SSID_NOT_FOUND: u32 = 53;
+
+/// step reached by the last connection attempt
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProvisioningState {
+    Idle,
+    Connecting,
+    Authenticating,
+    ObtainingIp,
+    Connected,
+    /// connected, but the internet is not reachable or a captive portal is in the way
+    LimitedConnectivity,
+    Failed
+}
+
+impl ProvisioningState {
+    // the wifi device goes through these states while a connection is activated
+    pub(super) fn from_device_state(state: u32) -> Option<Self> {
+        match state {
+            DEVICE_PREPARE => Some(Self::Connecting),
+            DEVICE_CONFIG | DEVICE_NEED_AUTH => Some(Self::Authenticating),
+            DEVICE_IP_CONFIG | DEVICE_IP_CHECK | DEVICE_SECONDARIES => Some(Self::ObtainingIp),
+            _ => None
+        }
+    }
+}
+
+/// progress of the last connection attempt, with the cause when it failed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProvisioningStatus {
+    pub state: ProvisioningState,
+    pub ssid: Option<String>,
+    /// NMDeviceStateReason of the failure, e.g. 7 when the password was refused
+    /// (NetworkManager asks for it again) or 53 when the network was not found
+    pub reason: Option<u32>,
+    pub message: Option<String>
+}
+
+impl Default for ProvisioningStatus {
+    fn default() -> Self {
+        Self {
+            state: ProvisioningState::Idle,
+            ssid: None,
+            reason: None,
+            message: None
+        }
+    }
+}
+
+// shared with the NetworkManager worker, which reports why a connection could not be made
+#[derive(Clone)]
+pub(super) struct Provisioning {
+    status: Arc<watch::Sender<ProvisioningStatus>>,
+    // kept so that sending never fails for lack of receivers
+    receiver: watch::Receiver<ProvisioningStatus>
+}
+
+impl Provisioning {
+    pub fn new() -> Self {
+        let (status, receiver) = watch::channel(ProvisioningStatus::default());
+        Self {
+            status: Arc::new(status),
+            receiver
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ProvisioningStatus> {
+        self.receiver.clone()
+    }
+
+    pub fn state(&self) -> ProvisioningState {
+        self.receiver.borrow().state
+    }
+
+    pub fn report(&self, ssid: &str, state: ProvisioningState, message: Option<String>) {
+        {
+            let status = self.receiver.borrow();
+            if status.state == state && status.ssid.as_deref() == Some(ssid) {
+                return
+            }
+        }
+
+        self.status.send(ProvisioningStatus {
+            state,
+            ssid: Some(ssid.to_string()),
+            reason: None,
+            message
+        }).unwrap_or_default();
+    }
+
+    pub fn fail(&self, ssid: &str, reason: Option<u32>, message: &str) {
+        self.status.send(ProvisioningStatus {
+            state: ProvisioningState::Failed,
+            ssid: Some(ssid.to_string()),
+            reason,
+            message: Some(message.to_string())
+        }).unwrap_or_default();
+    }
+}
@@ -1,60 +1,186 @@
-use std::{any::Any, thread};
+use std::{collections::VecDeque, sync::{Arc, Condvar, Mutex, Weak}, thread, time::{Duration, Instant}};
 
 use network_manager::NetworkManager;
-use tokio::sync::{mpsc::{Sender, Receiver, channel}, Mutex};
+use tokio::{sync::oneshot, time::timeout};
 
 use crate::util::Result;
 
-type WorkResult = Box<dyn Any + Send>;
-type Work = Box<dyn (FnOnce(&NetworkManager) -> Result<WorkResult>) + Send>;
+use super::settings::NmSettings;
 
-struct NmWorkerChannel {
-    tx_w: Sender<Work>,
-    rc_r: Receiver<Result<WorkResult>>
+// so that a worker that cannot even connect to NetworkManager is not restarted in a loop
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+// how often the watchdog looks for a task running past its limit
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+// longest wait behind the other tasks before a caller gives up
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(3 * 60);
+
+// runs a task and sends its result back to whoever is waiting for it
+type Job = Box<dyn FnOnce(&NetworkManager, &NmSettings) + Send>;
+
+struct QueuedJob {
+    job: Job,
+    limit: Duration,
+    // told when the job starts
+    started: oneshot::Sender<()>
+}
+
+// the task being run by the worker of a generation, which is abandoned past the deadline
+#[derive(Clone, Copy)]
+struct RunningJob {
+    generation: u64,
+    deadline: Instant
+}
+
+struct Queue {
+    jobs: VecDeque<QueuedJob>,
+    // the worker thread of an older generation exits once its running task returns
+    generation: u64,
+    running: Option<RunningJob>
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    available: Condvar
+}
+
+// starts a new worker when the thread dies because a task panicked
+// or because NetworkManager could not be reached
+struct RestartOnPanic {
+    shared: Arc<Shared>,
+    generation: u64
+}
+
+impl Drop for RestartOnPanic {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            println!("Restarting the NetworkManager worker");
+            thread::sleep(RESTART_DELAY);
+            restart(&self.shared, self.generation);
+        }
+    }
 }
 
+// the NetworkManager connection is not thread safe, so it is owned by a
+// dedicated thread that runs the tasks one at a time, along with the bus connection of the settings
 pub(super) struct NmWorker {
-    mutex: Mutex<NmWorkerChannel>
+    shared: Arc<Shared>
 }
 
-impl NmWorker {
-    pub fn new() -> Self {
-        let (tx_w, mut rc_w) = channel::<Work>(1);
-        let (tx_r, rc_r) = channel::<Result<WorkResult>>(1);
-
-        thread::spawn(move || {
-            let nm = NetworkManager::new();
-            loop {
-                let rc_r = rc_w.blocking_recv();
-                if let Some(res) = rc_r {
-                    tx_r.blocking_send(res(&nm))
-                        .unwrap_or_default();
+fn spawn_worker(shared: Arc<Shared>, generation: u64) {
+    thread::spawn(move || {
+        let _restart = RestartOnPanic { shared: shared.clone(), generation };
+        let nm = NetworkManager::new();
+        let settings = match NmSettings::new() {
+            Ok(settings) => settings,
+            Err(err) => {
+                println!("Unable to connect to the system bus: {}", err);
+                thread::sleep(RESTART_DELAY);
+                restart(&shared, generation);
+                return
+            }
+        };
+
+        loop {
+            let queued = {
+                let mut queue = shared.queue.lock().unwrap();
+                while queue.generation == generation && queue.jobs.is_empty() {
+                    queue = shared.available.wait(queue).unwrap();
+                }
+
+                if queue.generation != generation {
+                    return
                 }
+
+                let queued = queue.jobs.pop_front().unwrap();
+                queue.running = Some(RunningJob { generation, deadline: Instant::now() + queued.limit });
+                queued
+            };
+
+            queued.started.send(()).unwrap_or_default();
+            (queued.job)(&nm, &settings);
+
+            let mut queue = shared.queue.lock().unwrap();
+            if queue.running.map(|running| running.generation) == Some(generation) {
+                queue.running = None;
             }
-        });
+        }
+    });
+}
+
+// restarts the worker whose task runs past its limit, whether or not its caller still waits for it
+fn spawn_watchdog(shared: Weak<Shared>) {
+    thread::spawn(move || {
+        while let Some(shared) = shared.upgrade() {
+            let stuck = shared.queue.lock().unwrap().running
+                .filter(|running| Instant::now() >= running.deadline);
 
-        NmWorker {
-            mutex: Mutex::new(NmWorkerChannel { tx_w, rc_r })
+            if let Some(running) = stuck {
+                println!("Restarting the stuck NetworkManager worker");
+                restart(&shared, running.generation);
+            }
+
+            drop(shared);
+            thread::sleep(WATCHDOG_INTERVAL);
         }
+    });
+}
+
+// abandons the worker of the given generation, the jobs still queued are run by the new one
+fn restart(shared: &Arc<Shared>, generation: u64) {
+    let mut queue = shared.queue.lock().unwrap();
+    if queue.generation != generation {
+        return
     }
 
-    pub async fn do_task<T : Any + Send>(&self, task: Work) -> Result<T> {
-        let mut g = self.mutex
-            .lock()
-            .await;
+    queue.generation += 1;
+    queue.running = None;
+    spawn_worker(shared.clone(), queue.generation);
+    shared.available.notify_all();
+}
 
-        g.tx_w.send(task)
-            .await
-            .or(Err(String::from("Error executing operation")))?;
+impl NmWorker {
+    pub fn new() -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue { jobs: VecDeque::new(), generation: 0, running: None }),
+            available: Condvar::new()
+        });
+
+        spawn_worker(shared.clone(), 0);
+        spawn_watchdog(Arc::downgrade(&shared));
+        NmWorker { shared }
+    }
+
+    // runs the task on the worker thread, a task whose caller stopped waiting
+    // (because the future was dropped) is not started; `limit` starts once the task does,
+    // so waiting behind other tasks only counts towards QUEUE_TIMEOUT, and the watchdog
+    // restarts the worker past it even when the caller is gone
+    pub async fn do_task<T, F>(&self, limit: Duration, task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&NetworkManager, &NmSettings) -> Result<T> + Send + 'static
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move |nm, settings| {
+            if !tx.is_closed() {
+                tx.send(task(nm, settings)).unwrap_or_default();
+            }
+        });
 
-        let res = g.rc_r.recv()
-            .await
-            .unwrap_or(Err(String::from("Error receiving response")))?;
+        let (started, started_rx) = oneshot::channel();
+        self.shared.queue.lock().unwrap().jobs.push_back(QueuedJob { job, limit, started });
+        self.shared.available.notify_all();
 
-        let res_dc = res.downcast::<T>()
-            .or(Err(String::from("Error converting to output type")))
-            .unwrap();
+        match timeout(QUEUE_TIMEOUT, started_rx).await {
+            Ok(Ok(_)) => {},
+            Ok(Err(_)) => return Err(String::from("The NetworkManager worker stopped")),
+            Err(_) => return Err(String::from("The NetworkManager worker is busy"))
+        }
 
-        Ok(*res_dc)
+        match timeout(limit, rx).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(_)) => Err(String::from("The NetworkManager worker stopped")),
+            // the watchdog restarts the worker
+            Err(_) => Err(String::from("The NetworkManager operation timed out"))
+        }
     }
-}
\ No newline at end of file
+}
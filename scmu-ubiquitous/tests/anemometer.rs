@@ -0,0 +1,83 @@
+use std::{sync::Arc, time::{Duration, Instant, SystemTime}};
+
+use scmu_ubiquitous::{
+    clock::{SharedClock, VirtualClock},
+    gpio::{anemometer::{Anemometer, DEFAULT_CALIBRATION}, pulse::{PulseSource, PulseSourceSimulator}}
+};
+
+// pulses given by the test instead of an interrupt
+struct ManualPulses(Vec<Instant>);
+
+impl PulseSource for ManualPulses {
+    fn take_pulses(&mut self) -> Vec<Instant> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn converts_pulses_to_speed() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let shared: SharedClock = clock.clone();
+
+    // 2.4 km/h per pulse per second by default
+    let mut anemometer = Anemometer::with_source(PulseSourceSimulator::with_clock(5.0, shared.clone()))
+        .with_clock(shared.clone());
+
+    for _ in 0..60 {
+        clock.advance(secs(1));
+        anemometer.measure();
+    }
+
+    let wind = anemometer.measure();
+    assert!((wind.speed - 5.0 * DEFAULT_CALIBRATION).abs() <= 0.1);
+    assert!((wind.gust - 5.0 * DEFAULT_CALIBRATION).abs() <= 0.5);
+
+    // a calibrated anemometer
+    let mut anemometer = Anemometer::with_source(PulseSourceSimulator::with_clock(4.0, shared.clone()))
+        .with_clock(shared)
+        .with_calibration(1.5);
+
+    for _ in 0..60 {
+        clock.advance(secs(1));
+        anemometer.measure();
+    }
+
+    assert!((anemometer.measure().speed - 6.0).abs() <= 0.1);
+}
+
+#[test]
+fn detects_gusts_of_3_seconds() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let shared: SharedClock = clock.clone();
+
+    // a pulse every second, with a burst of 30 pulses in the 3 seconds after 20 s
+    let start = shared.now();
+    let mut pulses: Vec<Instant> = (1..=60)
+        .map(|second| start + secs(second))
+        .collect();
+    pulses.extend((1..=30).map(|i| start + secs(20) + Duration::from_millis(i * 99)));
+    pulses.sort();
+
+    let mut anemometer = Anemometer::with_source(ManualPulses(pulses))
+        .with_clock(shared)
+        .with_calibration(1.0);
+
+    clock.advance(secs(60));
+    let wind = anemometer.measure();
+    assert!((wind.speed - 1.5).abs() <= 0.05);
+    // the burst and the pulses of the second around it
+    assert!(wind.gust >= 10.0 && wind.gust <= 11.5);
+
+    // the average drops after a minute, the gust after 10 minutes
+    clock.advance(secs(61));
+    let wind = anemometer.measure();
+    assert_eq!(wind.speed, 0.0);
+    assert!(wind.gust >= 10.0);
+
+    clock.advance(secs(10 * 60));
+    assert_eq!(anemometer.measure().gust, 0.0);
+}
@@ -0,0 +1,85 @@
+use std::{sync::Arc, time::{Duration, SystemTime}};
+
+use scmu_ubiquitous::{
+    aqi::{caqi_index, eaqi_category, us_epa_index, CaqiCategory, EaqiCategory, PmHistory, UsEpaCategory},
+    clock::{SharedClock, VirtualClock},
+    gpio::particle_matter::ParticleMatter
+};
+
+fn us_epa(pm_25: f32, pm_10: f32) -> (u32, UsEpaCategory) {
+    let index = us_epa_index(pm_25, pm_10);
+    (index.value, index.category)
+}
+
+fn caqi(pm_25: f32, pm_10: f32) -> (u32, CaqiCategory) {
+    let index = caqi_index(pm_25, pm_10);
+    (index.value, index.category)
+}
+
+#[test]
+fn us_epa_pm_25_breakpoints() {
+    assert_eq!(us_epa(0.0, 0.0), (0, UsEpaCategory::Good));
+    assert_eq!(us_epa(9.0, 0.0), (50, UsEpaCategory::Good));
+    // truncated to one decimal before looking up the breakpoints
+    assert_eq!(us_epa(9.05, 0.0), (50, UsEpaCategory::Good));
+    assert_eq!(us_epa(12.0, 0.0), (56, UsEpaCategory::Moderate));
+    assert_eq!(us_epa(55.5, 0.0), (151, UsEpaCategory::Unhealthy));
+    assert_eq!(us_epa(125.5, 0.0), (201, UsEpaCategory::VeryUnhealthy));
+    assert_eq!(us_epa(225.5, 0.0), (301, UsEpaCategory::Hazardous));
+    assert_eq!(us_epa(1000.0, 0.0), (500, UsEpaCategory::Hazardous));
+}
+
+#[test]
+fn us_epa_pm_10_breakpoints() {
+    assert_eq!(us_epa(0.0, 54.0), (50, UsEpaCategory::Good));
+    assert_eq!(us_epa(0.0, 55.0), (51, UsEpaCategory::Moderate));
+    assert_eq!(us_epa(0.0, 155.0), (101, UsEpaCategory::UnhealthyForSensitiveGroups));
+    assert_eq!(us_epa(0.0, 255.0), (151, UsEpaCategory::Unhealthy));
+    assert_eq!(us_epa(0.0, 355.0), (201, UsEpaCategory::VeryUnhealthy));
+    assert_eq!(us_epa(0.0, 604.0), (500, UsEpaCategory::Hazardous));
+}
+
+#[test]
+fn us_epa_takes_the_worst_pollutant() {
+    assert_eq!(us_epa(12.0, 155.0), (101, UsEpaCategory::UnhealthyForSensitiveGroups));
+}
+
+#[test]
+fn caqi_breakpoints() {
+    assert_eq!(caqi(0.0, 0.0), (0, CaqiCategory::VeryLow));
+    assert_eq!(caqi(15.0, 0.0), (25, CaqiCategory::Low));
+    assert_eq!(caqi(0.0, 50.0), (50, CaqiCategory::Medium));
+    assert_eq!(caqi(55.0, 0.0), (75, CaqiCategory::High));
+    assert_eq!(caqi(110.0, 180.0), (100, CaqiCategory::High));
+    // above the grid the index keeps growing
+    assert_eq!(caqi(200.0, 0.0), (141, CaqiCategory::VeryHigh));
+}
+
+#[test]
+fn eaqi_bands() {
+    assert_eq!(eaqi_category(10.0, 20.0), EaqiCategory::Good);
+    assert_eq!(eaqi_category(10.5, 0.0), EaqiCategory::Fair);
+    assert_eq!(eaqi_category(25.0, 0.0), EaqiCategory::Moderate);
+    assert_eq!(eaqi_category(0.0, 100.0), EaqiCategory::Poor);
+    assert_eq!(eaqi_category(0.0, 150.0), EaqiCategory::VeryPoor);
+    assert_eq!(eaqi_category(0.0, 151.0), EaqiCategory::ExtremelyPoor);
+}
+
+#[test]
+fn nowcast_needs_two_of_the_last_three_hours() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let shared: SharedClock = clock.clone();
+    let mut history = PmHistory::new(shared);
+    let pm = ParticleMatter { pm_25_level: 12, pm_10_level: 20 };
+
+    history.record(&pm);
+    let air_quality = history.air_quality();
+    assert!(air_quality.us_epa.is_none());
+    assert_eq!(air_quality.caqi.map(|index| (index.value, index.category)), Some((20, CaqiCategory::VeryLow)));
+    assert_eq!(air_quality.eaqi, Some(EaqiCategory::Fair));
+
+    clock.advance(Duration::from_secs(60 * 60));
+    history.record(&pm);
+    let us_epa = history.air_quality().us_epa.unwrap();
+    assert_eq!((us_epa.value, us_epa.category), (56, UsEpaCategory::Moderate));
+}
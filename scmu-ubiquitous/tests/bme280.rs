@@ -0,0 +1,41 @@
+use scmu_ubiquitous::gpio::bme280::Calibration;
+
+const ADC_T: f64 = 519888.0;
+const ADC_P: f64 = 415148.0;
+
+// registers 0x88 to 0xA1 with the trimming parameters of the compensation example of the datasheet, and dig_H1 last
+fn temperature_pressure_registers(h1: u8) -> [u8; 26] {
+    let words = [
+        27504u16.to_le_bytes(), 26435i16.to_le_bytes(), (-1000i16).to_le_bytes(),
+        36477u16.to_le_bytes(), (-10685i16).to_le_bytes(), 3024i16.to_le_bytes(), 2855i16.to_le_bytes(),
+        140i16.to_le_bytes(), (-7i16).to_le_bytes(), 15500i16.to_le_bytes(), (-14600i16).to_le_bytes(), 6000i16.to_le_bytes()
+    ];
+
+    let mut registers = [0; 26];
+    registers[..24].copy_from_slice(&words.concat());
+    registers[25] = h1;
+    registers
+}
+
+// registers 0xE1 to 0xE7 of dig_H2 = 362, dig_H3 = 0, dig_H4 = 313, dig_H5 = 50 and dig_H6 = 30,
+// with dig_H4 and dig_H5 sharing the nibbles of 0xE5
+const HUMIDITY_REGISTERS: [u8; 7] = [0x6A, 0x01, 0x00, 0x13, 0x29, 0x03, 30];
+
+#[test]
+fn compensates_like_the_datasheet_example() {
+    let calibration = Calibration::parse(&temperature_pressure_registers(75), &HUMIDITY_REGISTERS);
+    let environment = calibration.compensate(ADC_T, ADC_P, 30000.0);
+
+    // 25.08 °C and 100653.27 Pa
+    assert!((environment.temperature - 25.08).abs() < 0.005);
+    assert!((environment.pressure.unwrap() - 1006.5327).abs() < 0.001);
+    assert!((environment.humidity - 55.0).abs() < 0.01);
+}
+
+#[test]
+fn clamps_the_humidity() {
+    let calibration = Calibration::parse(&temperature_pressure_registers(75), &HUMIDITY_REGISTERS);
+
+    assert_eq!(calibration.compensate(ADC_T, ADC_P, 60000.0).humidity, 100.0);
+    assert_eq!(calibration.compensate(ADC_T, ADC_P, 0.0).humidity, 0.0);
+}
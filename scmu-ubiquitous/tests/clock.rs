@@ -0,0 +1,87 @@
+use std::{sync::{Arc, Barrier, Mutex}, thread, time::{Duration, Instant, SystemTime}};
+
+use scmu_ubiquitous::clock::{Clock, VirtualClock};
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn fast_forwards_sleepers_in_deadline_order() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let wakeups = Arc::new(Mutex::new(Vec::new()));
+    // the threads join before any of them sleeps, so that time waits for all of them
+    let barrier = Arc::new(Barrier::new(2));
+    let started = Instant::now();
+
+    let threads: Vec<_> = [("fast", 3, 4), ("slow", 5, 3)]
+        .into_iter()
+        .map(|(name, interval, count)| {
+            let clock = clock.clone();
+            let wakeups = wakeups.clone();
+            let barrier = barrier.clone();
+
+            thread::spawn(move || {
+                let _participant = clock.join();
+                barrier.wait();
+
+                for _ in 0..count {
+                    clock.sleep(secs(interval));
+                    wakeups.lock().unwrap().push((name, clock.elapsed()));
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(*wakeups.lock().unwrap(), vec![
+        ("fast", secs(3)), ("slow", secs(5)), ("fast", secs(6)), ("fast", secs(9)),
+        ("slow", secs(10)), ("fast", secs(12)), ("slow", secs(15))
+    ]);
+
+    assert!(started.elapsed() < secs(1));
+}
+
+#[test]
+fn advance_wakes_sleepers_at_their_deadline() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let _participant = clock.join();
+
+    let sleeper = {
+        let clock = clock.clone();
+        thread::spawn(move || {
+            let _participant = clock.join();
+            clock.sleep(secs(10));
+            clock.elapsed()
+        })
+    };
+
+    // the test thread is awake, so time only moves when it is advanced
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(clock.elapsed(), Duration::ZERO);
+
+    clock.advance(secs(60));
+    assert_eq!(sleeper.join().unwrap(), secs(10));
+    assert_eq!(clock.elapsed(), secs(60));
+}
+
+#[tokio::test]
+async fn async_sleepers_wait_for_the_time_to_move() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let sleep = clock.sleep_async(secs(5));
+
+    let advancer = {
+        let clock = clock.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            clock.advance(secs(5));
+        })
+    };
+
+    sleep.await;
+    assert_eq!(clock.elapsed(), secs(5));
+    advancer.join().unwrap();
+}
@@ -0,0 +1,151 @@
+use scmu_ubiquitous::{
+    aqi::{AirQuality, AqiThreshold, CaqiCategory, Index},
+    controller::{close_reason, decide, CloseReason, Decision, OpenReason, Readings, Thresholds},
+    gpio::{anemometer::Wind, co2::Co2, particle_matter::ParticleMatter}
+};
+
+fn pm(pm_25_level: u16, pm_10_level: u16) -> ParticleMatter {
+    ParticleMatter { pm_25_level, pm_10_level }
+}
+
+fn readings<'a>(outdoor: &'a ParticleMatter, indoor: &'a ParticleMatter) -> Readings<'a> {
+    Readings {
+        rain: None,
+        pm: Some(outdoor),
+        indoor_pm: Some(indoor),
+        wind: None,
+        outdoor: None,
+        indoor: None,
+        co2: None,
+        air_quality: None
+    }
+}
+
+fn clean_air<'a>(outdoor: &'a ParticleMatter, co2: Option<&'a Co2>, wind: Option<&'a Wind>) -> Readings<'a> {
+    Readings {
+        rain: None,
+        pm: Some(outdoor),
+        indoor_pm: None,
+        wind,
+        outdoor: None,
+        indoor: None,
+        co2,
+        air_quality: None
+    }
+}
+
+fn comparing() -> Thresholds {
+    Thresholds {
+        compare_particle_matter: true,
+        pm_margin: 10,
+        ..Thresholds::default()
+    }
+}
+
+#[test]
+fn compares_both_particle_sizes() {
+    let thresholds = comparing();
+
+    let (outdoor, indoor) = (pm(5, 60), pm(30, 40));
+    assert_eq!(decide(&thresholds, &readings(&outdoor, &indoor), true), Decision::Close(CloseReason::OutdoorDirtier));
+
+    let (outdoor, indoor) = (pm(5, 20), pm(30, 40));
+    assert_eq!(decide(&thresholds, &readings(&outdoor, &indoor), true), Decision::Open(OpenReason::CleanerOutside));
+
+    // only pm 10 is cleaner outside by the margin
+    let (outdoor, indoor) = (pm(25, 20), pm(30, 40));
+    assert_eq!(decide(&thresholds, &readings(&outdoor, &indoor), true), Decision::Open(OpenReason::CleanerOutside));
+}
+
+#[test]
+fn keeps_the_window_within_the_margin() {
+    let (outdoor, indoor) = (pm(25, 35), pm(30, 40));
+    assert_eq!(decide(&comparing(), &readings(&outdoor, &indoor), true), Decision::Keep);
+}
+
+#[test]
+fn checks_particle_matter_below_the_aqi_threshold() {
+    let thresholds = Thresholds {
+        pm_25: 20,
+        aqi: Some(AqiThreshold::Caqi(CaqiCategory::Medium)),
+        ..Thresholds::default()
+    };
+
+    let outdoor = pm(30, 10);
+    let low = AirQuality {
+        us_epa: None,
+        caqi: Some(Index { value: 40, category: CaqiCategory::Low }),
+        eaqi: None
+    };
+
+    let high = AirQuality {
+        caqi: Some(Index { value: 80, category: CaqiCategory::High }),
+        ..low
+    };
+
+    let mut readings = Readings {
+        rain: None,
+        pm: Some(&outdoor),
+        indoor_pm: None,
+        wind: None,
+        outdoor: None,
+        indoor: None,
+        co2: None,
+        air_quality: Some(&low)
+    };
+
+    assert_eq!(close_reason(&thresholds, &readings), Some(CloseReason::Pm25));
+
+    readings.air_quality = Some(&high);
+    assert_eq!(close_reason(&thresholds, &readings), Some(CloseReason::AirQuality));
+}
+
+#[test]
+fn opens_to_ventilate_above_the_co2_limit() {
+    let thresholds = Thresholds::default();
+    let outdoor = pm(10, 10);
+
+    let high = Co2 { concentration: 1200 };
+    assert_eq!(decide(&thresholds, &clean_air(&outdoor, Some(&high), None), true), Decision::Open(OpenReason::Ventilation));
+
+    let limit = Co2 { concentration: 1000 };
+    assert_eq!(decide(&thresholds, &clean_air(&outdoor, Some(&limit), None), true), Decision::Keep);
+
+    // without a co2 sensor the window is open whenever the air outside is clear
+    assert_eq!(decide(&thresholds, &clean_air(&outdoor, None, None), true), Decision::Open(OpenReason::ClearAir));
+}
+
+#[test]
+fn closes_once_the_air_is_renewed() {
+    let thresholds = Thresholds::default();
+    let outdoor = pm(10, 10);
+
+    // stays open down to 200 ppm below the limit
+    let ventilated = Co2 { concentration: 850 };
+    assert_eq!(decide(&thresholds, &clean_air(&outdoor, Some(&ventilated), None), false), Decision::Keep);
+
+    let renewed = Co2 { concentration: 799 };
+    assert_eq!(decide(&thresholds, &clean_air(&outdoor, Some(&renewed), None), false), Decision::Close(CloseReason::AirRenewed));
+
+    // a closed window is kept closed
+    assert_eq!(decide(&thresholds, &clean_air(&outdoor, Some(&renewed), None), true), Decision::Keep);
+}
+
+#[test]
+fn closes_on_strong_wind_and_gusts() {
+    let thresholds = Thresholds::default();
+    let outdoor = pm(10, 10);
+
+    let calm = Wind { speed: 10.0, gust: 15.0 };
+    assert_eq!(close_reason(&thresholds, &clean_air(&outdoor, None, Some(&calm))), None);
+
+    let windy = Wind { speed: 10.5, gust: 20.0 };
+    assert_eq!(close_reason(&thresholds, &clean_air(&outdoor, None, Some(&windy))), Some(CloseReason::Wind));
+
+    let gusty = Wind { speed: 5.0, gust: 15.5 };
+    assert_eq!(close_reason(&thresholds, &clean_air(&outdoor, None, Some(&gusty))), Some(CloseReason::Gust));
+
+    // even when the room needs to be ventilated
+    let high = Co2 { concentration: 1500 };
+    assert_eq!(decide(&thresholds, &clean_air(&outdoor, Some(&high), Some(&gusty)), true), Decision::Close(CloseReason::Gust));
+}
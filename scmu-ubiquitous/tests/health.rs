@@ -0,0 +1,174 @@
+use std::{sync::Arc, time::{Duration, SystemTime}};
+
+use scmu_ubiquitous::{
+    clock::{SharedClock, VirtualClock},
+    config::HealthConfig,
+    gpio::{environment::Environment, particle_matter::ParticleMatter, rain::Rain},
+    health::{FailSafeAction, FailSafePolicy, Fault, HealthCheck, HealthReport, HealthState, SensorHealth}
+};
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+fn environment(temperature: f32) -> Environment {
+    Environment {
+        temperature,
+        humidity: 50.0,
+        pressure: None
+    }
+}
+
+fn check(config: &HealthConfig) -> (Arc<VirtualClock>, HealthCheck) {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let shared: SharedClock = clock.clone();
+    (clock, HealthCheck::new(config, shared))
+}
+
+fn state(check: &HealthCheck) -> (HealthState, Option<Fault>) {
+    let health = check.health();
+    (health.state, health.fault)
+}
+
+#[test]
+fn fails_after_too_many_read_errors() {
+    let (_, mut check) = check(&HealthConfig::default());
+
+    assert!(check.check::<Environment>(None, &[]).is_none());
+    assert_eq!(state(&check), (HealthState::Degraded, Some(Fault::ReadError)));
+
+    check.check::<Environment>(None, &[]);
+    check.check::<Environment>(None, &[]);
+    assert_eq!(state(&check), (HealthState::Failed, Some(Fault::ReadError)));
+
+    // a valid reading recovers the sensor
+    assert!(check.check(Some(environment(20.0)), &[]).is_some());
+    assert_eq!(state(&check), (HealthState::Ok, None));
+}
+
+#[test]
+fn fails_without_a_valid_reading_before_the_timeout() {
+    let config = HealthConfig {
+        max_read_errors: 100,
+        ..HealthConfig::default()
+    };
+    let (clock, mut check) = check(&config);
+
+    check.check(Some(environment(20.0)), &[]);
+    clock.advance(secs(60));
+    check.check::<Environment>(None, &[]);
+    assert_eq!(state(&check), (HealthState::Degraded, Some(Fault::ReadError)));
+
+    clock.advance(secs(1));
+    check.check::<Environment>(None, &[]);
+    assert_eq!(state(&check), (HealthState::Failed, Some(Fault::Timeout)));
+}
+
+#[test]
+fn detects_stuck_readings() {
+    let (_, check) = check(&HealthConfig::default());
+    let mut check = check.with_stuck_detection(3);
+
+    for _ in 0..3 {
+        check.check(Some(environment(20.0)), &[]);
+    }
+    assert_eq!(state(&check), (HealthState::Ok, None));
+
+    // the stuck reading is still used, but the sensor is degraded
+    assert!(check.check(Some(environment(20.0)), &[]).is_some());
+    assert_eq!(state(&check), (HealthState::Degraded, Some(Fault::Stuck)));
+
+    check.check(Some(environment(20.5)), &[]);
+    assert_eq!(state(&check), (HealthState::Ok, None));
+}
+
+#[test]
+fn detects_readings_stuck_for_too_long() {
+    let (clock, check) = check(&HealthConfig::default());
+    let mut check = check.with_stuck_duration(secs(24 * 60 * 60));
+    let dry = || Rain { is_raining: false, intensity: None };
+
+    check.check(Some(dry()), &[]);
+    for _ in 0..23 {
+        clock.advance(secs(60 * 60));
+        check.check(Some(dry()), &[]);
+    }
+    assert_eq!(state(&check), (HealthState::Ok, None));
+
+    clock.advance(secs(60 * 60));
+    check.check(Some(dry()), &[]);
+    assert_eq!(state(&check), (HealthState::Degraded, Some(Fault::Stuck)));
+}
+
+#[test]
+fn rejects_values_out_of_range() {
+    let (_, mut check) = check(&HealthConfig::default());
+
+    assert!(check.check(Some(environment(90.0)), &[]).is_none());
+    assert_eq!(state(&check), (HealthState::Degraded, Some(Fault::OutOfRange)));
+
+    let pressure = Environment {
+        pressure: Some(200.0),
+        ..environment(20.0)
+    };
+    assert!(check.check(Some(pressure), &[]).is_none());
+    assert!(check.check(Some(environment(-45.0)), &[]).is_none());
+    assert_eq!(state(&check), (HealthState::Failed, Some(Fault::OutOfRange)));
+}
+
+#[test]
+fn reports_the_faults_of_the_sps30() {
+    let (_, mut check) = check(&HealthConfig::default());
+    let pm = ParticleMatter { pm_25_level: 10, pm_10_level: 12 };
+
+    assert!(check.check(Some(pm), &[Fault::FanSpeed]).is_some());
+    assert_eq!(state(&check), (HealthState::Degraded, Some(Fault::FanSpeed)));
+
+    assert!(check.check(Some(pm), &[Fault::FanSpeed, Fault::LaserFailure]).is_none());
+    assert_eq!(state(&check), (HealthState::Failed, Some(Fault::LaserFailure)));
+
+    assert!(check.check(Some(pm), &[Fault::FanFailure]).is_none());
+    assert_eq!(state(&check), (HealthState::Failed, Some(Fault::FanFailure)));
+}
+
+const OK: SensorHealth = SensorHealth { state: HealthState::Ok, fault: None };
+const DEGRADED: SensorHealth = SensorHealth { state: HealthState::Degraded, fault: Some(Fault::Stuck) };
+const FAILED: SensorHealth = SensorHealth { state: HealthState::Failed, fault: Some(Fault::ReadError) };
+
+fn healthy() -> HealthReport {
+    HealthReport {
+        rain: OK,
+        outdoor_pm: OK,
+        indoor_pm: Some(OK),
+        wind: OK,
+        outdoor: OK,
+        indoor: OK,
+        co2: Some(OK)
+    }
+}
+
+#[test]
+fn takes_the_most_conservative_action_of_the_failed_sensors() {
+    let policy = FailSafePolicy::default();
+    assert_eq!(policy.action(&healthy()), FailSafeAction::Ignore);
+
+    // only failed sensors count
+    let report = HealthReport { rain: DEGRADED, ..healthy() };
+    assert_eq!(policy.action(&report), FailSafeAction::Ignore);
+
+    let report = HealthReport { co2: Some(FAILED), outdoor: FAILED, ..healthy() };
+    assert_eq!(policy.action(&report), FailSafeAction::Ignore);
+
+    let report = HealthReport { co2: Some(FAILED), wind: FAILED, ..healthy() };
+    assert_eq!(policy.action(&report), FailSafeAction::Close);
+
+    let policy = FailSafePolicy {
+        co2: FailSafeAction::Keep,
+        wind: FailSafeAction::Ignore,
+        ..FailSafePolicy::default()
+    };
+    assert_eq!(policy.action(&report), FailSafeAction::Keep);
+
+    let report = HealthReport { indoor_pm: Some(FAILED), ..report };
+    assert_eq!(policy.action(&report), FailSafeAction::Close);
+}
@@ -0,0 +1,113 @@
+use std::{env, fs, process, time::Duration};
+
+use scmu_ubiquitous::history::{History, Point, Resolution, Retention};
+
+// on the hour
+const START: i64 = 1_699_999_200;
+const HOUR: i64 = 60 * 60;
+const DAY: i64 = 24 * HOUR;
+
+fn open(name: &str, retention: Retention) -> History {
+    let path = env::temp_dir().join(format!("scmu-history-{}-{}.db", name, process::id()));
+    let path = path.to_str().unwrap().to_string();
+    fs::remove_file(&path).unwrap_or_default();
+
+    History::open(&path, retention).unwrap()
+}
+
+fn retention() -> Retention {
+    Retention {
+        raw: Duration::from_secs(HOUR as u64),
+        minute: Duration::from_secs(DAY as u64),
+        hour: Duration::from_secs(7 * DAY as u64)
+    }
+}
+
+fn summary(points: &[Point]) -> Vec<(i64, &str, f64, f64, f64)> {
+    points.iter()
+        .map(|point| (point.timestamp, point.metric.as_str(), point.min, point.avg, point.max))
+        .collect()
+}
+
+fn timestamps(history: &History, resolution: Resolution) -> Vec<i64> {
+    history.query(0, i64::MAX, resolution, None)
+        .unwrap()
+        .iter()
+        .map(|point| point.timestamp)
+        .collect()
+}
+
+#[test]
+fn rolls_up_the_readings_per_resolution() {
+    let mut history = open("rollups", retention());
+
+    history.record(START, &[("temperature", 10.0), ("humidity", 50.0)]).unwrap();
+    history.record(START + 30, &[("temperature", 20.0)]).unwrap();
+    history.record(START + 90, &[("temperature", 30.0)]).unwrap();
+    history.record(START + HOUR, &[("temperature", 40.0)]).unwrap();
+
+    let raw = history.query(START, START + HOUR, Resolution::Raw, Some("temperature")).unwrap();
+    assert_eq!(summary(&raw), vec![
+        (START, "temperature", 10.0, 10.0, 10.0),
+        (START + 30, "temperature", 20.0, 20.0, 20.0),
+        (START + 90, "temperature", 30.0, 30.0, 30.0),
+        (START + HOUR, "temperature", 40.0, 40.0, 40.0)
+    ]);
+
+    // the bucket the start falls in is included
+    let minutes = history.query(START + 30, START + HOUR, Resolution::Minute, None).unwrap();
+    assert_eq!(summary(&minutes), vec![
+        (START, "humidity", 50.0, 50.0, 50.0),
+        (START, "temperature", 10.0, 15.0, 20.0),
+        (START + 60, "temperature", 30.0, 30.0, 30.0),
+        (START + HOUR, "temperature", 40.0, 40.0, 40.0)
+    ]);
+
+    let hours = history.query(START, START + HOUR, Resolution::Hour, Some("temperature")).unwrap();
+    assert_eq!(summary(&hours), vec![
+        (START, "temperature", 10.0, 20.0, 30.0),
+        (START + HOUR, "temperature", 40.0, 40.0, 40.0)
+    ]);
+}
+
+#[test]
+fn prunes_each_resolution_after_its_retention() {
+    let mut history = open("prune", retention());
+
+    history.record(START, &[("temperature", 10.0)]).unwrap();
+    history.record_window_event(START, true, "Rain").unwrap();
+
+    history.record(START + 2 * HOUR, &[("temperature", 20.0)]).unwrap();
+    assert_eq!(timestamps(&history, Resolution::Raw), vec![START + 2 * HOUR]);
+    assert_eq!(timestamps(&history, Resolution::Minute), vec![START, START + 2 * HOUR]);
+
+    history.record(START + 2 * DAY, &[("temperature", 30.0)]).unwrap();
+    assert_eq!(timestamps(&history, Resolution::Minute), vec![START + 2 * DAY]);
+    assert_eq!(timestamps(&history, Resolution::Hour), vec![START, START + 2 * HOUR, START + 2 * DAY]);
+    assert_eq!(history.window_events(0, i64::MAX).unwrap().len(), 1);
+
+    // window events are kept as long as the hour rollups
+    history.record(START + 8 * DAY, &[("temperature", 40.0)]).unwrap();
+    assert_eq!(timestamps(&history, Resolution::Hour), vec![START + 2 * DAY, START + 8 * DAY]);
+    assert!(history.window_events(0, i64::MAX).unwrap().is_empty());
+}
+
+#[test]
+fn lists_the_window_events_in_a_range() {
+    let mut history = open("events", retention());
+
+    history.record_window_event(START + 20, false, "ClearAir").unwrap();
+    history.record_window_event(START, true, "Rain").unwrap();
+    history.record_window_event(START + 40, true, "Gust").unwrap();
+
+    let events: Vec<(i64, bool, String)> = history.window_events(START, START + 20)
+        .unwrap()
+        .into_iter()
+        .map(|event| (event.timestamp, event.closed, event.reason))
+        .collect();
+
+    assert_eq!(events, vec![
+        (START, true, String::from("Rain")),
+        (START + 20, false, String::from("ClearAir"))
+    ]);
+}
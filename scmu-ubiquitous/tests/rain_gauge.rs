@@ -0,0 +1,98 @@
+use std::{sync::Arc, time::{Duration, Instant, SystemTime}};
+
+use scmu_ubiquitous::{
+    clock::{SharedClock, VirtualClock},
+    gpio::{pulse::{Debounce, PulseSource, PulseSourceSimulator, PulseWindow}, rain_gauge::TippingBucketRainSensor}
+};
+
+const MM_PER_TIP: f32 = 0.2794;
+
+// pulses given by the test instead of an interrupt
+struct ManualPulses(Vec<Instant>);
+
+impl PulseSource for ManualPulses {
+    fn take_pulses(&mut self) -> Vec<Instant> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn debounce_ignores_bounces() {
+    let start = Instant::now();
+    let mut debounce = Debounce::new(Duration::from_millis(50));
+
+    assert!(debounce.accept(start));
+    assert!(!debounce.accept(start + Duration::from_millis(5)));
+    assert!(!debounce.accept(start + Duration::from_millis(30)));
+    assert!(debounce.accept(start + Duration::from_millis(50)));
+    assert!(!debounce.accept(start + Duration::from_millis(60)));
+    assert!(debounce.accept(start + Duration::from_millis(200)));
+}
+
+#[test]
+fn window_drops_old_pulses() {
+    let start = Instant::now();
+    let mut window = PulseWindow::new(secs(60));
+    let mut source = ManualPulses(vec![start, start + secs(10), start + secs(11), start + secs(12), start + secs(50)]);
+
+    window.update(&mut source, start + secs(50));
+    assert_eq!(window.count_since(start + secs(50), secs(60)), 5);
+    assert_eq!(window.count_since(start + secs(50), secs(38)), 2);
+    assert_eq!(window.max_count_in(secs(3)), 3);
+
+    // the first pulses are now older than the window
+    window.update(&mut source, start + secs(72));
+    assert_eq!(window.count_since(start + secs(72), secs(60)), 2);
+    assert_eq!(window.max_count_in(secs(3)), 1);
+}
+
+#[test]
+fn gauge_measures_accumulation_and_rate() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let shared: SharedClock = clock.clone();
+
+    // one tip every 6 minutes
+    let mut gauge = TippingBucketRainSensor::with_source(PulseSourceSimulator::with_clock(1.0 / 360.0, shared.clone()))
+        .with_clock(shared);
+
+    let rain = gauge.measure();
+    assert!(!rain.is_raining);
+
+    for _ in 0..60 {
+        clock.advance(secs(60));
+        gauge.measure();
+    }
+
+    let rain = gauge.measure();
+    let intensity = rain.intensity.unwrap();
+    assert!(rain.is_raining);
+    assert!((intensity.accumulated_mm - 10.0 * MM_PER_TIP).abs() <= MM_PER_TIP + 0.001);
+
+    // 1 or 2 tips in the last 10 minutes
+    assert!(intensity.rate_mm_h >= 6.0 * MM_PER_TIP - 0.001 && intensity.rate_mm_h <= 12.0 * MM_PER_TIP + 0.001);
+}
+
+#[test]
+fn gauge_dries_once_the_tips_leave_the_window() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let shared: SharedClock = clock.clone();
+
+    let start = shared.now();
+    let mut gauge = TippingBucketRainSensor::with_source(ManualPulses(vec![start, start + secs(1)]))
+        .with_clock(shared);
+
+    clock.advance(secs(2));
+    assert_eq!(gauge.measure().intensity.unwrap().accumulated_mm, 2.0 * MM_PER_TIP);
+
+    clock.advance(secs(15 * 60));
+    let rain = gauge.measure();
+    assert!(!rain.is_raining);
+    assert_eq!(rain.intensity.unwrap().accumulated_mm, 2.0 * MM_PER_TIP);
+
+    clock.advance(secs(60 * 60));
+    assert_eq!(gauge.measure().intensity.unwrap().accumulated_mm, 0.0);
+}
@@ -0,0 +1,86 @@
+use std::{env, fs, process, sync::Arc, time::{Duration, SystemTime}};
+
+use scmu_ubiquitous::{
+    clock::{SharedClock, VirtualClock},
+    config::{RecordingConfig, ReplayConfig},
+    gpio::{
+        anemometer::{ReplayWindSensor, Wind, WindSensor},
+        co2::{Co2, Co2Sensor, ReplayCo2Sensor},
+        environment::{Environment, EnvironmentSensor, ReplayEnvironmentSensor},
+        particle_matter::ParticleMatter,
+        recording::{Event, Recorder, Replay}
+    }
+};
+
+fn pm_25(event: Option<Event>) -> Option<u16> {
+    match event {
+        Some(Event::ParticleMatter(pm)) => pm.map(|pm| pm.pm_25_level),
+        _ => None
+    }
+}
+
+#[test]
+fn appends_and_skips_readings_already_due() {
+    let path = env::temp_dir().join(format!("scmu-recording-{}.jsonl", process::id()));
+    let path = path.to_str().unwrap().to_string();
+    fs::remove_file(&path).unwrap_or_default();
+
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let shared: SharedClock = clock.clone();
+    let config = RecordingConfig { path: Some(path.clone()) };
+
+    // a restart keeps the readings recorded before it
+    for levels in [[1, 2], [3, 4]] {
+        let recorder = Recorder::new(&config, shared.clone());
+        for pm_25_level in levels {
+            recorder.record("pm_0", Event::ParticleMatter(Some(ParticleMatter { pm_25_level, pm_10_level: 0 })));
+            clock.advance(Duration::from_secs(10));
+        }
+    }
+
+    let replay_clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let replay = Replay::load(&ReplayConfig { path: path.clone(), speed: 1.0 }, replay_clock.clone());
+    let mut cursor = replay.cursor("pm_0");
+
+    assert_eq!(pm_25(cursor.next_event()), Some(1));
+    // the reader fell behind, the reading at 10s is skipped
+    replay_clock.advance(Duration::from_secs(25));
+    assert_eq!(pm_25(cursor.next_event()), Some(3));
+    // the next reading is waited for
+    assert_eq!(pm_25(cursor.next_event()), Some(4));
+    assert_eq!(replay_clock.elapsed(), Duration::from_secs(30));
+    // the last reading is repeated once the recording ends
+    assert_eq!(pm_25(cursor.next_event()), Some(4));
+
+    fs::remove_file(&path).unwrap_or_default();
+}
+
+#[test]
+fn replays_the_wind_environment_and_co2_sensors() {
+    let path = env::temp_dir().join(format!("scmu-replay-{}.jsonl", process::id()));
+    let path = path.to_str().unwrap().to_string();
+    fs::remove_file(&path).unwrap_or_default();
+
+    let clock: SharedClock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let recorder = Recorder::new(&RecordingConfig { path: Some(path.clone()) }, clock.clone());
+    recorder.record("wind", Event::Wind(Some(Wind { speed: 4.5, gust: 9.0 })));
+    recorder.record("outdoor_environment", Event::Environment(Some(Environment { temperature: 12.5, humidity: 80.0, pressure: Some(1002.0) })));
+    recorder.record("indoor_environment", Event::Environment(Some(Environment { temperature: 21.0, humidity: 45.0, pressure: None })));
+    recorder.record("co2", Event::Co2(Some(Co2 { concentration: 1200 })));
+
+    let replay = Replay::load(&ReplayConfig { path: path.clone(), speed: 1.0 }, Arc::new(VirtualClock::new(SystemTime::now())));
+
+    let wind = ReplayWindSensor::with_replay(&replay, "wind").read_value().unwrap();
+    assert_eq!((wind.speed, wind.gust), (4.5, 9.0));
+
+    let outdoor = ReplayEnvironmentSensor::with_replay(&replay, "outdoor_environment").read_value().unwrap();
+    assert_eq!((outdoor.temperature, outdoor.pressure), (12.5, Some(1002.0)));
+
+    let indoor = ReplayEnvironmentSensor::with_replay(&replay, "indoor_environment").read_value().unwrap();
+    assert_eq!((indoor.temperature, indoor.pressure), (21.0, None));
+
+    let co2 = ReplayCo2Sensor::with_replay(&replay, "co2").read_value().unwrap();
+    assert_eq!(co2.concentration, 1200);
+
+    fs::remove_file(&path).unwrap_or_default();
+}
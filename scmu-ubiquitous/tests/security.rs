@@ -0,0 +1,208 @@
+use std::{sync::Arc, time::{Duration, SystemTime}};
+
+use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
+use bluer::{gatt::local::ReqError, Address};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use scmu_ubiquitous::{bt::security::ProvisioningSecurity, clock::{SharedClock, VirtualClock}};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+const PROOF_OF_POSSESSION: &str = "label-1234";
+const PHONE: Address = Address([1, 2, 3, 4, 5, 6]);
+const OTHER_PHONE: Address = Address([6, 5, 4, 3, 2, 1]);
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn seal(cipher: &Aes256Gcm, data: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+    [nonce.as_slice(), &cipher.encrypt(Nonce::from_slice(&nonce), data).unwrap()].concat()
+}
+
+fn open(cipher: &Aes256Gcm, data: &[u8]) -> Option<Vec<u8>> {
+    let (nonce, ciphertext) = data.split_at(12);
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+}
+
+// the side of the app, which derives the session key with the proof of possession it was given
+struct App {
+    key: [u8; 32],
+    device_key: Vec<u8>,
+    cipher: Aes256Gcm
+}
+
+impl App {
+    fn start(security: &ProvisioningSecurity, device: Address, proof_of_possession: &str) -> Self {
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let key = PublicKey::from(&secret).to_bytes();
+        security.start(device, &to_hex(&key)).unwrap();
+
+        let info = security.info(device).unwrap();
+        let device_key: [u8; 32] = from_hex(&info.device_key).try_into().unwrap();
+        let shared = secret.diffie_hellman(&PublicKey::from(device_key));
+
+        let salt = Sha256::digest(proof_of_possession.as_bytes());
+        let mut session_key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(&salt[..]), shared.as_bytes())
+            .expand(b"scmu-provisioning", &mut session_key)
+            .unwrap();
+
+        Self {
+            key,
+            device_key: device_key.to_vec(),
+            cipher: Aes256Gcm::new_from_slice(&session_key).unwrap()
+        }
+    }
+
+    fn proof(&self) -> String {
+        to_hex(&seal(&self.cipher, &self.device_key))
+    }
+}
+
+fn security(clock: SharedClock) -> ProvisioningSecurity {
+    ProvisioningSecurity::new(PROOF_OF_POSSESSION, clock)
+}
+
+fn verified(security: &ProvisioningSecurity, device: Address) -> App {
+    let app = App::start(security, device, PROOF_OF_POSSESSION);
+    security.verify(device, &app.proof()).unwrap();
+    app
+}
+
+// a session with the wrong proof of possession, ended by its proof
+fn fail(security: &ProvisioningSecurity) {
+    let app = App::start(security, PHONE, "wrong-guess");
+    assert!(security.verify(PHONE, &app.proof()).is_err());
+}
+
+fn is_locked(security: &ProvisioningSecurity) -> bool {
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    security.start(PHONE, &to_hex(PublicKey::from(&secret).as_bytes())).is_err()
+}
+
+#[test]
+fn proves_the_device_only_after_the_app() {
+    let security = security(Arc::new(VirtualClock::new(SystemTime::now())));
+    let app = App::start(&security, PHONE, PROOF_OF_POSSESSION);
+
+    let info = security.info(PHONE).unwrap();
+    assert!(info.device_proof.is_none());
+    assert!(!info.verified);
+    assert!(matches!(security.encrypt(PHONE, b"networks"), Err(ReqError::NotAuthorized)));
+
+    security.verify(PHONE, &app.proof()).unwrap();
+
+    let info = security.info(PHONE).unwrap();
+    assert!(info.verified);
+    let device_proof = from_hex(&info.device_proof.unwrap());
+    assert_eq!(open(&app.cipher, &device_proof).unwrap(), app.key);
+}
+
+#[test]
+fn encrypts_the_values_of_the_verified_session() {
+    let security = security(Arc::new(VirtualClock::new(SystemTime::now())));
+    let app = verified(&security, PHONE);
+
+    let encrypted = security.encrypt(PHONE, b"networks").unwrap();
+    assert_eq!(open(&app.cipher, &encrypted).unwrap(), b"networks");
+
+    let written = seal(&app.cipher, b"settings");
+    assert_eq!(security.decrypt(PHONE, &written).unwrap(), b"settings");
+
+    let mut tampered = written.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(matches!(security.decrypt(PHONE, &tampered), Err(ReqError::Failed)));
+    assert!(matches!(security.decrypt(PHONE, &written[..8]), Err(ReqError::Failed)));
+
+    assert!(matches!(security.decrypt(OTHER_PHONE, &written), Err(ReqError::NotAuthorized)));
+}
+
+#[test]
+fn rejects_invalid_keys_and_proofs() {
+    let security = security(Arc::new(VirtualClock::new(SystemTime::now())));
+
+    assert!(security.start(PHONE, "not hex").is_err());
+    assert!(security.start(PHONE, "abc").is_err());
+    assert!(security.start(PHONE, &"ab".repeat(31)).is_err());
+    // a key of a low order
+    assert!(security.start(PHONE, &"00".repeat(32)).is_err());
+
+    let app = App::start(&security, PHONE, PROOF_OF_POSSESSION);
+    assert!(security.verify(PHONE, "zz").is_err());
+
+    // the wrong proof ended the session
+    assert!(security.info(PHONE).is_err());
+    assert!(security.verify(PHONE, &app.proof()).is_err());
+
+    let empty = ProvisioningSecurity::new("", Arc::new(VirtualClock::new(SystemTime::now())));
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    assert!(empty.start(PHONE, &to_hex(PublicKey::from(&secret).as_bytes())).is_err());
+}
+
+#[test]
+fn locks_out_with_a_doubling_delay_after_repeated_wrong_proofs() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let security = security(clock.clone());
+
+    fail(&security);
+    fail(&security);
+    assert!(!is_locked(&security));
+
+    // 30 seconds after the third, doubled on every wrong proof up to an hour
+    for lockout in [30, 60, 120, 240, 480, 960, 1920, 3600, 3600] {
+        fail(&security);
+        assert!(is_locked(&security));
+
+        clock.advance(secs(lockout - 1));
+        assert!(is_locked(&security));
+
+        clock.advance(secs(1));
+        assert!(!is_locked(&security));
+    }
+
+    // the right proof starts the count over
+    verified(&security, PHONE);
+    fail(&security);
+    fail(&security);
+    assert!(!is_locked(&security));
+}
+
+#[test]
+fn keeps_the_verified_session_from_other_devices() {
+    let clock = Arc::new(VirtualClock::new(SystemTime::now()));
+    let security = security(clock.clone());
+    let app = verified(&security, PHONE);
+
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    let other_key = to_hex(PublicKey::from(&secret).as_bytes());
+    assert!(security.start(OTHER_PHONE, &other_key).is_err());
+
+    // every request keeps the session alive
+    clock.advance(secs(90));
+    let encrypted = security.encrypt(PHONE, b"status").unwrap();
+    assert_eq!(open(&app.cipher, &encrypted).unwrap(), b"status");
+
+    clock.advance(secs(90));
+    assert!(security.start(OTHER_PHONE, &other_key).is_err());
+
+    // until it is left idle
+    clock.advance(secs(30));
+    security.start(OTHER_PHONE, &other_key).unwrap();
+    assert!(matches!(security.encrypt(PHONE, b"status"), Err(ReqError::NotAuthorized)));
+}
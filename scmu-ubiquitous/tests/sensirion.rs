@@ -0,0 +1,7 @@
+use scmu_ubiquitous::gpio::sensirion::crc8;
+
+#[test]
+fn computes_the_checksum_of_the_datasheet_example() {
+    assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+    assert_eq!(crc8(&[]), 0xFF);
+}
@@ -0,0 +1,42 @@
+use std::{sync::Arc, time::{Duration, SystemTime}};
+
+use scmu_ubiquitous::{
+    clock::{SharedClock, VirtualClock},
+    config::SimulationConfig,
+    gpio::{
+        particle_matter::{ParticleMatterSensor, ParticleMatterSensorSimulator},
+        rain::{RainSensor, RainSensorSimulator},
+        simulation::Simulation,
+        Location
+    }
+};
+
+// a day of readings, a minute apart
+fn run(seed: u64, origin: SystemTime) -> Vec<(u16, u16, bool)> {
+    let clock = Arc::new(VirtualClock::new(origin));
+    let shared: SharedClock = clock.clone();
+    let simulation = Simulation::new(&SimulationConfig { seed: Some(seed), scenario: None }, shared);
+
+    let mut pm_sensor = ParticleMatterSensorSimulator::with_simulation(&simulation, Location::Outdoor, 0);
+    let mut rain_sensor = RainSensorSimulator::with_simulation(&simulation);
+
+    (0..24 * 60)
+        .map(|_| {
+            clock.advance(Duration::from_secs(59));
+            let pm = pm_sensor.read_value().unwrap();
+            let rain = rain_sensor.read_value().unwrap();
+            (pm.pm_25_level, pm.pm_10_level, rain.is_raining)
+        })
+        .collect()
+}
+
+#[test]
+fn same_seed_gives_the_same_readings() {
+    let first = run(42, SystemTime::now());
+    // the simulated time does not depend on when the run started
+    let second = run(42, SystemTime::now() + Duration::from_secs(5 * 60 * 60));
+
+    assert_eq!(first, second);
+    assert!(first.iter().any(|(_, _, is_raining)| *is_raining));
+    assert_ne!(first, run(43, SystemTime::now()));
+}
@@ -0,0 +1,337 @@
+use std::{
+    env, fs,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::{self, Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant}
+};
+
+use serde_json::{json, Value};
+use tungstenite::{accept_hdr, handshake::server::{ErrorResponse, Request, Response}, Message, WebSocket};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const PUBLIC_KEY: &str = "test_pk";
+
+// device daemon running with simulated sensors, killed when the test ends
+struct Device {
+    process: Child,
+    dir: PathBuf
+}
+
+impl Device {
+    fn start(name: &str, server: &MockServer) -> Self {
+        Self::start_with(name, server, json!({}))
+    }
+
+    // `extra` replaces the top level entries of the default test configuration
+    fn start_with(name: &str, server: &MockServer, extra: Value) -> Self {
+        let dir = env::temp_dir().join(format!("scmu-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut config = json!({
+            // the settings changed by the server must not leak into the working directory
+            "device": {
+                "settings_path": dir.join("device.json")
+            },
+            "server": {
+                "url": format!("ws://127.0.0.1:{}/ubiquitous", server.port()),
+                "public_key": PUBLIC_KEY
+            },
+            "sampling_interval_ms": 100,
+            "history": {
+                "path": dir.join("history.db")
+            },
+            "simulation": {
+                "seed": 1
+            },
+            "api": {
+                "listen": null
+            },
+            // there is no NetworkManager to talk to
+            "wifi": {
+                "enabled": false
+            }
+        });
+
+        for (key, value) in extra.as_object().unwrap() {
+            config[key] = value.clone();
+        }
+
+        let config_path = dir.join("config.json");
+        fs::write(&config_path, config.to_string()).unwrap();
+
+        let process = Command::new(env!("CARGO_BIN_EXE_scmu-ubiquitous"))
+            .arg(&config_path)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        Self { process, dir }
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        self.process.kill().unwrap_or_default();
+        self.process.wait().unwrap();
+        fs::remove_dir_all(&self.dir).unwrap_or_default();
+    }
+}
+
+// mimics the /ubiquitous endpoint of the scmu server
+struct MockServer {
+    listener: TcpListener
+}
+
+impl MockServer {
+    fn new() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        Self { listener }
+    }
+
+    fn port(&self) -> u16 {
+        self.listener.local_addr().unwrap().port()
+    }
+
+    // waits for the device to connect and checks the configuration packet
+    fn accept(&self) -> WebSocket<TcpStream> {
+        let deadline = Instant::now() + TIMEOUT;
+        let stream = loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => break stream,
+                Err(err) if err.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(50))
+                },
+                Err(err) => panic!("The device did not connect: {}", err)
+            }
+        };
+
+        stream.set_nonblocking(false).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        let mut ws = accept_hdr(stream, check_path)
+            .unwrap();
+
+        let config = next_json(&mut ws);
+        assert_eq!(config, json!({ "public_key": PUBLIC_KEY }));
+        ws
+    }
+}
+
+// the error type is imposed by tungstenite
+#[allow(clippy::result_large_err)]
+fn check_path(req: &Request, res: Response) -> Result<Response, ErrorResponse> {
+    assert_eq!(req.uri().path(), "/ubiquitous");
+    Ok(res)
+}
+
+fn next_json(ws: &mut WebSocket<TcpStream>) -> Value {
+    loop {
+        if let Message::Text(text) = ws.read_message().unwrap() {
+            return serde_json::from_str(&text).unwrap()
+        }
+    }
+}
+
+// reads status packets until one satisfies the predicate
+fn wait_for_status(ws: &mut WebSocket<TcpStream>, predicate: impl Fn(&Value) -> bool) -> Value {
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        let status = next_json(ws);
+        if predicate(&status) {
+            return status
+        }
+    }
+
+    panic!("No matching status received")
+}
+
+#[test]
+fn sends_configuration_and_periodic_status() {
+    let server = MockServer::new();
+    let _device = Device::start("status", &server);
+    let mut ws = server.accept();
+
+    for _ in 0..3 {
+        let status = next_json(&mut ws);
+        assert!(status["is_closed"].is_boolean());
+        assert!(status["is_raining"].is_boolean());
+        assert!(status["pm_25_level"].is_u64());
+        assert!(status["pm_10_level"].is_u64());
+        assert_eq!(status["thresholds"]["pm_25_threshold"], 100);
+        assert_eq!(status["health"]["rain"]["state"], "ok");
+    }
+}
+
+#[test]
+fn applies_thresholds() {
+    let server = MockServer::new();
+    let _device = Device::start("thresholds", &server);
+    let mut ws = server.accept();
+
+    let thresholds = json!({
+        "pm_25_threshold": 42,
+        "pm_10_threshold": 43,
+        "wind_threshold": 5.5,
+        "co2_threshold": 800,
+        "aqi_threshold": { "scale": "caqi", "category": "medium" }
+    });
+
+    ws.write_message(Message::Text(thresholds.to_string())).unwrap();
+
+    let status = wait_for_status(&mut ws, |status| status["thresholds"]["pm_25_threshold"] == 42);
+    assert_eq!(status["thresholds"]["pm_10_threshold"], 43);
+    assert_eq!(status["thresholds"]["wind_threshold"], 5.5);
+    assert_eq!(status["thresholds"]["co2_threshold"], 800);
+    assert_eq!(status["thresholds"]["aqi_threshold"], json!({ "scale": "caqi", "category": "medium" }));
+}
+
+#[test]
+fn answers_pings() {
+    let server = MockServer::new();
+    let _device = Device::start("ping", &server);
+    let mut ws = server.accept();
+
+    ws.write_message(Message::Ping(b"scmu".to_vec())).unwrap();
+
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        if let Message::Pong(payload) = ws.read_message().unwrap() {
+            assert_eq!(payload, b"scmu");
+            return
+        }
+    }
+
+    panic!("No pong received")
+}
+
+#[test]
+fn answers_history_requests() {
+    let server = MockServer::new();
+    let _device = Device::start("history", &server);
+    let mut ws = server.accept();
+
+    // waits for something to be stored
+    next_json(&mut ws);
+
+    let request = json!({ "request_id": 7, "from": 0, "to": i64::MAX, "resolution": "minute", "metric": "pm_25_level" });
+    ws.write_message(Message::Text(request.to_string())).unwrap();
+
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        if let Message::Binary(bytes) = ws.read_message().unwrap() {
+            let chunk: Value = rmp_serde::from_slice(&bytes).unwrap();
+            assert_eq!(chunk["request_id"], 7);
+            assert_eq!(chunk["page"], 0);
+            assert!(chunk["error"].is_null());
+
+            let points = chunk["points"].as_array().unwrap();
+            assert!(!points.is_empty());
+            assert!(points.iter().all(|point| point["metric"] == "pm_25_level"));
+            return
+        }
+    }
+
+    panic!("No history received")
+}
+
+#[test]
+fn reconnects_after_connection_loss() {
+    let server = MockServer::new();
+    let _device = Device::start("reconnect", &server);
+
+    let mut ws = server.accept();
+    next_json(&mut ws);
+    drop(ws);
+
+    // the configuration is sent again on the new connection
+    let mut ws = server.accept();
+    let status = next_json(&mut ws);
+    assert!(status["is_closed"].is_boolean());
+
+    // followed by the readings that may not have reached the old one
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        if let Message::Binary(bytes) = ws.read_message().unwrap() {
+            let chunk: Value = rmp_serde::from_slice(&bytes).unwrap();
+            assert_eq!(chunk["request_id"], 0);
+            assert!(!chunk["points"].as_array().unwrap().is_empty());
+            return
+        }
+    }
+
+    panic!("No backfill received")
+}
+
+#[test]
+fn serves_history_over_http() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let server = MockServer::new();
+    let _device = Device::start_with("api", &server, json!({ "api": { "listen": format!("127.0.0.1:{}", port) } }));
+    let mut ws = server.accept();
+
+    // waits for something to be stored
+    next_json(&mut ws);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    write!(stream, "GET /history?from=0&resolution=raw&metric=pm_25_level HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let body: Value = serde_json::from_str(body).unwrap();
+    let points = body["points"].as_array().unwrap();
+    assert!(!points.is_empty());
+    assert!(points.iter().all(|point| point["metric"] == "pm_25_level"));
+}
+
+// accepts a single CONNECT request and relays the tunnel to the server
+fn start_proxy(server_port: u16) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    thread::spawn(move || {
+        let (mut client, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut byte = [0; 1];
+        while !request.ends_with(b"\r\n\r\n") {
+            client.read_exact(&mut byte).unwrap();
+            request.push(byte[0]);
+        }
+
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with(&format!("CONNECT 127.0.0.1:{} HTTP/1.1\r\n", server_port)));
+
+        let mut server = TcpStream::connect(("127.0.0.1", server_port)).unwrap();
+        write!(client, "HTTP/1.1 200 Connection established\r\n\r\n").unwrap();
+
+        let (mut client_reader, mut server_writer) = (client.try_clone().unwrap(), server.try_clone().unwrap());
+        thread::spawn(move || io::copy(&mut client_reader, &mut server_writer));
+        io::copy(&mut server, &mut client).unwrap_or_default();
+    });
+
+    port
+}
+
+#[test]
+fn connects_through_a_proxy() {
+    let server = MockServer::new();
+    let proxy_port = start_proxy(server.port());
+    let _device = Device::start_with("proxy", &server, json!({
+        "server": {
+            "url": format!("ws://127.0.0.1:{}/ubiquitous", server.port()),
+            "public_key": PUBLIC_KEY,
+            "proxy": format!("http://127.0.0.1:{}", proxy_port)
+        }
+    }));
+
+    let mut ws = server.accept();
+    let status = next_json(&mut ws);
+    assert!(status["is_closed"].is_boolean());
+}